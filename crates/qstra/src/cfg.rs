@@ -7,16 +7,32 @@ use std::path::PathBuf;
 
 
 pub const CONF_FILE: &str = "qstra.conf";
+pub const DEFAULT_MAX_FRAME_SZ: usize = 1 << 20;
+pub const DEFAULT_CHECKPOINT_WAL_SZ: u64 = 1 << 26;
+pub const DEFAULT_MAX_OBJECT_SZ: usize = 1 << 28;
 
 
 pub struct Config {
         pub listen_local: bool,
         pub listen_network: bool,
+        pub listen_resp: bool,
+        pub listen_http: bool,
         pub inet_addr: String,
         pub sock_addr: String,
+        pub resp_addr: String,
+        pub http_addr: String,
         pub db_file: PathBuf,
         pub wal_file: PathBuf,
         pub wal_mode: u32,
+        pub max_frame_sz: usize,
+        // The most bytes an object sized by the command creating it may
+        // take, where zero means no limit
+        pub max_object_sz: usize,
+        pub read_only: bool,
+        // The size in bytes and the number of records past which the
+        // write-ahead log is checkpointed, where zero means no limit
+        pub checkpoint_wal_sz: u64,
+        pub checkpoint_wal_records: u64,
 }
 
 
@@ -25,11 +41,20 @@ impl Default for Config {
                 Self {
                         listen_local: true,
                         listen_network: true,
+                        listen_resp: false,
+                        listen_http: false,
                         inet_addr: "127.0.0.1:1234".into(),
                         sock_addr: "qstra.sock".into(),
+                        resp_addr: "127.0.0.1:6379".into(),
+                        http_addr: "127.0.0.1:8080".into(),
                         db_file: PathBuf::from("qstra.db"),
                         wal_file: PathBuf::from("qstra.wal"),
                         wal_mode: 1,
+                        max_frame_sz: DEFAULT_MAX_FRAME_SZ,
+                        max_object_sz: DEFAULT_MAX_OBJECT_SZ,
+                        read_only: false,
+                        checkpoint_wal_sz: DEFAULT_CHECKPOINT_WAL_SZ,
+                        checkpoint_wal_records: 0,
                 }
         }
 }
@@ -52,15 +77,42 @@ impl Config {
                                 Some(("LISTEN_NETWORK", val)) => {
                                         cfg.listen_network = val.to_lowercase().parse().unwrap_or(false);
                                 }
+                                Some(("LISTEN_RESP", val)) => {
+                                        cfg.listen_resp = val.to_lowercase().parse().unwrap_or(false);
+                                }
+                                Some(("LISTEN_HTTP", val)) => {
+                                        cfg.listen_http = val.to_lowercase().parse().unwrap_or(false);
+                                }
                                 Some(("INET_ADDRESS", val)) => {
                                         cfg.inet_addr = val.into();
                                 }
                                 Some(("UNIX_SOCKET", val)) => {
                                         cfg.sock_addr = val.into();
                                 }
+                                Some(("RESP_ADDRESS", val)) => {
+                                        cfg.resp_addr = val.into();
+                                }
+                                Some(("HTTP_ADDRESS", val)) => {
+                                        cfg.http_addr = val.into();
+                                }
                                 Some(("WAL_MODE", val)) => {
                                         cfg.wal_mode = val.parse::<u32>().unwrap_or(1);
                                 }
+                                Some(("MAX_FRAME_SIZE", val)) => {
+                                        cfg.max_frame_sz = val.parse::<usize>().unwrap_or(DEFAULT_MAX_FRAME_SZ);
+                                }
+                                Some(("MAX_OBJECT_SIZE", val)) => {
+                                        cfg.max_object_sz = val.parse::<usize>().unwrap_or(DEFAULT_MAX_OBJECT_SZ);
+                                }
+                                Some(("READ_ONLY", val)) => {
+                                        cfg.read_only = val.to_lowercase().parse().unwrap_or(false);
+                                }
+                                Some(("CHECKPOINT_WAL_SIZE", val)) => {
+                                        cfg.checkpoint_wal_sz = val.parse::<u64>().unwrap_or(DEFAULT_CHECKPOINT_WAL_SZ);
+                                }
+                                Some(("CHECKPOINT_WAL_RECORDS", val)) => {
+                                        cfg.checkpoint_wal_records = val.parse::<u64>().unwrap_or(0);
+                                }
                                 _ => {}
                         }
                 }
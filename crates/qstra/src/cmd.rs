@@ -2,13 +2,19 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::io;
 use std::rc::Rc;
 
-use tokio::io::AsyncWriteExt;
-
-use qstra_prob::bf::BloomFilterStructure;
+use qstra_prob::bf::{BloomFilterParams, BloomFilterStructure, MembershipFilter};
+use qstra_prob::cbf::{CounterWidth, CountingBloomFilterStructure};
+use qstra_prob::cf::{CuckooFilterParams, CuckooFilterStructure};
+use qstra_prob::cms::{CountMinSketchParams, CountMinSketchStructure};
+use qstra_prob::hll::{HyperLogLog, HyperLogLogStructure};
+use qstra_prob::topk::{TopK, TopKStructure};
+use qstra_prob::sbf::{ScalableBloomFilterParams, ScalableBloomFilterStructure};
+use qstra_proto::frame::{self as proto, ServerError};
 
 use crate::ctl;
 use crate::db;
@@ -16,33 +22,97 @@ use crate::db;
 
 const END_SENTINEL: u8 = 255;
 
-const TOKEN_FALSE: u8 = 0;
-const TOKEN_TRUE: u8 = 1;
-
 const U8_OFFSET: usize = std::mem::size_of::<u8>();
-
-
+const U64_OFFSET: usize = std::mem::size_of::<u64>();
+
+
+// In the second version of the protocol, a response mirrors the request
+// frame as
+//
+//   rc (u8) | 0xFF | req_id (u16) | len (u32) | val (u8...)
+//
+// where the request id is NO_REQUEST_ID if the request carried none, and the
+// value of an error response is its detail message (UTF-8).
+//
+// In the first version, a response is laid out as
+//
+//   [req_id (u16)] | rc (u8) | val (u8...) | END_SENTINEL
+//
+// where the request id is echoed only if the request carried one. An error
+// response carries no value, and its detail is left out.
 pub struct CmdResponseTLV {
         rc: CmdResponseCode,
         val: Vec<u8>,
+        detail: String,
+        req_id: Option<u16>,
 }
 
 
 impl CmdResponseTLV {
         #[must_use]
         pub fn new() -> Self {
-                Self { rc: CmdResponseCode::Success, val: Vec::<u8>::new() }
+                Self { rc: CmdResponseCode::Success, val: Vec::<u8>::new(), detail: String::new(), req_id: None }
+        }
+
+        #[must_use]
+        pub fn with_error(err: CmdError) -> Self {
+                Self { rc: CmdResponseCode::Error(err), val: Vec::<u8>::new(), detail: String::new(), req_id: None }
+        }
+
+        /// Attach a message saying what went wrong to an error response.
+        #[must_use]
+        pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+                self.detail = detail.into();
+                self
+        }
+
+        /// Echo the id of the request this response answers.
+        #[must_use]
+        pub fn with_request_id(mut self, req_id: Option<u16>) -> Self {
+                self.req_id = req_id;
+                self
+        }
+
+        pub fn set_request_id(&mut self, req_id: Option<u16>) {
+                self.req_id = req_id;
         }
 
         pub fn status(&self) -> CmdResponseCode {
                 self.rc
         }
 
+        #[must_use]
+        pub fn detail(&self) -> &str {
+                &self.detail
+        }
+
+        #[must_use]
+        pub fn value(&self) -> &[u8] {
+                &self.val
+        }
+
         #[inline(always)]
         fn init_error_response(&mut self, err: CmdError) {
                 self.rc = CmdResponseCode::Error(err);
         }
 
+        fn init_error_detail(&mut self, err: CmdError, detail: impl Into<String>) {
+                self.init_error_response(err);
+                self.detail = detail.into();
+        }
+
+        fn database_not_found(&mut self, db_id: u8) {
+                self.init_error_detail(CmdError::DatabaseNotFound, format!("no database {db_id}"));
+        }
+
+        fn object_exists(&mut self, kind: db::ObjectKind, db_id: u8, obj_id: u8) {
+                self.init_error_detail(CmdError::ObjectExists, format!("{} {obj_id} already exists in database {db_id}", kind.name()));
+        }
+
+        fn object_not_found(&mut self, kind: db::ObjectKind, db_id: u8, obj_id: u8) {
+                self.init_error_detail(CmdError::ObjectNotFound, format!("no {} {obj_id} in database {db_id}", kind.name()));
+        }
+
         #[inline(always)]
         #[expect(dead_code)]
         fn init_success_response(&mut self) {
@@ -54,17 +124,43 @@ impl CmdResponseTLV {
                 self.val.push(x);
         }
 
-        pub async fn respond<S>(&self, mut stream: S) -> io::Result<()>
-        where S: AsyncWriteExt + Unpin
-        {
-                let mut ret = Vec::<u8>::with_capacity(self.val.len() + 1);
-                ret.push(self.rc.as_u8());
-                ret.extend(&self.val);
-                ret.push(END_SENTINEL);
-                if stream.write_all(&ret).await.is_err() {
-                        eprintln!("Error responding to client stream.");
+        #[inline(always)]
+        fn extend(&mut self, bytes: &[u8]) {
+                self.val.extend_from_slice(bytes);
+        }
+
+        /// Append the response as it goes on the wire in the given protocol
+        /// version to a buffer, so that the responses to pipelined requests
+        /// can be written at once.
+        pub fn encode_into(&self, buf: &mut Vec<u8>, version: u8) {
+                if version >= proto::PROTOCOL_V2 {
+                        let val = match self.rc {
+                                CmdResponseCode::Success => &self.val,
+                                CmdResponseCode::Error(_) => self.detail.as_bytes(),
+                        };
+                        buf.push(self.rc.as_u8());
+                        buf.push(0xFF);
+                        buf.extend(self.req_id.unwrap_or(proto::NO_REQUEST_ID).to_le_bytes());
+                        #[allow(clippy::cast_possible_truncation)]
+                        buf.extend((val.len() as u32).to_le_bytes());
+                        buf.extend(val);
+                        return;
                 }
-                Ok(())
+                if let Some(req_id) = self.req_id {
+                        buf.extend(req_id.to_le_bytes());
+                }
+                buf.push(self.rc.as_u8());
+                if let CmdResponseCode::Success = self.rc {
+                        buf.extend(&self.val);
+                }
+                buf.push(END_SENTINEL);
+        }
+}
+
+
+impl Default for CmdResponseTLV {
+        fn default() -> Self {
+                Self::new()
         }
 }
 
@@ -78,22 +174,67 @@ pub enum CmdResponseCode {
 
 impl CmdResponseCode {
         #[must_use]
-        fn as_u8(self) -> u8 {
+        pub fn as_u8(self) -> u8 {
                 match self {
-                        CmdResponseCode::Success => 0,
-                        CmdResponseCode::Error(CmdError::BloomFilterExists) => 1,
-                        CmdResponseCode::Error(CmdError::RequestBytesMalformed) => 2,
-                        CmdResponseCode::Error(CmdError::ObjectNotFound) => 3,
+                        CmdResponseCode::Success => proto::RC_SUCCESS,
+                        CmdResponseCode::Error(err) => match err {
+                                CmdError::ObjectExists => ServerError::EXISTS,
+                                CmdError::RequestBytesMalformed => ServerError::MALFORMED,
+                                CmdError::NameNotFound => ServerError::NAME_NOT_FOUND,
+                                CmdError::FilterFull => ServerError::FILTER_FULL,
+                                CmdError::FrameTooLarge => ServerError::FRAME_TOO_LARGE,
+                                CmdError::UnknownCommand => ServerError::UNKNOWN_COMMAND,
+                                CmdError::TruncatedFrame => ServerError::TRUNCATED_FRAME,
+                                CmdError::UnsupportedVersion => ServerError::UNSUPPORTED_VERSION,
+                                CmdError::InvalidParameters => ServerError::INVALID_PARAMETERS,
+                                CmdError::DatabaseNotFound => ServerError::DATABASE_NOT_FOUND,
+                                CmdError::ObjectNotFound => ServerError::OBJECT_NOT_FOUND,
+                                CmdError::InternalError => ServerError::INTERNAL_ERROR,
+                                CmdError::ReadOnly => ServerError::READ_ONLY,
+                        },
                 }
         }
 }
 
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CmdError {
-        BloomFilterExists,
+        /// An object, or a database, of the given id or name already exists
+        ObjectExists,
         RequestBytesMalformed,
+        /// No database or object goes by the given name
+        NameNotFound,
+        FilterFull,
+        FrameTooLarge,
+        UnknownCommand,
+        TruncatedFrame,
+        UnsupportedVersion,
+        /// The parameters are well-formed but out of range
+        InvalidParameters,
+        DatabaseNotFound,
+        /// No object of the given kind goes by the given id
         ObjectNotFound,
+        /// The command failed on the server side, e.g. on file I/O
+        InternalError,
+        /// The server doesn't accept commands that change its state
+        ReadOnly,
+}
+
+
+impl CmdError {
+        /// Classify a failure to parse or decode a frame. The decoders flag
+        /// unknown commands as unsupported, short values as unexpected EOF
+        /// and out-of-range parameters as invalid input; anything else is
+        /// malformed.
+        #[must_use]
+        pub fn from_decode_error(e: &io::Error) -> Self {
+                match e.kind() {
+                        io::ErrorKind::Unsupported => CmdError::UnknownCommand,
+                        io::ErrorKind::UnexpectedEof => CmdError::TruncatedFrame,
+                        io::ErrorKind::InvalidInput => CmdError::InvalidParameters,
+                        _ => CmdError::RequestBytesMalformed,
+                }
+        }
 }
 
 
@@ -108,31 +249,225 @@ impl<'a> LV<'a> {
                         return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl LV: new: too few bytes in buffer"));
                 }
                 let len = buf[0] as usize;
-                let val = &buf[U8_OFFSET..=len];
+                let Some(val) = buf.get(U8_OFFSET..=len) else {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl LV: new: value runs past the buffer"));
+                };
                 Ok(Self { val })
         }
 }
 
 
-pub enum Cmd<'a> {
+// Split a batch into its elements, or return None if an LV in it runs past
+// the end. A batch is checked whole before any of it is applied, so that a
+// malformed one leaves the state as it was.
+fn split_batch(buf: &[u8]) -> Option<Vec<&[u8]>> {
+        let mut elts = Vec::new();
+        let mut idx = 0;
+        while idx < buf.len() {
+                let lv = LV::new(&buf[idx..]).ok()?;
+                idx += lv.val.len()+1;
+                elts.push(lv.val);
+        }
+        Some(elts)
+}
+
+
+pub(crate) enum Cmd<'a> {
         Read(ReadCmd<'a>),
         Write(WriteCmd<'a>),
 }
 
 
-pub enum ReadCmd<'a> {
+impl Cmd<'_> {
+        /// The protocol version a handshake asks to switch the connection to
+        pub(crate) fn handshake_version(&self) -> Option<u8> {
+                match self {
+                        Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::Handshake(op) })) => Some(op.version),
+                        _ => None,
+                }
+        }
+
+        /// Whether the command changes the state or the files backing it,
+        /// which a read-only server turns away. Saving the databases reads
+        /// the state but still writes the database file.
+        pub(crate) fn is_write(&self) -> bool {
+                matches!(self, Cmd::Write(_) | Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::WriteData })))
+        }
+}
+
+
+pub(crate) enum ReadCmd<'a> {
         BloomFilter(ReadCmdBloomFilter<'a>),
-        Ctl(ReadCmdCtl),
+        CountingBloomFilter(ReadCmdCountingBloomFilter<'a>),
+        CuckooFilter(ReadCmdCuckooFilter<'a>),
+        HyperLogLog(ReadCmdHyperLogLog),
+        CountMinSketch(ReadCmdCountMinSketch<'a>),
+        TopK(ReadCmdTopK<'a>),
+        Database(ReadCmdDatabase<'a>),
+        Ctl(ReadCmdCtl<'a>),
+}
+
+
+// The reads of a bloom filter, for running them in-process without a frame
+impl<'a> ReadCmd<'a> {
+        pub(crate) fn bf_has(db_id: u8, bf_id: u8, elt: &'a [u8]) -> Self {
+                let op = ReadOpBloomFilter::Has(ReadOpBloomFilterHas { elt });
+                ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op })
+        }
+
+        pub(crate) fn bf_has_batch(db_id: u8, bf_id: u8, elts: &'a [u8]) -> Self {
+                let op = ReadOpBloomFilter::HasBatch(ReadOpBloomFilterHasBatch { elts });
+                ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op })
+        }
+
+        pub(crate) fn bf_info(db_id: u8, bf_id: u8) -> Self {
+                let op = ReadOpBloomFilter::Info(ReadOpBloomFilterInfo);
+                ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op })
+        }
 }
 
 
-pub(crate) struct ReadCmdCtl {
-        op: ReadOpCtl,
+pub(crate) struct ReadCmdCtl<'a> {
+        op: ReadOpCtl<'a>,
 }
 
 
-enum ReadOpCtl {
+enum ReadOpCtl<'a> {
         WriteData,
+        ListDatabases(ReadOpCtlListDatabases),
+        LookupDatabase(ReadOpCtlLookupDatabase<'a>),
+        Handshake(ReadOpCtlHandshake),
+        Info(ReadOpCtlInfo),
+}
+
+
+// Agree on the protocol version of the connection. The version is echoed
+// back, framed in that version, or refused with an error framed in the
+// version the connection is in.
+struct ReadOpCtlHandshake {
+        version: u8,
+}
+
+
+impl ReadOpCtlHandshake {
+        fn execute(&self, resp: &mut CmdResponseTLV) {
+                if !(proto::PROTOCOL_V1..=proto::PROTOCOL_VERSION).contains(&self.version) {
+                        let detail = format!("protocol version {} is not supported, only {} to {}", self.version, proto::PROTOCOL_V1, proto::PROTOCOL_VERSION);
+                        resp.init_error_detail(CmdError::UnsupportedVersion, detail);
+                        return;
+                }
+                resp.append(self.version);
+        }
+}
+
+
+// The list is id (u8) | name_len (u8) | name for every database in creation
+// order, with a zero name_len for an unnamed database.
+struct ReadOpCtlListDatabases;
+
+
+impl ReadOpCtlListDatabases {
+        #[allow(clippy::cast_possible_truncation)]
+        fn execute(&self, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+                for db in ctl.db_registry.list() {
+                        let name = db.name.as_deref().unwrap_or_default();
+                        resp.append(db.id);
+                        resp.append(name.len() as u8);
+                        resp.extend(name.as_bytes());
+                }
+        }
+}
+
+
+struct ReadOpCtlLookupDatabase<'a> {
+        name: &'a str,
+}
+
+
+impl ReadOpCtlLookupDatabase<'_> {
+        fn execute(&self, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+                match ctl.lookup_database(self.name) {
+                        Some(db_id) => { resp.append(db_id); }
+                        None => { resp.init_error_detail(CmdError::NameNotFound, format!("no database named {}", self.name)); }
+                }
+        }
+}
+
+
+// Describe what the server supports, laid out as
+//
+//   min_version (u8) | max_version (u8) | kind_cnt (u8) | kinds (u8...)
+//   | max_frame_sz (u32) | server_version_len (u8) | server_version (UTF-8)
+//
+// where the kinds are the command families serving each kind of object.
+struct ReadOpCtlInfo;
+
+
+impl ReadOpCtlInfo {
+        #[allow(clippy::cast_possible_truncation)]
+        fn execute(&self, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+                resp.append(proto::PROTOCOL_V1);
+                resp.append(proto::PROTOCOL_VERSION);
+                resp.append(db::ObjectKind::ALL.len() as u8);
+                for kind in db::ObjectKind::ALL {
+                        resp.append(kind.value());
+                }
+                let max_frame_sz = u32::try_from(ctl.config().max_frame_sz).unwrap_or(u32::MAX);
+                resp.extend(&max_frame_sz.to_le_bytes());
+                let server_version = env!("CARGO_PKG_VERSION");
+                resp.append(server_version.len() as u8);
+                resp.extend(server_version.as_bytes());
+        }
+}
+
+
+pub(crate) struct ReadCmdDatabase<'a> {
+        db_id: u8,
+        op: ReadOpDatabase<'a>,
+}
+
+
+enum ReadOpDatabase<'a> {
+        LookupObject(ReadOpDatabaseLookupObject<'a>),
+        ListObjects(ReadOpDatabaseListObjects),
+}
+
+
+// The object is returned as the family of the commands serving it followed
+// by its id.
+struct ReadOpDatabaseLookupObject<'a> {
+        name: &'a str,
+}
+
+
+impl ReadOpDatabaseLookupObject<'_> {
+        fn execute(&self, db: &db::Database, resp: &mut CmdResponseTLV) {
+                match db.lookup(self.name) {
+                        Some((kind, obj_id)) => {
+                                resp.append(kind.value());
+                                resp.append(obj_id);
+                        }
+                        None => { resp.init_error_detail(CmdError::NameNotFound, format!("no object named {} in database {}", self.name, db.id)); }
+                }
+        }
+}
+
+
+// The list is family (u8) | id (u8) | name_len (u8) | name for every named
+// object, ordered by name.
+struct ReadOpDatabaseListObjects;
+
+
+impl ReadOpDatabaseListObjects {
+        #[allow(clippy::cast_possible_truncation)]
+        fn execute(&self, db: &db::Database, resp: &mut CmdResponseTLV) {
+                for (name, (kind, obj_id)) in &db.names {
+                        resp.append(kind.value());
+                        resp.append(*obj_id);
+                        resp.append(name.len() as u8);
+                        resp.extend(name.as_bytes());
+                }
+        }
 }
 
 
@@ -146,6 +481,37 @@ pub(crate) struct ReadCmdBloomFilter<'a> {
 enum ReadOpBloomFilter<'a> {
         Has(ReadOpBloomFilterHas<'a>),
         HasBatch(ReadOpBloomFilterHasBatch<'a>),
+        Info(ReadOpBloomFilterInfo),
+}
+
+
+// Describe a plain or scalable bloom filter as capacity (u64) | size in bytes
+// (u64) | slice_cnt (u64) | insert_cnt (u64) | growth (u64), where a plain
+// filter is a single slice that doesn't grow. The capacity of a plain filter
+// is the item count its size and hash function count are optimal for.
+struct ReadOpBloomFilterInfo;
+
+
+impl ReadOpBloomFilterInfo {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        fn execute(&self, db: &db::Database, bf_id: u8, resp: &mut CmdResponseTLV) {
+                let stats = if let Some(bfs) = db.bf_registry.get(&[bf_id]) {
+                        let bf = &bfs.inner;
+                        let cpty = (bf.bit_cnt as f64 * std::f64::consts::LN_2 / bf.hfn_cnt as f64) as usize;
+                        [cpty, bf.bit_cnt.div_ceil(8), 1, bf.insert_cnt, 0]
+                } else if let Some(sbfs) = db.sbf_registry.get(&[bf_id]) {
+                        let sbf = &sbfs.inner;
+                        let cpty = (0..sbf.slices.len()).map(|i| sbf.slice_cpty(i)).fold(0, usize::saturating_add);
+                        let size = sbf.slices.iter().map(|slice| slice.bit_cnt.div_ceil(8)).sum();
+                        [cpty, size, sbf.slices.len(), sbf.insert_cnt, sbf.params.growth]
+                } else {
+                        resp.object_not_found(db::ObjectKind::BloomFilter, db.id, bf_id);
+                        return;
+                };
+                for stat in stats {
+                        resp.extend(&(stat as u64).to_le_bytes());
+                }
+        }
 }
 
 
@@ -155,10 +521,10 @@ struct ReadOpBloomFilterHas<'a> {
 
 
 impl ReadOpBloomFilterHas<'_> {
-        fn execute(&self, bfs: &BloomFilterStructure, resp: &mut CmdResponseTLV) -> io::Result<()> {
-                let mut ans = TOKEN_FALSE;
-                if bfs.inner.has(self.elt)? {
-                        ans = TOKEN_TRUE;
+        fn execute(&self, bf: &dyn MembershipFilter, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let mut ans = proto::TOKEN_FALSE;
+                if bf.has(self.elt)? {
+                        ans = proto::TOKEN_TRUE;
                 }
                 resp.append(ans);
                 Ok(())
@@ -172,195 +538,1199 @@ struct ReadOpBloomFilterHasBatch<'a> {
 
 
 impl ReadOpBloomFilterHasBatch<'_> {
-        fn execute(&self, bfs: &BloomFilterStructure, resp: &mut CmdResponseTLV) -> io::Result<()> {
-                let mut idx = 0;
-                let len = self.elts.len();
-                let mut ans_elt;
-
-                while idx < len {
-                        let lv = match LV::new(&self.elts[idx..]) {
-                                Ok(v) => v,
-                                Err(_) => {
-                                        resp.init_error_response(CmdError::RequestBytesMalformed);
-                                        return Ok(());
-                                }
-                        };
-                        ans_elt = TOKEN_FALSE;
-                        if bfs.inner.has(lv.val)? {
-                                ans_elt = TOKEN_TRUE;
+        fn execute(&self, bf: &dyn MembershipFilter, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let Some(elts) = split_batch(self.elts) else {
+                        resp.init_error_detail(CmdError::RequestBytesMalformed, "malformed element list");
+                        return Ok(());
+                };
+                for elt in elts {
+                        let mut ans_elt = proto::TOKEN_FALSE;
+                        if bf.has(elt)? {
+                                ans_elt = proto::TOKEN_TRUE;
                         }
                         resp.append(ans_elt);
-                        idx += lv.val.len()+1;
                 }
                 Ok(())
         }
 }
 
 
-pub enum WriteCmd<'a> {
-        Ctl(WriteCmdCtl),
-        Database(WriteCmdDatabase),
-        BloomFilter(WriteCmdBloomFilter<'a>),
+pub(crate) struct ReadCmdCountingBloomFilter<'a> {
+        db_id: u8,
+        cbf_id: u8,
+        op: ReadOpCountingBloomFilter<'a>,
 }
 
 
-pub(crate) struct WriteCmdCtl {
-        op: WriteOpCtl,
+enum ReadOpCountingBloomFilter<'a> {
+        Has(ReadOpCountingBloomFilterHas<'a>),
 }
 
 
-enum WriteOpCtl {
-        WalReplay,
-        LoadData,
+struct ReadOpCountingBloomFilterHas<'a> {
+        elt: &'a [u8],
+}
+
+
+impl ReadOpCountingBloomFilterHas<'_> {
+        fn execute(&self, cbfs: &CountingBloomFilterStructure, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let mut ans = proto::TOKEN_FALSE;
+                if cbfs.inner.has(self.elt)? {
+                        ans = proto::TOKEN_TRUE;
+                }
+                resp.append(ans);
+                Ok(())
+        }
 }
 
 
-pub(crate) struct WriteCmdDatabase {
+pub(crate) struct ReadCmdCuckooFilter<'a> {
         db_id: u8,
-        op: WriteOpDatabase,
+        cf_id: u8,
+        op: ReadOpCuckooFilter<'a>,
 }
 
 
-enum WriteOpDatabase {
-        NewBloomFilter(WriteOpDatabaseNewBloomFilter),
+enum ReadOpCuckooFilter<'a> {
+        Has(ReadOpCuckooFilterHas<'a>),
 }
 
 
-struct WriteOpDatabaseNewBloomFilter {
-        bf_id: u8,
+struct ReadOpCuckooFilterHas<'a> {
+        elt: &'a [u8],
 }
 
 
-impl WriteOpDatabaseNewBloomFilter {
-        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
-                match db.bf_registry.get(&[self.bf_id]) {
-                        Some(_) => {
-                                resp.init_error_response(CmdError::BloomFilterExists);
-                        }
-                        None => {
-                                db.bf_registry.add(BloomFilterStructure::new_default(self.bf_id, db.id), &[self.bf_id])?;
-                        }
+impl ReadOpCuckooFilterHas<'_> {
+        fn execute(&self, cfs: &CuckooFilterStructure, resp: &mut CmdResponseTLV) {
+                let mut ans = proto::TOKEN_FALSE;
+                if cfs.inner.has(self.elt) {
+                        ans = proto::TOKEN_TRUE;
                 }
-                Ok(())
+                resp.append(ans);
         }
 }
 
 
-pub(crate) struct WriteCmdBloomFilter<'a> {
+pub(crate) struct ReadCmdHyperLogLog {
         db_id: u8,
-        bf_id: u8,
-        op: WriteOpBloomFilter<'a>,
+        hll_id: u8,
+        op: ReadOpHyperLogLog,
 }
 
 
-enum WriteOpBloomFilter<'a> {
-        Add(WriteOpBloomFilterAdd<'a>),
-        AddBatch(WriteOpBloomFilterAddBatch<'a>),
+enum ReadOpHyperLogLog {
+        Count(ReadOpHyperLogLogCount),
 }
 
 
-struct WriteOpBloomFilterAdd<'a> {
+struct ReadOpHyperLogLogCount;
+
+
+impl ReadOpHyperLogLogCount {
+        fn execute(&self, hlls: &HyperLogLogStructure, resp: &mut CmdResponseTLV) {
+                resp.extend(&hlls.inner.count().to_le_bytes());
+        }
+}
+
+
+pub(crate) struct ReadCmdCountMinSketch<'a> {
+        db_id: u8,
+        cms_id: u8,
+        op: ReadOpCountMinSketch<'a>,
+}
+
+
+enum ReadOpCountMinSketch<'a> {
+        Query(ReadOpCountMinSketchQuery<'a>),
+}
+
+
+struct ReadOpCountMinSketchQuery<'a> {
         elt: &'a [u8],
 }
 
 
-impl WriteOpBloomFilterAdd<'_> {
-        fn execute(&self, bfs: &mut BloomFilterStructure, _resp: &mut CmdResponseTLV) -> io::Result<()> {
-                bfs.inner.add(self.elt)?;
-                Ok(())
+impl ReadOpCountMinSketchQuery<'_> {
+        fn execute(&self, cmss: &CountMinSketchStructure, resp: &mut CmdResponseTLV) {
+                resp.extend(&cmss.inner.query(self.elt).to_le_bytes());
         }
 }
 
 
-struct WriteOpBloomFilterAddBatch<'a> {
+pub(crate) struct ReadCmdTopK<'a> {
+        db_id: u8,
+        topk_id: u8,
+        op: ReadOpTopK<'a>,
+}
+
+
+enum ReadOpTopK<'a> {
+        Query(ReadOpTopKQuery<'a>),
+        Count(ReadOpTopKCount<'a>),
+        List(ReadOpTopKList),
+}
+
+
+struct ReadOpTopKQuery<'a> {
         elts: &'a [u8],
 }
 
 
-impl WriteOpBloomFilterAddBatch<'_> {
-        fn execute(&self, bfs: &mut BloomFilterStructure, resp: &mut CmdResponseTLV) -> io::Result<()> {
-                let mut idx = 0;
-                let len = self.elts.len();
-
-                while idx < len {
-                        let lv = match LV::new(&self.elts[idx..]) {
-                                Ok(v) => v,
-                                Err(_) => {
-                                        resp.init_error_response(CmdError::RequestBytesMalformed);
-                                        return Ok(());
-                                }
-                        };
-                        bfs.inner.add(lv.val)?;
-                        idx += lv.val.len()+1;
+impl ReadOpTopKQuery<'_> {
+        fn execute(&self, topks: &TopKStructure, resp: &mut CmdResponseTLV) {
+                let Some(elts) = split_batch(self.elts) else {
+                        resp.init_error_detail(CmdError::RequestBytesMalformed, "malformed element list");
+                        return;
+                };
+                for elt in elts {
+                        resp.append(if topks.inner.has(elt) { proto::TOKEN_TRUE } else { proto::TOKEN_FALSE });
+                }
+        }
+}
+
+
+struct ReadOpTopKCount<'a> {
+        elt: &'a [u8],
+}
+
+
+impl ReadOpTopKCount<'_> {
+        fn execute(&self, topks: &TopKStructure, resp: &mut CmdResponseTLV) {
+                resp.extend(&topks.inner.count(self.elt).to_le_bytes());
+        }
+}
+
+
+// The list is the entry count (u32) followed by key_len (u32) | key | count
+// (u64) for every entry, most frequent first, all little-endian.
+struct ReadOpTopKList;
+
+
+impl ReadOpTopKList {
+        #[allow(clippy::cast_possible_truncation)]
+        fn execute(&self, topks: &TopKStructure, resp: &mut CmdResponseTLV) {
+                let entries = topks.inner.list();
+                resp.extend(&(entries.len() as u32).to_le_bytes());
+                for entry in entries {
+                        resp.extend(&(entry.key.len() as u32).to_le_bytes());
+                        resp.extend(&entry.key);
+                        resp.extend(&entry.count.to_le_bytes());
+                }
+        }
+}
+
+
+pub(crate) enum WriteCmd<'a> {
+        Ctl(WriteCmdCtl<'a>),
+        Database(WriteCmdDatabase<'a>),
+        BloomFilter(WriteCmdBloomFilter<'a>),
+        CountingBloomFilter(WriteCmdCountingBloomFilter<'a>),
+        CuckooFilter(WriteCmdCuckooFilter<'a>),
+        HyperLogLog(WriteCmdHyperLogLog<'a>),
+        CountMinSketch(WriteCmdCountMinSketch<'a>),
+        TopK(WriteCmdTopK<'a>),
+}
+
+
+pub(crate) struct WriteCmdCtl<'a> {
+        op: WriteOpCtl<'a>,
+}
+
+
+impl WriteCmd<'_> {
+        /// Whether the command changes the state, and so goes to the
+        /// write-ahead log once it succeeds.
+        pub(crate) fn is_logged(&self) -> bool {
+                match self {
+                        WriteCmd::Ctl(cmd) => cmd.is_logged(),
+                        _ => true,
+                }
+        }
+}
+
+
+impl WriteCmdCtl<'_> {
+        // Most control commands act on the files backing the state, but
+        // those that change the state itself go to the write-ahead log.
+        pub(crate) fn is_logged(&self) -> bool {
+                matches!(self.op, WriteOpCtl::CreateDatabase(_) | WriteOpCtl::DropDatabase(_) | WriteOpCtl::NameDatabase(_))
+        }
+}
+
+
+enum WriteOpCtl<'a> {
+        WalReplay,
+        LoadData,
+        Checkpoint,
+        CreateDatabase(WriteOpCtlCreateDatabase<'a>),
+        DropDatabase(WriteOpCtlDropDatabase),
+        NameDatabase(WriteOpCtlNameDatabase<'a>),
+}
+
+
+struct WriteOpCtlCreateDatabase<'a> {
+        db_id: u8,
+        name: Option<&'a str>,
+}
+
+
+impl WriteOpCtlCreateDatabase<'_> {
+        fn execute(&self, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                if !ctl.create_database(self.db_id, self.name)? {
+                        resp.init_error_detail(CmdError::ObjectExists, format!("database {} or its name already exists", self.db_id));
                 }
                 Ok(())
         }
 }
 
 
-pub struct CmdTLV<'a> {
-        cmd_type: [u8; 4],
-        val: &'a [u8],
+// Rename a database, or leave it unnamed given an empty name.
+struct WriteOpCtlNameDatabase<'a> {
+        db_id: u8,
+        name: &'a str,
 }
 
 
-impl<'a> CmdTLV<'a> {
-        pub fn new(buf: &'a [u8]) -> io::Result<Self> {
-                if buf.len() < 9 {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CmdTLV: new: too few bytes in buffer to form TLV"));
+impl WriteOpCtlNameDatabase<'_> {
+        fn execute(&self, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) {
+                if ctl.lookup_database(self.name).is_some_and(|db_id| db_id != self.db_id) {
+                        resp.init_error_detail(CmdError::ObjectExists, format!("another database is named {}", self.name));
+                        return;
+                }
+                match ctl.db_registry.get_mut(&[self.db_id]) {
+                        Some(db) => { db.name = (!self.name.is_empty()).then(|| self.name.to_owned()); }
+                        None => { resp.database_not_found(self.db_id); }
                 }
+        }
+}
 
-                // The bytes at indices 0, 1, 2, and 3 are reserved for the command type
-                let cmd_type: [u8; 4] = buf[0..4].try_into().unwrap();
 
-                // The bytes at indices 4, 5, 6, and 7 are reserved for the command type
-                let len = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+struct WriteOpCtlDropDatabase {
+        db_id: u8,
+}
 
-                // The bytes at indices 8 and beyond are used for the value
-                if buf.len() < 8+len as usize {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CmdTLV: new: too few bytes in buffer to form value"));
+
+impl WriteOpCtlDropDatabase {
+        fn execute(&self, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) {
+                if !ctl.drop_database(self.db_id) {
+                        resp.database_not_found(self.db_id);
+                }
+        }
+}
+
+
+// A creation may name the new object, which is then created and named in
+// one command, as one record of the log.
+pub(crate) struct WriteCmdDatabase<'a> {
+        db_id: u8,
+        name: Option<&'a str>,
+        op: WriteOpDatabase<'a>,
+}
+
+
+enum WriteOpDatabase<'a> {
+        NewBloomFilter(WriteOpDatabaseNewBloomFilter),
+        NewCountingBloomFilter(WriteOpDatabaseNewCountingBloomFilter),
+        NewScalableBloomFilter(WriteOpDatabaseNewScalableBloomFilter),
+        NewCuckooFilter(WriteOpDatabaseNewCuckooFilter),
+        NewHyperLogLog(WriteOpDatabaseNewHyperLogLog),
+        NewCountMinSketch(WriteOpDatabaseNewCountMinSketch),
+        NewTopK(WriteOpDatabaseNewTopK),
+        DropObject(WriteOpDatabaseDropObject),
+        NameObject(WriteOpDatabaseNameObject<'a>),
+}
+
+
+impl WriteOpDatabase<'_> {
+        // The object a creation makes
+        fn new_object(&self) -> Option<(db::ObjectKind, u8)> {
+                match self {
+                        WriteOpDatabase::NewBloomFilter(op) => Some((db::ObjectKind::BloomFilter, op.bf_id)),
+                        WriteOpDatabase::NewCountingBloomFilter(op) => Some((db::ObjectKind::CountingBloomFilter, op.cbf_id)),
+                        WriteOpDatabase::NewScalableBloomFilter(op) => Some((db::ObjectKind::BloomFilter, op.sbf_id)),
+                        WriteOpDatabase::NewCuckooFilter(op) => Some((db::ObjectKind::CuckooFilter, op.cf_id)),
+                        WriteOpDatabase::NewHyperLogLog(op) => Some((db::ObjectKind::HyperLogLog, op.hll_id)),
+                        WriteOpDatabase::NewCountMinSketch(op) => Some((db::ObjectKind::CountMinSketch, op.cms_id)),
+                        WriteOpDatabase::NewTopK(op) => Some((db::ObjectKind::TopK, op.topk_id)),
+                        WriteOpDatabase::DropObject(_) | WriteOpDatabase::NameObject(_) => None,
+                }
+        }
+
+        // The size in bytes of the object a creation asks for, where the
+        // command chooses it
+        fn new_object_sz(&self) -> Option<usize> {
+                match self {
+                        WriteOpDatabase::NewBloomFilter(op) => op.params.map(|params| params.byte_len()),
+                        WriteOpDatabase::NewCuckooFilter(op) => op.params.map(|params| params.byte_len()),
+                        WriteOpDatabase::NewCountingBloomFilter(_)
+                        | WriteOpDatabase::NewScalableBloomFilter(_)
+                        | WriteOpDatabase::NewHyperLogLog(_)
+                        | WriteOpDatabase::NewCountMinSketch(_)
+                        | WriteOpDatabase::NewTopK(_)
+                        | WriteOpDatabase::DropObject(_)
+                        | WriteOpDatabase::NameObject(_) => None,
+                }
+        }
+}
+
+
+struct WriteOpDatabaseNewBloomFilter {
+        bf_id: u8,
+        params: Option<BloomFilterParams>,
+}
+
+
+impl WriteOpDatabaseNewBloomFilter {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                if db.has_bloom_filter(self.bf_id) {
+                        resp.object_exists(db::ObjectKind::BloomFilter, db.id, self.bf_id);
+                        return Ok(());
+                }
+                let bfs = match &self.params {
+                        Some(params) => BloomFilterStructure::with_params(self.bf_id, db.id, params),
+                        None => BloomFilterStructure::new_default(self.bf_id, db.id),
+                };
+                db.bf_registry.add(bfs, &[self.bf_id])?;
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewCountingBloomFilter {
+        cbf_id: u8,
+        width: CounterWidth,
+        params: Option<BloomFilterParams>,
+}
+
+
+impl WriteOpDatabaseNewCountingBloomFilter {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                match db.cbf_registry.get(&[self.cbf_id]) {
+                        Some(_) => {
+                                resp.object_exists(db::ObjectKind::CountingBloomFilter, db.id, self.cbf_id);
+                        }
+                        None => {
+                                let params = self.params.unwrap_or_default();
+                                let cbfs = CountingBloomFilterStructure::with_params(self.cbf_id, db.id, &params, self.width);
+                                db.cbf_registry.add(cbfs, &[self.cbf_id])?;
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewScalableBloomFilter {
+        sbf_id: u8,
+        params: Option<ScalableBloomFilterParams>,
+}
+
+
+impl WriteOpDatabaseNewScalableBloomFilter {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                if db.has_bloom_filter(self.sbf_id) {
+                        resp.object_exists(db::ObjectKind::BloomFilter, db.id, self.sbf_id);
+                        return Ok(());
+                }
+                let params = self.params.unwrap_or_default();
+                let sbfs = ScalableBloomFilterStructure::with_params(self.sbf_id, db.id, &params)?;
+                db.sbf_registry.add(sbfs, &[self.sbf_id])?;
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewCuckooFilter {
+        cf_id: u8,
+        params: Option<CuckooFilterParams>,
+}
+
+
+impl WriteOpDatabaseNewCuckooFilter {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                match db.cf_registry.get(&[self.cf_id]) {
+                        Some(_) => {
+                                resp.object_exists(db::ObjectKind::CuckooFilter, db.id, self.cf_id);
+                        }
+                        None => {
+                                let params = self.params.unwrap_or_default();
+                                let cfs = CuckooFilterStructure::with_params(self.cf_id, db.id, &params);
+                                db.cf_registry.add(cfs, &[self.cf_id])?;
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewHyperLogLog {
+        hll_id: u8,
+        precision: u8,
+}
+
+
+impl WriteOpDatabaseNewHyperLogLog {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                match db.hll_registry.get(&[self.hll_id]) {
+                        Some(_) => {
+                                resp.object_exists(db::ObjectKind::HyperLogLog, db.id, self.hll_id);
+                        }
+                        None => {
+                                let hlls = HyperLogLogStructure::new(self.hll_id, db.id, self.precision);
+                                db.hll_registry.add(hlls, &[self.hll_id])?;
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewCountMinSketch {
+        cms_id: u8,
+        conservative: bool,
+        params: Option<CountMinSketchParams>,
+}
+
+
+impl WriteOpDatabaseNewCountMinSketch {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                match db.cms_registry.get(&[self.cms_id]) {
+                        Some(_) => {
+                                resp.object_exists(db::ObjectKind::CountMinSketch, db.id, self.cms_id);
+                        }
+                        None => {
+                                let params = self.params.unwrap_or_default();
+                                let cmss = CountMinSketchStructure::with_params(self.cms_id, db.id, &params, self.conservative);
+                                db.cms_registry.add(cmss, &[self.cms_id])?;
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseNewTopK {
+        topk_id: u8,
+        k: usize,
+}
+
+
+impl WriteOpDatabaseNewTopK {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                match db.topk_registry.get(&[self.topk_id]) {
+                        Some(_) => {
+                                resp.object_exists(db::ObjectKind::TopK, db.id, self.topk_id);
+                        }
+                        None => {
+                                let topks = TopKStructure::new(self.topk_id, db.id, self.k);
+                                db.topk_registry.add(topks, &[self.topk_id])?;
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+struct WriteOpDatabaseDropObject {
+        kind: db::ObjectKind,
+        obj_id: u8,
+}
+
+
+impl WriteOpDatabaseDropObject {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) {
+                if !db.drop_object(self.kind, self.obj_id) {
+                        resp.object_not_found(self.kind, db.id, self.obj_id);
+                }
+        }
+}
+
+
+// Rename an object, or leave it unnamed given an empty name.
+struct WriteOpDatabaseNameObject<'a> {
+        kind: db::ObjectKind,
+        obj_id: u8,
+        name: &'a str,
+}
+
+
+impl WriteOpDatabaseNameObject<'_> {
+        fn execute(&self, db: &mut db::Database, resp: &mut CmdResponseTLV) {
+                if !db.has_object(self.kind, self.obj_id) {
+                        resp.object_not_found(self.kind, db.id, self.obj_id);
+                        return;
+                }
+                if db.lookup(self.name).is_some_and(|obj| obj != (self.kind, self.obj_id)) {
+                        resp.init_error_detail(CmdError::ObjectExists, format!("another object is named {}", self.name));
+                        return;
+                }
+                db.set_name(self.kind, self.obj_id, self.name);
+        }
+}
+
+
+pub(crate) struct WriteCmdBloomFilter<'a> {
+        db_id: u8,
+        bf_id: u8,
+        op: WriteOpBloomFilter<'a>,
+}
+
+
+enum WriteOpBloomFilter<'a> {
+        Add(WriteOpBloomFilterAdd<'a>),
+        AddBatch(WriteOpBloomFilterAddBatch<'a>),
+}
+
+
+struct WriteOpBloomFilterAdd<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpBloomFilterAdd<'_> {
+        fn execute(&self, bf: &mut dyn MembershipFilter, _resp: &mut CmdResponseTLV) -> io::Result<()> {
+                bf.add(self.elt)?;
+                Ok(())
+        }
+}
+
+
+struct WriteOpBloomFilterAddBatch<'a> {
+        elts: &'a [u8],
+}
+
+
+impl WriteOpBloomFilterAddBatch<'_> {
+        fn execute(&self, bf: &mut dyn MembershipFilter, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let Some(elts) = split_batch(self.elts) else {
+                        resp.init_error_detail(CmdError::RequestBytesMalformed, "malformed element list");
+                        return Ok(());
+                };
+                bf.add_batch(&elts)
+        }
+}
+
+
+pub(crate) struct WriteCmdCountingBloomFilter<'a> {
+        db_id: u8,
+        cbf_id: u8,
+        op: WriteOpCountingBloomFilter<'a>,
+}
+
+
+enum WriteOpCountingBloomFilter<'a> {
+        Add(WriteOpCountingBloomFilterAdd<'a>),
+        Remove(WriteOpCountingBloomFilterRemove<'a>),
+}
+
+
+struct WriteOpCountingBloomFilterAdd<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpCountingBloomFilterAdd<'_> {
+        fn execute(&self, cbfs: &mut CountingBloomFilterStructure, _resp: &mut CmdResponseTLV) -> io::Result<()> {
+                cbfs.inner.add(self.elt)?;
+                Ok(())
+        }
+}
+
+
+struct WriteOpCountingBloomFilterRemove<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpCountingBloomFilterRemove<'_> {
+        fn execute(&self, cbfs: &mut CountingBloomFilterStructure, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let mut ans = proto::TOKEN_FALSE;
+                if cbfs.inner.remove(self.elt)? {
+                        ans = proto::TOKEN_TRUE;
+                }
+                resp.append(ans);
+                Ok(())
+        }
+}
+
+
+pub(crate) struct WriteCmdCuckooFilter<'a> {
+        db_id: u8,
+        cf_id: u8,
+        op: WriteOpCuckooFilter<'a>,
+}
+
+
+enum WriteOpCuckooFilter<'a> {
+        Add(WriteOpCuckooFilterAdd<'a>),
+        Delete(WriteOpCuckooFilterDelete<'a>),
+}
+
+
+struct WriteOpCuckooFilterAdd<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpCuckooFilterAdd<'_> {
+        fn execute(&self, cfs: &mut CuckooFilterStructure, resp: &mut CmdResponseTLV) {
+                if !cfs.inner.insert(self.elt) {
+                        resp.init_error_detail(CmdError::FilterFull, format!("cuckoo filter {} is full", cfs.id));
+                }
+        }
+}
+
+
+struct WriteOpCuckooFilterDelete<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpCuckooFilterDelete<'_> {
+        fn execute(&self, cfs: &mut CuckooFilterStructure, resp: &mut CmdResponseTLV) {
+                let mut ans = proto::TOKEN_FALSE;
+                if cfs.inner.delete(self.elt) {
+                        ans = proto::TOKEN_TRUE;
+                }
+                resp.append(ans);
+        }
+}
+
+
+pub(crate) struct WriteCmdHyperLogLog<'a> {
+        db_id: u8,
+        hll_id: u8,
+        op: WriteOpHyperLogLog<'a>,
+}
+
+
+enum WriteOpHyperLogLog<'a> {
+        Add(WriteOpHyperLogLogAdd<'a>),
+        AddBatch(WriteOpHyperLogLogAddBatch<'a>),
+        Merge(WriteOpHyperLogLogMerge<'a>),
+}
+
+
+struct WriteOpHyperLogLogAdd<'a> {
+        elt: &'a [u8],
+}
+
+
+impl WriteOpHyperLogLogAdd<'_> {
+        fn execute(&self, hlls: &mut HyperLogLogStructure, _resp: &mut CmdResponseTLV) {
+                hlls.inner.add(self.elt);
+        }
+}
+
+
+struct WriteOpHyperLogLogAddBatch<'a> {
+        elts: &'a [u8],
+}
+
+
+impl WriteOpHyperLogLogAddBatch<'_> {
+        fn execute(&self, hlls: &mut HyperLogLogStructure, resp: &mut CmdResponseTLV) {
+                let Some(elts) = split_batch(self.elts) else {
+                        resp.init_error_detail(CmdError::RequestBytesMalformed, "malformed element list");
+                        return;
+                };
+                for elt in elts {
+                        hlls.inner.add(elt);
+                }
+        }
+}
+
+
+// Fold the source sketches, given as a list of ids in the same database,
+// into the target sketch.
+struct WriteOpHyperLogLogMerge<'a> {
+        src_ids: &'a [u8],
+}
+
+
+impl WriteOpHyperLogLogMerge<'_> {
+        fn execute(&self, db: &mut db::Database, hll_id: u8, resp: &mut CmdResponseTLV) -> io::Result<()> {
+                let mut srcs = Vec::<HyperLogLog>::with_capacity(self.src_ids.len());
+                for src_id in self.src_ids {
+                        match db.hll_registry.get(&[*src_id]) {
+                                Some(src) => { srcs.push(src.inner.clone()); }
+                                None => {
+                                        resp.object_not_found(db::ObjectKind::HyperLogLog, db.id, *src_id);
+                                        return Ok(());
+                                }
+                        }
+                }
+                let db_id = db.id;
+                let Some(target) = db.hll_registry.get_mut(&[hll_id]) else {
+                        resp.object_not_found(db::ObjectKind::HyperLogLog, db_id, hll_id);
+                        return Ok(());
+                };
+                if srcs.iter().any(|src| src.precision != target.inner.precision) {
+                        resp.init_error_detail(CmdError::InvalidParameters, "cannot merge sketches of different precision");
+                        return Ok(());
+                }
+                for src in &srcs {
+                        target.inner.merge(src)?;
+                }
+                Ok(())
+        }
+}
+
+
+pub(crate) struct WriteCmdCountMinSketch<'a> {
+        db_id: u8,
+        cms_id: u8,
+        op: WriteOpCountMinSketch<'a>,
+}
+
+
+enum WriteOpCountMinSketch<'a> {
+        Incr(WriteOpCountMinSketchIncr<'a>),
+}
+
+
+struct WriteOpCountMinSketchIncr<'a> {
+        elt: &'a [u8],
+        n: u64,
+}
+
+
+impl WriteOpCountMinSketchIncr<'_> {
+        fn execute(&self, cmss: &mut CountMinSketchStructure, resp: &mut CmdResponseTLV) {
+                let est = cmss.inner.incr(self.elt, self.n);
+                resp.extend(&est.to_le_bytes());
+        }
+}
+
+
+pub(crate) struct WriteCmdTopK<'a> {
+        db_id: u8,
+        topk_id: u8,
+        op: WriteOpTopK<'a>,
+}
+
+
+enum WriteOpTopK<'a> {
+        Add(WriteOpTopKAdd<'a>),
+}
+
+
+struct WriteOpTopKAdd<'a> {
+        elt: &'a [u8],
+        n: u64,
+}
+
+
+impl WriteOpTopKAdd<'_> {
+        // The response holds the key that was dropped to make room, in the
+        // encoding of a list entry key, or nothing if no key was dropped.
+        #[allow(clippy::cast_possible_truncation)]
+        fn execute(&self, topks: &mut TopKStructure, resp: &mut CmdResponseTLV) {
+                if let Some(evicted) = topks.inner.add(self.elt, self.n) {
+                        resp.extend(&(evicted.len() as u32).to_le_bytes());
+                        resp.extend(&evicted);
+                }
+        }
+}
+
+
+pub struct CmdTLV<'a> {
+        cmd_type: [u8; 4],
+        val: &'a [u8],
+        raw: &'a [u8],
+}
+
+
+impl<'a> CmdTLV<'a> {
+        /// The length of the frame at the start of the buffer, header
+        /// included, or None if the header hasn't fully arrived.
+        #[must_use]
+        pub fn frame_len(buf: &[u8]) -> Option<usize> {
+                let len = u32::from_le_bytes(buf.get(4..proto::HEADER_SZ)?.try_into().unwrap());
+                Some(proto::HEADER_SZ.saturating_add(len as usize))
+        }
+
+        /// The request id in the header at the start of the buffer, if the
+        /// client chose one.
+        #[must_use]
+        pub fn peek_request_id(buf: &[u8]) -> Option<u16> {
+                let req_id = u16::from_le_bytes(buf.get(2..4)?.try_into().unwrap());
+                (req_id != proto::NO_REQUEST_ID).then_some(req_id)
+        }
+
+        pub fn new(buf: &'a [u8]) -> io::Result<Self> {
+                if buf.len() < proto::HEADER_SZ {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CmdTLV: new: too few bytes in buffer to form TLV"));
+                }
+
+                // The bytes at indices 0, 1, 2, and 3 are reserved for the command type,
+                // the last two of which hold the request id
+                let cmd_type: [u8; 4] = buf[0..4].try_into().unwrap();
+
+                // The bytes at indices 4, 5, 6, and 7 are reserved for the command type
+                let len = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+
+                // The bytes at indices 8 and beyond are used for the value
+                if buf.len() < 8+len as usize {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CmdTLV: new: too few bytes in buffer to form value"));
+                }
+
+                let val = &buf[8..(8+len as usize)];
+                let raw = &buf[..(8+len as usize)];
+                Ok(Self { cmd_type, val, raw })
+        }
+
+        // The whole frame, header included, as it is logged to the WAL
+        pub fn bytes(&self) -> &'a [u8] {
+                self.raw
+        }
+}
+
+
+fn decode_bf_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+        let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
+
+        if val.len() < 2 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_bf_cmd: too few bytes in buffer"));
+        }
+        let db_id = val[0];
+        let bf_id = val[1];
+
+        // Describing the filter takes no value
+        if cmd_type == 4 {
+                let op = ReadOpBloomFilter::Info(ReadOpBloomFilterInfo);
+                return Ok(Cmd::Read(ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op })));
+        }
+
+        let lv = LV::new(&val[2..])?;
+
+        Ok(match cmd_type {
+                0 => {
+                        let op = WriteOpBloomFilter::Add(WriteOpBloomFilterAdd { elt: lv.val });
+                        Cmd::Write(WriteCmd::BloomFilter(WriteCmdBloomFilter { db_id, bf_id, op }))
+                }
+                1 => {
+                        let op = WriteOpBloomFilter::AddBatch(WriteOpBloomFilterAddBatch { elts: lv.val });
+                        Cmd::Write(WriteCmd::BloomFilter(WriteCmdBloomFilter { db_id, bf_id, op }))
+                }
+                2 => {
+                        let op = ReadOpBloomFilter::Has(ReadOpBloomFilterHas { elt: lv.val });
+                        Cmd::Read(ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op }))
+                }
+                3 => {
+                        let op = ReadOpBloomFilter::HasBatch(ReadOpBloomFilterHasBatch { elts: lv.val });
+                        Cmd::Read(ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op }))
+                }
+                _ => {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_bf_cmd: unrecognized command"));
+                }
+        })
+}
+
+
+// The optional parameter block of a new bloom filter is either
+// PARAMS_EXPLICIT followed by bit_cnt (u64) and hfn_cnt (u8), or
+// PARAMS_ESTIMATE followed by the expected item count (u64) and the target
+// false-positive rate (f64), all little-endian.
+fn decode_bf_params(buf: &[u8]) -> io::Result<Option<BloomFilterParams>> {
+        if buf.is_empty() {
+                return Ok(None);
+        }
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "decode_bf_params: malformed parameter block");
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "decode_bf_params: parameters out of range");
+        let read_u64 = |i: usize| -> io::Result<u64> {
+                let bytes = buf.get(i..i+U64_OFFSET).ok_or_else(malformed)?;
+                Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let params = match buf[0] {
+                proto::PARAMS_EXPLICIT => {
+                        if buf.len() != 2*U8_OFFSET + U64_OFFSET {
+                                return Err(malformed());
+                        }
+                        let bit_cnt = usize::try_from(read_u64(U8_OFFSET)?).map_err(|_| malformed())?;
+                        BloomFilterParams::new(bit_cnt, buf[U8_OFFSET+U64_OFFSET] as usize)
+                }
+                proto::PARAMS_ESTIMATE => {
+                        if buf.len() != U8_OFFSET + 2*U64_OFFSET {
+                                return Err(malformed());
+                        }
+                        let item_cnt = usize::try_from(read_u64(U8_OFFSET)?).map_err(|_| malformed())?;
+                        let fpr = f64::from_bits(read_u64(U8_OFFSET+U64_OFFSET)?);
+                        BloomFilterParams::from_estimate(item_cnt, fpr)
+                }
+                _ => { return Err(malformed()); }
+        };
+        params.map(Some).ok_or_else(invalid)
+}
+
+
+// The optional parameter block of a new scalable bloom filter is the initial
+// capacity (u64) and the false-positive bound (f64), optionally followed by
+// the growth factor (u8) and the tightening ratio (f64), all little-endian.
+fn decode_sbf_params(buf: &[u8]) -> io::Result<Option<ScalableBloomFilterParams>> {
+        if buf.is_empty() {
+                return Ok(None);
+        }
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "decode_sbf_params: malformed parameter block");
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "decode_sbf_params: parameters out of range");
+        let read_u64 = |i: usize| -> io::Result<u64> {
+                let bytes = buf.get(i..i+U64_OFFSET).ok_or_else(malformed)?;
+                Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let defaults = ScalableBloomFilterParams::default();
+        let initial_cpty = usize::try_from(read_u64(0)?).map_err(|_| malformed())?;
+        let fpr = f64::from_bits(read_u64(U64_OFFSET)?);
+        let (growth, tightening) = match buf.len() {
+                16 => (defaults.growth, defaults.tightening),
+                25 => (buf[2*U64_OFFSET] as usize, f64::from_bits(read_u64(2*U64_OFFSET + U8_OFFSET)?)),
+                _ => { return Err(malformed()); }
+        };
+        ScalableBloomFilterParams::new(initial_cpty, fpr, growth, tightening)
+                .map(Some)
+                .ok_or_else(invalid)
+}
+
+
+// The optional parameter block of a new cuckoo filter is the capacity (u64),
+// the fingerprint size in bits (u8) and the bucket size (u8), optionally
+// followed by the maximum number of relocations per insert (u16), all
+// little-endian.
+fn decode_cf_params(buf: &[u8]) -> io::Result<Option<CuckooFilterParams>> {
+        if buf.is_empty() {
+                return Ok(None);
+        }
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "decode_cf_params: malformed parameter block");
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "decode_cf_params: parameters out of range");
+
+        let defaults = CuckooFilterParams::default();
+        if buf.len() != U64_OFFSET + 2*U8_OFFSET && buf.len() != U64_OFFSET + 4*U8_OFFSET {
+                return Err(malformed());
+        }
+        let cpty = usize::try_from(u64::from_le_bytes(buf[0..U64_OFFSET].try_into().unwrap())).map_err(|_| malformed())?;
+        let fp_bits = buf[U64_OFFSET];
+        let bucket_sz = buf[U64_OFFSET+1] as usize;
+        let max_kicks = match buf.get(U64_OFFSET+2..) {
+                Some(&[lo, hi]) => u16::from_le_bytes([lo, hi]) as usize,
+                _ => defaults.max_kicks,
+        };
+        CuckooFilterParams::from_capacity(cpty, bucket_sz, fp_bits, max_kicks)
+                .map(Some)
+                .ok_or_else(invalid)
+}
+
+
+fn decode_cf_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+        let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
+
+        if val.len() < 4 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_cf_cmd: too few bytes in buffer"));
+        }
+        let db_id = val[0];
+        let cf_id = val[1];
+        let lv = LV::new(&val[2..])?;
+
+        Ok(match cmd_type {
+                0 => {
+                        let op = WriteOpCuckooFilter::Add(WriteOpCuckooFilterAdd { elt: lv.val });
+                        Cmd::Write(WriteCmd::CuckooFilter(WriteCmdCuckooFilter { db_id, cf_id, op }))
+                }
+                1 => {
+                        let op = WriteOpCuckooFilter::Delete(WriteOpCuckooFilterDelete { elt: lv.val });
+                        Cmd::Write(WriteCmd::CuckooFilter(WriteCmdCuckooFilter { db_id, cf_id, op }))
+                }
+                2 => {
+                        let op = ReadOpCuckooFilter::Has(ReadOpCuckooFilterHas { elt: lv.val });
+                        Cmd::Read(ReadCmd::CuckooFilter(ReadCmdCuckooFilter { db_id, cf_id, op }))
+                }
+                _ => {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_cf_cmd: unrecognized command"));
+                }
+        })
+}
+
+
+fn decode_hll_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+        let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
+
+        if val.len() < 2 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_hll_cmd: too few bytes in buffer"));
+        }
+        let db_id = val[0];
+        let hll_id = val[1];
+
+        // Counting takes no value
+        if cmd_type == 2 {
+                let op = ReadOpHyperLogLog::Count(ReadOpHyperLogLogCount);
+                return Ok(Cmd::Read(ReadCmd::HyperLogLog(ReadCmdHyperLogLog { db_id, hll_id, op })));
+        }
+
+        let lv = LV::new(&val[2..])?;
+        Ok(match cmd_type {
+                0 => {
+                        let op = WriteOpHyperLogLog::Add(WriteOpHyperLogLogAdd { elt: lv.val });
+                        Cmd::Write(WriteCmd::HyperLogLog(WriteCmdHyperLogLog { db_id, hll_id, op }))
+                }
+                1 => {
+                        let op = WriteOpHyperLogLog::AddBatch(WriteOpHyperLogLogAddBatch { elts: lv.val });
+                        Cmd::Write(WriteCmd::HyperLogLog(WriteCmdHyperLogLog { db_id, hll_id, op }))
+                }
+                3 => {
+                        let op = WriteOpHyperLogLog::Merge(WriteOpHyperLogLogMerge { src_ids: lv.val });
+                        Cmd::Write(WriteCmd::HyperLogLog(WriteCmdHyperLogLog { db_id, hll_id, op }))
+                }
+                _ => {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_hll_cmd: unrecognized command"));
+                }
+        })
+}
+
+
+fn decode_cbf_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+        let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
+
+        if val.len() < 4 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_cbf_cmd: too few bytes in buffer"));
+        }
+        let db_id = val[0];
+        let cbf_id = val[1];
+        let lv = LV::new(&val[2..])?;
+
+        Ok(match cmd_type {
+                0 => {
+                        let op = WriteOpCountingBloomFilter::Add(WriteOpCountingBloomFilterAdd { elt: lv.val });
+                        Cmd::Write(WriteCmd::CountingBloomFilter(WriteCmdCountingBloomFilter { db_id, cbf_id, op }))
+                }
+                1 => {
+                        let op = WriteOpCountingBloomFilter::Remove(WriteOpCountingBloomFilterRemove { elt: lv.val });
+                        Cmd::Write(WriteCmd::CountingBloomFilter(WriteCmdCountingBloomFilter { db_id, cbf_id, op }))
+                }
+                2 => {
+                        let op = ReadOpCountingBloomFilter::Has(ReadOpCountingBloomFilterHas { elt: lv.val });
+                        Cmd::Read(ReadCmd::CountingBloomFilter(ReadCmdCountingBloomFilter { db_id, cbf_id, op }))
+                }
+                _ => {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_cbf_cmd: unrecognized command"));
+                }
+        })
+}
+
+
+// The optional parameter block of a new Count-Min sketch is either
+// PARAMS_EXPLICIT followed by width (u64) and depth (u8), or
+// PARAMS_ESTIMATE followed by the error factor epsilon (f64) and the
+// probability delta (f64) of exceeding it, all little-endian.
+fn decode_cms_params(buf: &[u8]) -> io::Result<Option<CountMinSketchParams>> {
+        if buf.is_empty() {
+                return Ok(None);
+        }
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "decode_cms_params: malformed parameter block");
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "decode_cms_params: parameters out of range");
+        let read_u64 = |i: usize| -> io::Result<u64> {
+                let bytes = buf.get(i..i+U64_OFFSET).ok_or_else(malformed)?;
+                Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        let params = match buf[0] {
+                proto::PARAMS_EXPLICIT => {
+                        if buf.len() != 2*U8_OFFSET + U64_OFFSET {
+                                return Err(malformed());
+                        }
+                        let width = usize::try_from(read_u64(U8_OFFSET)?).map_err(|_| malformed())?;
+                        CountMinSketchParams::new(width, buf[U8_OFFSET+U64_OFFSET] as usize)
+                }
+                proto::PARAMS_ESTIMATE => {
+                        if buf.len() != U8_OFFSET + 2*U64_OFFSET {
+                                return Err(malformed());
+                        }
+                        let epsilon = f64::from_bits(read_u64(U8_OFFSET)?);
+                        let delta = f64::from_bits(read_u64(U8_OFFSET+U64_OFFSET)?);
+                        CountMinSketchParams::from_error(epsilon, delta)
+                }
+                _ => { return Err(malformed()); }
+        };
+        params.map(Some).ok_or_else(invalid)
+}
+
+
+fn decode_cms_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+        let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
+
+        if val.len() < 3 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_cms_cmd: too few bytes in buffer"));
+        }
+        let db_id = val[0];
+        let cms_id = val[1];
+        let lv = LV::new(&val[2..])?;
+
+        Ok(match cmd_type {
+                0 => {
+                        let n = decode_incr(&val[3+lv.val.len()..])?;
+                        let op = WriteOpCountMinSketch::Incr(WriteOpCountMinSketchIncr { elt: lv.val, n });
+                        Cmd::Write(WriteCmd::CountMinSketch(WriteCmdCountMinSketch { db_id, cms_id, op }))
+                }
+                1 => {
+                        let op = ReadOpCountMinSketch::Query(ReadOpCountMinSketchQuery { elt: lv.val });
+                        Cmd::Read(ReadCmd::CountMinSketch(ReadCmdCountMinSketch { db_id, cms_id, op }))
+                }
+                _ => {
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_cms_cmd: unrecognized command"));
                 }
+        })
+}
 
-                let val = &buf[8..(8+len as usize)];
-                Ok(Self { cmd_type, val })
-        }
 
-        pub fn value(&self) -> &'a [u8] {
-                self.val
+// The increment following the element of an add is optional and defaults to
+// one.
+fn decode_incr(buf: &[u8]) -> io::Result<u64> {
+        match buf {
+                [] => Ok(1),
+                bytes if bytes.len() == U64_OFFSET => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "decode_incr: malformed increment")),
         }
 }
 
 
-fn decode_bf_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+fn decode_topk_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
         let cmd_type = tlv.cmd_type[1];
         let val = &tlv.val;
 
-        if val.len() < 4 {
-                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_bf_cmd: too few bytes in buffer"));
+        if val.len() < 2 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_topk_cmd: too few bytes in buffer"));
         }
         let db_id = val[0];
-        let bf_id = val[1];
-        let lv = LV::new(&val[2..])?;
+        let topk_id = val[1];
+
+        // Listing takes no value
+        if cmd_type == 3 {
+                let op = ReadOpTopK::List(ReadOpTopKList);
+                return Ok(Cmd::Read(ReadCmd::TopK(ReadCmdTopK { db_id, topk_id, op })));
+        }
 
+        let lv = LV::new(&val[2..])?;
         Ok(match cmd_type {
                 0 => {
-                        let op = WriteOpBloomFilter::Add(WriteOpBloomFilterAdd { elt: lv.val });
-                        Cmd::Write(WriteCmd::BloomFilter(WriteCmdBloomFilter { db_id, bf_id, op }))
+                        let n = decode_incr(&val[3+lv.val.len()..])?;
+                        let op = WriteOpTopK::Add(WriteOpTopKAdd { elt: lv.val, n });
+                        Cmd::Write(WriteCmd::TopK(WriteCmdTopK { db_id, topk_id, op }))
                 }
                 1 => {
-                        let op = WriteOpBloomFilter::AddBatch(WriteOpBloomFilterAddBatch { elts: lv.val });
-                        Cmd::Write(WriteCmd::BloomFilter(WriteCmdBloomFilter { db_id, bf_id, op }))
+                        let op = ReadOpTopK::Query(ReadOpTopKQuery { elts: lv.val });
+                        Cmd::Read(ReadCmd::TopK(ReadCmdTopK { db_id, topk_id, op }))
                 }
                 2 => {
-                        let op = ReadOpBloomFilter::Has(ReadOpBloomFilterHas { elt: lv.val });
-                        Cmd::Read(ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op }))
-                }
-                3 => {
-                        let op = ReadOpBloomFilter::HasBatch(ReadOpBloomFilterHasBatch { elts: lv.val });
-                        Cmd::Read(ReadCmd::BloomFilter(ReadCmdBloomFilter { db_id, bf_id, op }))
+                        let op = ReadOpTopK::Count(ReadOpTopKCount { elt: lv.val });
+                        Cmd::Read(ReadCmd::TopK(ReadCmdTopK { db_id, topk_id, op }))
                 }
                 _ => {
-                        return Err(io::Error::new(io::ErrorKind::Other, "decode_bf_cmd: unrecognized command"));
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_topk_cmd: unrecognized command"));
                 }
         })
 }
@@ -370,6 +1740,12 @@ fn decode_db_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
         let cmd_type = tlv.cmd_type[1];
         let val = &tlv.val;
 
+        // Listing the objects takes no value
+        if let (10, Some(&db_id)) = (cmd_type, val.first()) {
+                let op = ReadOpDatabase::ListObjects(ReadOpDatabaseListObjects);
+                return Ok(Cmd::Read(ReadCmd::Database(ReadCmdDatabase { db_id, op })));
+        }
+
         if val.len() < 3 {
                 return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_db_cmd: too few bytes in buffer"));
         }
@@ -377,13 +1753,102 @@ fn decode_db_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
         let db_id = val[0];
         let lv = LV::new(&val[1..])?;
 
+        // The value of every creation starts with the id of the new object,
+        // and the name of the object may follow it
+        let mut name = None;
+        if cmd_type <= 6 {
+                if lv.val.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_db_cmd: missing object id"));
+                }
+                let rest = &val[2+lv.val.len()..];
+                if !rest.is_empty() {
+                        name = Some(db::parse_name(rest)?);
+                }
+        }
+
         Ok(match cmd_type {
                 0 => {
-                        let op = WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id: lv.val[0] });
-                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, op }))
+                        let params = decode_bf_params(&lv.val[U8_OFFSET..])?;
+                        let op = WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id: lv.val[0], params });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                1 => {
+                        // The counter width and the parameter block are optional
+                        let width = match lv.val.get(U8_OFFSET) {
+                                Some(&bits) => CounterWidth::try_from(bits)?,
+                                None => CounterWidth::default(),
+                        };
+                        let params = decode_bf_params(lv.val.get(2*U8_OFFSET..).unwrap_or_default())?;
+                        let op = WriteOpDatabase::NewCountingBloomFilter(WriteOpDatabaseNewCountingBloomFilter { cbf_id: lv.val[0], width, params });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                2 => {
+                        let params = decode_sbf_params(&lv.val[U8_OFFSET..])?;
+                        let op = WriteOpDatabase::NewScalableBloomFilter(WriteOpDatabaseNewScalableBloomFilter { sbf_id: lv.val[0], params });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                3 => {
+                        let params = decode_cf_params(&lv.val[U8_OFFSET..])?;
+                        let op = WriteOpDatabase::NewCuckooFilter(WriteOpDatabaseNewCuckooFilter { cf_id: lv.val[0], params });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                4 => {
+                        // The precision is optional
+                        let precision = match lv.val.get(U8_OFFSET..) {
+                                Some(&[]) | None => HyperLogLog::DEFAULT_PRECISION,
+                                Some(&[p]) if (HyperLogLog::MIN_PRECISION..=HyperLogLog::MAX_PRECISION).contains(&p) => p,
+                                Some(_) => {
+                                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "decode_db_cmd: invalid HyperLogLog precision"));
+                                }
+                        };
+                        let op = WriteOpDatabase::NewHyperLogLog(WriteOpDatabaseNewHyperLogLog { hll_id: lv.val[0], precision });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                5 => {
+                        // The update mode and the parameter block are optional
+                        let conservative = lv.val.get(U8_OFFSET).is_some_and(|mode| *mode != 0);
+                        let params = decode_cms_params(lv.val.get(2*U8_OFFSET..).unwrap_or_default())?;
+                        let op = WriteOpDatabase::NewCountMinSketch(WriteOpDatabaseNewCountMinSketch { cms_id: lv.val[0], conservative, params });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                6 => {
+                        // k is optional
+                        let k = match lv.val.get(U8_OFFSET..) {
+                                Some(&[]) | None => TopK::DEFAULT_K,
+                                Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]) as usize,
+                                Some(_) => 0,
+                        };
+                        if !(1..=TopK::MAX_K).contains(&k) {
+                                return Err(io::Error::new(io::ErrorKind::InvalidInput, "decode_db_cmd: invalid Top-K size"));
+                        }
+                        let op = WriteOpDatabase::NewTopK(WriteOpDatabaseNewTopK { topk_id: lv.val[0], k });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                7 => {
+                        // The object is named by the family of the commands
+                        // serving it, followed by its id.
+                        let &[family, obj_id] = lv.val else {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, "decode_db_cmd: malformed object reference"));
+                        };
+                        let op = WriteOpDatabase::DropObject(WriteOpDatabaseDropObject { kind: db::ObjectKind::try_from(family)?, obj_id });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name, op }))
+                }
+                8 => {
+                        // The object reference is followed by the new name,
+                        // which is empty to leave the object unnamed.
+                        let &[family, obj_id, ref name @ ..] = lv.val else {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, "decode_db_cmd: malformed object reference"));
+                        };
+                        let name = if name.is_empty() { "" } else { db::parse_name(name)? };
+                        let op = WriteOpDatabase::NameObject(WriteOpDatabaseNameObject { kind: db::ObjectKind::try_from(family)?, obj_id, name });
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op }))
+                }
+                9 => {
+                        let op = ReadOpDatabase::LookupObject(ReadOpDatabaseLookupObject { name: db::parse_name(lv.val)? });
+                        Cmd::Read(ReadCmd::Database(ReadCmdDatabase { db_id, op }))
                 }
                 _ => {
-                        return Err(io::Error::new(io::ErrorKind::Other, "decode_db_cmd: unrecognized command"));
+                        return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_db_cmd: unrecognized command"));
                 }
         })
 }
@@ -391,38 +1856,130 @@ fn decode_db_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
 
 fn decode_ctl_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
         let cmd_type = tlv.cmd_type[1];
+        let val = &tlv.val;
         Ok(match cmd_type {
                 0 => { Cmd::Write(WriteCmd::Ctl(WriteCmdCtl { op: WriteOpCtl::WalReplay })) }
                 1 => { Cmd::Write(WriteCmd::Ctl(WriteCmdCtl { op: WriteOpCtl::LoadData })) }
+                10 => { Cmd::Write(WriteCmd::Ctl(WriteCmdCtl { op: WriteOpCtl::Checkpoint })) }
                 2 => { Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::WriteData })) }
-                _ => { return Err(io::Error::new(io::ErrorKind::Other, "decode_ctl_cmd: unrecognized command")); }
+                3 | 4 | 6 => {
+                        let Some((&db_id, name)) = val.split_first() else {
+                                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "decode_ctl_cmd: too few bytes in buffer"));
+                        };
+                        // The name following the id is optional
+                        let name = if name.is_empty() { None } else { Some(db::parse_name(name)?) };
+                        let op = match cmd_type {
+                                3 => WriteOpCtl::DropDatabase(WriteOpCtlDropDatabase { db_id }),
+                                4 => WriteOpCtl::CreateDatabase(WriteOpCtlCreateDatabase { db_id, name }),
+                                _ => WriteOpCtl::NameDatabase(WriteOpCtlNameDatabase { db_id, name: name.unwrap_or_default() }),
+                        };
+                        Cmd::Write(WriteCmd::Ctl(WriteCmdCtl { op }))
+                }
+                5 => { Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::ListDatabases(ReadOpCtlListDatabases) })) }
+                7 => {
+                        let op = ReadOpCtl::LookupDatabase(ReadOpCtlLookupDatabase { name: db::parse_name(val)? });
+                        Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op }))
+                }
+                8 => {
+                        let &[version] = &val[..] else {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, "decode_ctl_cmd: malformed handshake"));
+                        };
+                        Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::Handshake(ReadOpCtlHandshake { version }) }))
+                }
+                9 => { Cmd::Read(ReadCmd::Ctl(ReadCmdCtl { op: ReadOpCtl::Info(ReadOpCtlInfo) })) }
+                _ => { return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_ctl_cmd: unrecognized command")); }
         })
 }
 
 
-pub fn decode_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
+pub(crate) fn decode_cmd<'a>(tlv: &'a CmdTLV) -> io::Result<Cmd<'a>> {
         let cmd_type = tlv.cmd_type[0];
         Ok(match cmd_type {
                 1 => { decode_ctl_cmd(tlv)? }
                 2 => { decode_db_cmd(tlv)? }
                 3 => { decode_bf_cmd(tlv)? }
-                _ => { return Err(io::Error::new(io::ErrorKind::Other, "decode_cmd: unrecognized command")); }
+                4 => { decode_cbf_cmd(tlv)? }
+                5 => { decode_cf_cmd(tlv)? }
+                6 => { decode_hll_cmd(tlv)? }
+                7 => { decode_cms_cmd(tlv)? }
+                8 => { decode_topk_cmd(tlv)? }
+                _ => { return Err(io::Error::new(io::ErrorKind::Unsupported, "decode_cmd: unrecognized command")); }
         })
 }
 
 
-fn handle_write_cmd_ctl(cmd: &WriteCmdCtl, ctl: &mut ctl::Ctl, _resp: &mut CmdResponseTLV) -> io::Result<()> {
+// A data command addressing its object by name has the value
+//
+//   db_id (u8) | name_len (u8) | name | ...
+//
+// in place of the database and object ids, followed by what follows them
+// otherwise. The name is resolved to the frame of the same command
+// addressing the object by id, which is what is run and logged, so replay
+// doesn't depend on what names were bound to. Other frames are given back as
+// they are.
+pub(crate) fn resolve_name<'a>(frame: &'a [u8], ctl: &ctl::Ctl) -> Result<Cow<'a, [u8]>, CmdResponseTLV> {
+        let (Some(&family), Some(&op)) = (frame.first(), frame.get(1)) else {
+                return Ok(Cow::Borrowed(frame));
+        };
+        if op & proto::OP_BY_NAME == 0 || db::ObjectKind::try_from(family).is_err() {
+                return Ok(Cow::Borrowed(frame));
+        }
+        let tlv = CmdTLV::new(frame).map_err(|e| CmdResponseTLV::with_error(CmdError::from_decode_error(&e)).with_detail(e.to_string()))?;
+        let malformed = || CmdResponseTLV::with_error(CmdError::RequestBytesMalformed).with_detail("resolve_name: malformed object name");
+        let (&db_id, rest) = tlv.val.split_first().ok_or_else(malformed)?;
+        let (&len, rest) = rest.split_first().ok_or_else(malformed)?;
+        let name = rest.get(..len as usize).ok_or_else(malformed)?;
+        let name = db::parse_name(name).map_err(|_| malformed())?;
+
+        let Some(db) = ctl.db_registry.get(&[db_id]) else {
+                let mut resp = CmdResponseTLV::new();
+                resp.database_not_found(db_id);
+                return Err(resp);
+        };
+        let obj_id = match db.lookup(name) {
+                Some((kind, obj_id)) if kind.value() == family => obj_id,
+                Some((kind, _)) => {
+                        return Err(CmdResponseTLV::with_error(CmdError::NameNotFound).with_detail(format!("{name} in database {db_id} is a {}", kind.name())));
+                }
+                None => {
+                        return Err(CmdResponseTLV::with_error(CmdError::NameNotFound).with_detail(format!("no object named {name} in database {db_id}")));
+                }
+        };
+
+        let val = [&[db_id, obj_id], &rest[len as usize..]].concat();
+        let mut resolved = vec![family, op & !proto::OP_BY_NAME];
+        resolved.extend(&frame[2..4]);
+        resolved.extend(u32::try_from(val.len()).unwrap().to_le_bytes());
+        resolved.extend(val);
+        Ok(Cow::Owned(resolved))
+}
+
+
+fn handle_write_cmd_ctl(cmd: &WriteCmdCtl, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         match &cmd.op {
-                WriteOpCtl::WalReplay => { ctl.replay_logging_data()?; }
+                WriteOpCtl::WalReplay => {
+                        // Replaying onto the live state would apply the logged
+                        // commands a second time, where reloading the data
+                        // replays them onto the snapshot
+                        resp.init_error_detail(CmdError::InvalidParameters, "the write-ahead log is replayed only on load; reload the data instead");
+                }
                 WriteOpCtl::LoadData => { ctl.load_from_storage()?; }
+                WriteOpCtl::Checkpoint => { ctl.checkpoint()?; }
+                WriteOpCtl::CreateDatabase(op) => { op.execute(ctl, resp)?; }
+                WriteOpCtl::DropDatabase(op) => { op.execute(ctl, resp); }
+                WriteOpCtl::NameDatabase(op) => { op.execute(ctl, resp); }
         }
         Ok(())
 }
 
 
-fn handle_read_cmd_ctl(cmd: &ReadCmdCtl, ctl: &ctl::Ctl, _resp: &mut CmdResponseTLV) -> io::Result<()> {
+fn handle_read_cmd_ctl(cmd: &ReadCmdCtl, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         match &cmd.op {
                 ReadOpCtl::WriteData => { ctl.write_to_storage()?; }
+                ReadOpCtl::ListDatabases(op) => { op.execute(ctl, resp); }
+                ReadOpCtl::LookupDatabase(op) => { op.execute(ctl, resp); }
+                ReadOpCtl::Handshake(op) => { op.execute(resp); }
+                ReadOpCtl::Info(op) => { op.execute(ctl, resp); }
         }
         Ok(())
 }
@@ -430,66 +1987,286 @@ fn handle_read_cmd_ctl(cmd: &ReadCmdCtl, ctl: &ctl::Ctl, _resp: &mut CmdResponse
 
 fn handle_read_cmd_bf(cmd: &ReadCmdBloomFilter, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
-                if let Some(bf) = db.bf_registry.get(&[cmd.bf_id]).as_ref() {
+                // Describing a filter depends on whether it's scalable
+                if let ReadOpBloomFilter::Info(op) = &cmd.op {
+                        op.execute(db, cmd.bf_id, resp);
+                        return Ok(());
+                }
+                if let Some(bf) = db.bloom_filter(cmd.bf_id) {
                         match &cmd.op {
                                 ReadOpBloomFilter::Has(op) => { op.execute(bf, resp)?; }
                                 ReadOpBloomFilter::HasBatch(op) => { op.execute(bf, resp)?; }
+                                ReadOpBloomFilter::Info(_) => {}
                         }
                         return Ok(());
                 }
+                resp.object_not_found(db::ObjectKind::BloomFilter, cmd.db_id, cmd.bf_id);
+                return Ok(());
         }
-        resp.init_error_response(CmdError::ObjectNotFound);
+        resp.database_not_found(cmd.db_id);
         Ok(())
 }
 
 
 fn handle_write_cmd_bf(cmd: &WriteCmdBloomFilter, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
-                if let Some(bf) = db.bf_registry.get_mut(&[cmd.bf_id]).as_mut() {
+                if let Some(bf) = db.bloom_filter_mut(cmd.bf_id) {
                         match &cmd.op {
                                 WriteOpBloomFilter::Add(op) => { op.execute(bf, resp)?; }
                                 WriteOpBloomFilter::AddBatch(op) => { op.execute(bf, resp)?; }
                         }
                         return Ok(());
                 }
+                resp.object_not_found(db::ObjectKind::BloomFilter, cmd.db_id, cmd.bf_id);
+                return Ok(());
         }
-        resp.init_error_response(CmdError::ObjectNotFound);
+        resp.database_not_found(cmd.db_id);
         Ok(())
 }
 
 
+fn handle_read_cmd_cbf(cmd: &ReadCmdCountingBloomFilter, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                if let Some(cbf) = db.cbf_registry.get(&[cmd.cbf_id]).as_ref() {
+                        match &cmd.op {
+                                ReadOpCountingBloomFilter::Has(op) => { op.execute(cbf, resp)?; }
+                        }
+                        return Ok(());
+                }
+                resp.object_not_found(db::ObjectKind::CountingBloomFilter, cmd.db_id, cmd.cbf_id);
+                return Ok(());
+        }
+        resp.database_not_found(cmd.db_id);
+        Ok(())
+}
+
+
+fn handle_write_cmd_cbf(cmd: &WriteCmdCountingBloomFilter, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+        if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                if let Some(cbf) = db.cbf_registry.get_mut(&[cmd.cbf_id]).as_mut() {
+                        match &cmd.op {
+                                WriteOpCountingBloomFilter::Add(op) => { op.execute(cbf, resp)?; }
+                                WriteOpCountingBloomFilter::Remove(op) => { op.execute(cbf, resp)?; }
+                        }
+                        return Ok(());
+                }
+                resp.object_not_found(db::ObjectKind::CountingBloomFilter, cmd.db_id, cmd.cbf_id);
+                return Ok(());
+        }
+        resp.database_not_found(cmd.db_id);
+        Ok(())
+}
+
+
+fn handle_read_cmd_cf(cmd: &ReadCmdCuckooFilter, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                if let Some(cf) = db.cf_registry.get(&[cmd.cf_id]).as_ref() {
+                        match &cmd.op {
+                                ReadOpCuckooFilter::Has(op) => { op.execute(cf, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::CuckooFilter, cmd.db_id, cmd.cf_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_write_cmd_cf(cmd: &WriteCmdCuckooFilter, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                if let Some(cf) = db.cf_registry.get_mut(&[cmd.cf_id]).as_mut() {
+                        match &cmd.op {
+                                WriteOpCuckooFilter::Add(op) => { op.execute(cf, resp); }
+                                WriteOpCuckooFilter::Delete(op) => { op.execute(cf, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::CuckooFilter, cmd.db_id, cmd.cf_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_read_cmd_hll(cmd: &ReadCmdHyperLogLog, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                if let Some(hll) = db.hll_registry.get(&[cmd.hll_id]).as_ref() {
+                        match &cmd.op {
+                                ReadOpHyperLogLog::Count(op) => { op.execute(hll, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::HyperLogLog, cmd.db_id, cmd.hll_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_write_cmd_hll(cmd: &WriteCmdHyperLogLog, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+        if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                // Merging reads other sketches of the database besides the target
+                if let WriteOpHyperLogLog::Merge(op) = &cmd.op {
+                        return op.execute(db, cmd.hll_id, resp);
+                }
+                if let Some(hll) = db.hll_registry.get_mut(&[cmd.hll_id]).as_mut() {
+                        match &cmd.op {
+                                WriteOpHyperLogLog::Add(op) => { op.execute(hll, resp); }
+                                WriteOpHyperLogLog::AddBatch(op) => { op.execute(hll, resp); }
+                                WriteOpHyperLogLog::Merge(_) => {}
+                        }
+                        return Ok(());
+                }
+                resp.object_not_found(db::ObjectKind::HyperLogLog, cmd.db_id, cmd.hll_id);
+                return Ok(());
+        }
+        resp.database_not_found(cmd.db_id);
+        Ok(())
+}
+
+
+fn handle_read_cmd_cms(cmd: &ReadCmdCountMinSketch, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                if let Some(cms) = db.cms_registry.get(&[cmd.cms_id]).as_ref() {
+                        match &cmd.op {
+                                ReadOpCountMinSketch::Query(op) => { op.execute(cms, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::CountMinSketch, cmd.db_id, cmd.cms_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_write_cmd_cms(cmd: &WriteCmdCountMinSketch, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                if let Some(cms) = db.cms_registry.get_mut(&[cmd.cms_id]).as_mut() {
+                        match &cmd.op {
+                                WriteOpCountMinSketch::Incr(op) => { op.execute(cms, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::CountMinSketch, cmd.db_id, cmd.cms_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_read_cmd_topk(cmd: &ReadCmdTopK, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                if let Some(topk) = db.topk_registry.get(&[cmd.topk_id]).as_ref() {
+                        match &cmd.op {
+                                ReadOpTopK::Query(op) => { op.execute(topk, resp); }
+                                ReadOpTopK::Count(op) => { op.execute(topk, resp); }
+                                ReadOpTopK::List(op) => { op.execute(topk, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::TopK, cmd.db_id, cmd.topk_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_write_cmd_topk(cmd: &WriteCmdTopK, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                if let Some(topk) = db.topk_registry.get_mut(&[cmd.topk_id]).as_mut() {
+                        match &cmd.op {
+                                WriteOpTopK::Add(op) => { op.execute(topk, resp); }
+                        }
+                        return;
+                }
+                resp.object_not_found(db::ObjectKind::TopK, cmd.db_id, cmd.topk_id);
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
+fn handle_read_cmd_db(cmd: &ReadCmdDatabase, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) {
+        if let Some(db) = ctl.db_registry.get(&[cmd.db_id]) {
+                match &cmd.op {
+                        ReadOpDatabase::LookupObject(op) => { op.execute(db, resp); }
+                        ReadOpDatabase::ListObjects(op) => { op.execute(db, resp); }
+                }
+                return;
+        }
+        resp.database_not_found(cmd.db_id);
+}
+
+
 fn handle_write_cmd_db(cmd: &WriteCmdDatabase, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+        let max_sz = ctl.config().max_object_sz;
         if let Some(db) = ctl.db_registry.get_mut(&[cmd.db_id]) {
+                if let Some(name) = cmd.name.filter(|name| db.lookup(name).is_some()) {
+                        resp.init_error_detail(CmdError::ObjectExists, format!("an object named {name} exists in database {}", db.id));
+                        return Ok(())
+                }
+                if let Some(sz) = cmd.op.new_object_sz().filter(|sz| max_sz > 0 && *sz > max_sz) {
+                        resp.init_error_detail(CmdError::InvalidParameters, format!("object of {sz} bytes exceeds the limit of {max_sz}"));
+                        return Ok(())
+                }
                 match &cmd.op {
                         WriteOpDatabase::NewBloomFilter(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewCountingBloomFilter(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewScalableBloomFilter(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewCuckooFilter(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewHyperLogLog(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewCountMinSketch(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::NewTopK(op) => { op.execute(db, resp)?; }
+                        WriteOpDatabase::DropObject(op) => { op.execute(db, resp); }
+                        WriteOpDatabase::NameObject(op) => { op.execute(db, resp); }
+                }
+                if let (Some(name), Some((kind, obj_id)), CmdResponseCode::Success) = (cmd.name, cmd.op.new_object(), resp.status()) {
+                        db.set_name(kind, obj_id, name);
                 }
                 return Ok(())
         }
-        resp.init_error_response(CmdError::ObjectNotFound);
+        resp.database_not_found(cmd.db_id);
         Ok(())
 }
 
 
-pub fn dispatch_read_cmd(cmd: &ReadCmd, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+pub(crate) fn dispatch_read_cmd(cmd: &ReadCmd, ctl: &ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         match cmd {
                 ReadCmd::BloomFilter(cmd_bf) => { handle_read_cmd_bf(cmd_bf, ctl, resp)?; }
+                ReadCmd::CountingBloomFilter(cmd_cbf) => { handle_read_cmd_cbf(cmd_cbf, ctl, resp)?; }
+                ReadCmd::CuckooFilter(cmd_cf) => { handle_read_cmd_cf(cmd_cf, ctl, resp); }
+                ReadCmd::HyperLogLog(cmd_hll) => { handle_read_cmd_hll(cmd_hll, ctl, resp); }
+                ReadCmd::CountMinSketch(cmd_cms) => { handle_read_cmd_cms(cmd_cms, ctl, resp); }
+                ReadCmd::TopK(cmd_topk) => { handle_read_cmd_topk(cmd_topk, ctl, resp); }
+                ReadCmd::Database(cmd_db) => { handle_read_cmd_db(cmd_db, ctl, resp); }
                 ReadCmd::Ctl(cmd_ctl) => { handle_read_cmd_ctl(cmd_ctl, ctl, resp)?; }
         }
         Ok(())
 }
 
 
-pub fn dispatch_write_cmd(cmd: &WriteCmd, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
+pub(crate) fn dispatch_write_cmd(cmd: &WriteCmd, ctl: &mut ctl::Ctl, resp: &mut CmdResponseTLV) -> io::Result<()> {
         match cmd {
                 WriteCmd::Ctl(cmd_ctl) => { handle_write_cmd_ctl(cmd_ctl, ctl, resp)?; }
                 WriteCmd::Database(cmd_db) => { handle_write_cmd_db(cmd_db, ctl, resp)?; }
                 WriteCmd::BloomFilter(cmd_bf) => { handle_write_cmd_bf(cmd_bf, ctl, resp)?; }
+                WriteCmd::CountingBloomFilter(cmd_cbf) => { handle_write_cmd_cbf(cmd_cbf, ctl, resp)?; }
+                WriteCmd::CuckooFilter(cmd_cf) => { handle_write_cmd_cf(cmd_cf, ctl, resp); }
+                WriteCmd::HyperLogLog(cmd_hll) => { handle_write_cmd_hll(cmd_hll, ctl, resp)?; }
+                WriteCmd::CountMinSketch(cmd_cms) => { handle_write_cmd_cms(cmd_cms, ctl, resp); }
+                WriteCmd::TopK(cmd_topk) => { handle_write_cmd_topk(cmd_topk, ctl, resp); }
         }
         Ok(())
 }
 
 
-pub async fn dispatch_cmd(ctl_rc: &Rc<RefCell<ctl::Ctl>>, cmd: &Cmd<'_>, resp: &mut CmdResponseTLV ) -> io::Result<()> {
+pub(crate) async fn dispatch_cmd(ctl_rc: &Rc<RefCell<ctl::Ctl>>, cmd: &Cmd<'_>, resp: &mut CmdResponseTLV ) -> io::Result<()> {
+        if cmd.is_write() && ctl_rc.try_borrow().is_ok_and(|ctl| ctl.config().read_only) {
+                resp.init_error_detail(CmdError::ReadOnly, "the server is read-only");
+                return Ok(());
+        }
         match &cmd {
                 Cmd::Read(read_cmd) => {
                         let ctl_guard = match ctl_rc.try_borrow () {
@@ -499,7 +2276,9 @@ pub async fn dispatch_cmd(ctl_rc: &Rc<RefCell<ctl::Ctl>>, cmd: &Cmd<'_>, resp: &
                                         return Ok(());
                                 }
                         };
-                        dispatch_read_cmd(read_cmd, &ctl_guard, resp)?;
+                        if let Err(e) = dispatch_read_cmd(read_cmd, &ctl_guard, resp) {
+                                resp.init_error_detail(CmdError::InternalError, e.to_string());
+                        }
                 }
                 Cmd::Write(write_cmd) => {
                         let mut ctl_guard = match ctl_rc.try_borrow_mut () {
@@ -509,7 +2288,9 @@ pub async fn dispatch_cmd(ctl_rc: &Rc<RefCell<ctl::Ctl>>, cmd: &Cmd<'_>, resp: &
                                         return Ok(());
                                 }
                         };
-                        dispatch_write_cmd(write_cmd, &mut ctl_guard, resp)?;
+                        if let Err(e) = dispatch_write_cmd(write_cmd, &mut ctl_guard, resp) {
+                                resp.init_error_detail(CmdError::InternalError, e.to_string());
+                        }
                 }
         }
         Ok(())
@@ -532,13 +2313,179 @@ mod tests {
                 let tlv = CmdTLV::new(inbytes).unwrap();
                 let cmd = decode_cmd(&tlv).unwrap();
                 match cmd {
-                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id })})) => {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id, .. })})) => {
                         }
                         _ => { assert!(false) }
                 }
                 let mut resp = CmdResponseTLV::new();
                 let _ = dispatch_cmd(&ctl_rc, &cmd, &mut resp).await;
+                assert!(matches!(resp.rc, CmdResponseCode::Error(CmdError::DatabaseNotFound)));
+                assert!(resp.detail() == "no database 0");
+
+                // The log is replayed on load, never onto the live state
+                let inbytes: &[u8] = &[1, 0, 255, 255, 0, 0, 0, 0];
+                let mut resp = CmdResponseTLV::new();
+                dispatch_cmd(&ctl_rc, &decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), &mut resp).await.unwrap();
+                assert!(matches!(resp.rc, CmdResponseCode::Error(CmdError::InvalidParameters)));
+        }
+
+        #[tokio::test]
+        async fn test_error_detail() {
+                let conf = cfg::Config { read_only: true, ..cfg::Config::new("test") };
+                let mut ctl = ctl::Ctl::new_blank(conf).unwrap();
+                ctl.create_database(0, None).unwrap();
+                let ctl_rc = Rc::new(RefCell::new(ctl));
+
+                let inbytes: &[u8] = &[3, 2, 255, 255, 4, 0, 0, 0, 0, 1, 1, 7];
+                let mut resp = CmdResponseTLV::new();
+                dispatch_cmd(&ctl_rc, &decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), &mut resp).await.unwrap();
                 assert!(matches!(resp.rc, CmdResponseCode::Error(CmdError::ObjectNotFound)));
+                // The detail goes only in the second version of the protocol
+                let mut outbytes = Vec::new();
+                resp.encode_into(&mut outbytes, proto::PROTOCOL_V1);
+                assert!(outbytes == [11, 255]);
+                let detail = b"no bloom filter 1 in database 0";
+                outbytes.clear();
+                resp.encode_into(&mut outbytes, proto::PROTOCOL_V2);
+                assert!(outbytes[..8] == [11, 255, 255, 255, detail.len() as u8, 0, 0, 0]);
+                assert!(outbytes[8..] == *detail);
+
+                // Changes to the state are turned away in read-only mode
+                let inbytes: &[u8] = &[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 1];
+                let mut resp = CmdResponseTLV::new();
+                dispatch_cmd(&ctl_rc, &decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), &mut resp).await.unwrap();
+                assert!(matches!(resp.rc, CmdResponseCode::Error(CmdError::ReadOnly)));
+
+                // And so are those that only touch the files backing it
+                for op in [0, 1, 2, 10] {
+                        let inbytes: &[u8] = &[1, op, 255, 255, 0, 0, 0, 0];
+                        let mut resp = CmdResponseTLV::new();
+                        dispatch_cmd(&ctl_rc, &decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), &mut resp).await.unwrap();
+                        assert!(matches!(resp.rc, CmdResponseCode::Error(CmdError::ReadOnly)));
+                }
+
+                let inbytes: &[u8] = &[2, 4, 255, 255, 4, 0, 0, 0, 0, 2, 1, 20];
+                let e = decode_cmd(&CmdTLV::new(inbytes).unwrap()).err().unwrap();
+                assert!(CmdError::from_decode_error(&e) == CmdError::InvalidParameters);
+
+                let resp = CmdResponseTLV::with_error(CmdError::InternalError).with_detail("é".repeat(200));
+                let mut outbytes = Vec::new();
+                resp.encode_into(&mut outbytes, proto::PROTOCOL_V1);
+                assert!(outbytes == [12, 255]);
+        }
+
+        #[tokio::test]
+        async fn test_info() {
+                let conf = cfg::Config { max_frame_sz: 4096, ..cfg::Config::new("test") };
+                let ctl_rc = Rc::new(RefCell::new(ctl::Ctl::new_blank(conf).unwrap()));
+
+                let inbytes: &[u8] = &[1, 9, 255, 255, 0, 0, 0, 0];
+                let mut resp = CmdResponseTLV::new();
+                dispatch_cmd(&ctl_rc, &decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), &mut resp).await.unwrap();
+                let server_version = env!("CARGO_PKG_VERSION").as_bytes();
+                let mut expected = vec![proto::PROTOCOL_V1, proto::PROTOCOL_VERSION, 6, 3, 4, 5, 6, 7, 8, 0, 16, 0, 0];
+                expected.push(server_version.len() as u8);
+                expected.extend(server_version);
+                assert!(resp.val == expected);
+        }
+
+        #[test]
+        fn test_names() {
+                let frame = |family: u8, op: u8, val: &[u8]| [&[family, op, 255, 255], &(val.len() as u32).to_le_bytes()[..], val].concat();
+                let run = |ctl: &mut ctl::Ctl, frame: &[u8]| -> CmdResponseTLV {
+                        let frame = match resolve_name(frame, ctl) {
+                                Ok(frame) => frame,
+                                Err(resp) => { return resp; }
+                        };
+                        let tlv = CmdTLV::new(&frame).unwrap();
+                        let mut resp = CmdResponseTLV::new();
+                        match decode_cmd(&tlv).unwrap() {
+                                Cmd::Write(cmd) => { dispatch_write_cmd(&cmd, ctl, &mut resp).unwrap(); }
+                                Cmd::Read(cmd) => { dispatch_read_cmd(&cmd, ctl, &mut resp).unwrap(); }
+                        }
+                        resp
+                };
+                let mut ctl = ctl::Ctl::new_blank(cfg::Config::new("test")).unwrap();
+                ctl.create_database(0, None).unwrap();
+
+                // A creation names the new object in the same command
+                let resp = run(&mut ctl, &frame(2, 0, b"\x00\x01\x01users"));
+                assert!(matches!(resp.status(), CmdResponseCode::Success));
+                let resp = run(&mut ctl, &frame(2, 2, b"\x00\x01\x02users"));
+                assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::ObjectExists)));
+                assert!(!ctl.db_registry.get(&[0]).unwrap().has_bloom_filter(2));
+
+                // A data command addresses the object by name, and runs as
+                // the command addressing it by id
+                let add = frame(3, proto::OP_BY_NAME, b"\x00\x05users\x01a");
+                assert!(resolve_name(&add, &ctl).is_ok_and(|resolved| resolved[..] == frame(3, 0, b"\x00\x01\x01a")));
+                assert!(matches!(run(&mut ctl, &add).status(), CmdResponseCode::Success));
+                assert!(run(&mut ctl, &frame(3, 2, b"\x00\x01\x01a")).val == [proto::TOKEN_TRUE]);
+                let resp = run(&mut ctl, &frame(3, 2 | proto::OP_BY_NAME, b"\x00\x05userz\x01a"));
+                assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::NameNotFound)));
+                let resp = run(&mut ctl, &frame(4, 2 | proto::OP_BY_NAME, b"\x00\x05users\x01a"));
+                assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::NameNotFound)));
+                let resp = run(&mut ctl, &frame(3, 2 | proto::OP_BY_NAME, b"\x00\x09users"));
+                assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::RequestBytesMalformed)));
+        }
+
+        #[test]
+        fn test_object_size() {
+                let conf = cfg::Config { max_object_sz: 1 << 16, ..cfg::Config::new("test") };
+                let mut ctl = ctl::Ctl::new_blank(conf).unwrap();
+                ctl.create_database(0, None).unwrap();
+                let create = |ctl: &mut ctl::Ctl, op: u8, spec: &[u8]| -> CmdResponseTLV {
+                        let val = [&[0, spec.len() as u8], spec].concat();
+                        let frame = [&[2, op, 255, 255], &(val.len() as u32).to_le_bytes()[..], &val].concat();
+                        let mut resp = CmdResponseTLV::new();
+                        if let Cmd::Write(cmd) = decode_cmd(&CmdTLV::new(&frame).unwrap()).unwrap() {
+                                dispatch_write_cmd(&cmd, ctl, &mut resp).unwrap();
+                        }
+                        resp
+                };
+
+                // A filter sized past the limit is refused, one at it isn't
+                let bf = |bf_id: u8, bit_cnt: u64| [&[bf_id, proto::PARAMS_EXPLICIT], &bit_cnt.to_le_bytes()[..], &[3]].concat();
+                let resp = create(&mut ctl, 0, &bf(1, (8 << 16) + 1));
+                assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::InvalidParameters)));
+                assert!(resp.detail() == "object of 65537 bytes exceeds the limit of 65536");
+                assert!(!ctl.db_registry.get(&[0]).unwrap().has_bloom_filter(1));
+                assert!(matches!(create(&mut ctl, 0, &bf(1, 8 << 16)).status(), CmdResponseCode::Success));
+
+                // A cuckoo filter takes two bytes a slot, in a power of two
+                // of buckets of four slots
+                let cf = |cf_id: u8, cpty: u64| [&[cf_id], &cpty.to_le_bytes()[..], &[8, 4]].concat();
+                let resp = create(&mut ctl, 3, &cf(2, 40_000));
+                assert!(resp.detail() == "object of 131072 bytes exceeds the limit of 65536");
+                assert!(matches!(create(&mut ctl, 3, &cf(2, 31_000)).status(), CmdResponseCode::Success));
+        }
+
+        #[test]
+        fn test_malformed_batch() {
+                let run = |ctl: &mut ctl::Ctl, family: u8, op: u8, val: &[u8]| -> CmdResponseTLV {
+                        let frame = [&[family, op, 255, 255], &(val.len() as u32).to_le_bytes()[..], val].concat();
+                        let tlv = CmdTLV::new(&frame).unwrap();
+                        let mut resp = CmdResponseTLV::new();
+                        match decode_cmd(&tlv).unwrap() {
+                                Cmd::Write(cmd) => { dispatch_write_cmd(&cmd, ctl, &mut resp).unwrap(); }
+                                Cmd::Read(cmd) => { dispatch_read_cmd(&cmd, ctl, &mut resp).unwrap(); }
+                        }
+                        resp
+                };
+                let mut ctl = ctl::Ctl::new_blank(cfg::Config::new("test")).unwrap();
+                ctl.create_database(0, None).unwrap();
+                assert!(matches!(run(&mut ctl, 2, 0, b"\x00\x01\x01").status(), CmdResponseCode::Success));
+                assert!(matches!(run(&mut ctl, 2, 4, b"\x00\x01\x01").status(), CmdResponseCode::Success));
+
+                // A batch whose last element runs past its end is refused
+                // before any of the elements ahead of it are added
+                let batch = b"\x00\x01\x06\x01a\x01b\x05c";
+                for family in [3, 6] {
+                        let resp = run(&mut ctl, family, 1, batch);
+                        assert!(matches!(resp.status(), CmdResponseCode::Error(CmdError::RequestBytesMalformed)));
+                }
+                assert!(run(&mut ctl, 3, 3, b"\x00\x01\x04\x01a\x01b").val == [proto::TOKEN_FALSE; 2]);
+                assert!(run(&mut ctl, 6, 2, b"\x00\x01").val == 0u64.to_le_bytes());
         }
 
         #[test]
@@ -563,9 +2510,102 @@ mod tests {
 
                 let inbytes: &[u8] = &[2, 0, 255, 255, 3, 0, 0, 0, 1, 1, 3];
                 match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
-                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id })})) => {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id, params })})) => {
+                                assert!(db_id == 1);
+                                assert!(bf_id == 3);
+                                assert!(params.is_none());
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[2, 0, 255, 255, 13, 0, 0, 0, 1, 11, 3, 0, 64, 0, 0, 0, 0, 0, 0, 0, 7];
+                match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { bf_id, params })})) => {
                                 assert!(db_id == 1);
                                 assert!(bf_id == 3);
+                                assert!(params == BloomFilterParams::new(64, 7));
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let mut inbytes = vec![2, 0, 255, 255, 20, 0, 0, 0, 1, 18, 3, 1];
+                inbytes.extend(1000u64.to_le_bytes());
+                inbytes.extend(0.01f64.to_le_bytes());
+                match decode_cmd(&CmdTLV::new(&inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { op: WriteOpDatabase::NewBloomFilter(WriteOpDatabaseNewBloomFilter { params: Some(params), .. }), .. })) => {
+                                assert!(params.bit_cnt == 9586, "{}", params.bit_cnt);
+                                assert!(params.hfn_cnt == 7, "{}", params.hfn_cnt);
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[2, 0, 255, 255, 13, 0, 0, 0, 1, 11, 3, 0, 64, 0, 0, 0, 0, 0, 0, 0, 1];
+                assert!(decode_cmd(&CmdTLV::new(inbytes).unwrap()).is_err());
+
+                let inbytes: &[u8] = &[2, 1, 255, 255, 4, 0, 0, 0, 0, 2, 5, 8];
+                match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op: WriteOpDatabase::NewCountingBloomFilter(WriteOpDatabaseNewCountingBloomFilter { cbf_id, width, params })})) => {
+                                assert!(db_id == 0);
+                                assert!(cbf_id == 5);
+                                assert!(width == CounterWidth::Bits8);
+                                assert!(params.is_none());
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[4, 1, 255, 255, 5, 0, 0, 0, 0, 5, 2, 42, 43];
+                match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::CountingBloomFilter(WriteCmdCountingBloomFilter { db_id, cbf_id, op: WriteOpCountingBloomFilter::Remove(WriteOpCountingBloomFilterRemove { elt })})) => {
+                                assert!(db_id == 0);
+                                assert!(cbf_id == 5);
+                                assert!(elt == &[42, 43]);
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[2, 4, 255, 255, 4, 0, 0, 0, 1, 2, 9, 12];
+                match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { db_id, name: None, op: WriteOpDatabase::NewHyperLogLog(WriteOpDatabaseNewHyperLogLog { hll_id, precision })})) => {
+                                assert!(db_id == 1);
+                                assert!(hll_id == 9);
+                                assert!(precision == 12);
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[2, 4, 255, 255, 4, 0, 0, 0, 1, 2, 9, 3];
+                assert!(decode_cmd(&CmdTLV::new(inbytes).unwrap()).is_err());
+
+                let inbytes: &[u8] = &[6, 3, 255, 255, 5, 0, 0, 0, 1, 9, 2, 7, 8];
+                match decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::HyperLogLog(WriteCmdHyperLogLog { db_id, hll_id, op: WriteOpHyperLogLog::Merge(WriteOpHyperLogLogMerge { src_ids })})) => {
+                                assert!(db_id == 1);
+                                assert!(hll_id == 9);
+                                assert!(src_ids == &[7, 8]);
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let mut inbytes = vec![2, 5, 255, 255, 21, 0, 0, 0, 0, 19, 4, 1, 1];
+                inbytes.extend(0.01f64.to_le_bytes());
+                inbytes.extend(0.001f64.to_le_bytes());
+                match decode_cmd(&CmdTLV::new(&inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::Database(WriteCmdDatabase { op: WriteOpDatabase::NewCountMinSketch(WriteOpDatabaseNewCountMinSketch { cms_id, conservative, params: Some(params) }), .. })) => {
+                                assert!(cms_id == 4);
+                                assert!(conservative);
+                                assert!(params.width == 272 && params.depth == 7, "{params:?}");
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let mut inbytes = vec![7, 0, 255, 255, 13, 0, 0, 0, 1, 4, 2, 5, 6];
+                inbytes.extend(3u64.to_le_bytes());
+                match decode_cmd(&CmdTLV::new(&inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::CountMinSketch(WriteCmdCountMinSketch { db_id, cms_id, op: WriteOpCountMinSketch::Incr(WriteOpCountMinSketchIncr { elt, n })})) => {
+                                assert!(db_id == 1);
+                                assert!(cms_id == 4);
+                                assert!(elt == &[5, 6]);
+                                assert!(n == 3);
                         }
                         _ => { assert!(false) }
                 }
@@ -609,5 +2649,57 @@ mod tests {
                         }
                         _ => { assert!(false) }
                 }
+
+                let mut inbytes = vec![8, 0, 255, 255, 13, 0, 0, 0, 0, 2, 2, 7, 8];
+                inbytes.extend(5u64.to_le_bytes());
+                match decode_cmd(&CmdTLV::new(&inbytes).unwrap()).unwrap() {
+                        Cmd::Write(WriteCmd::TopK(WriteCmdTopK { db_id, topk_id, op: WriteOpTopK::Add(WriteOpTopKAdd { elt, n })})) => {
+                                assert!(db_id == 0);
+                                assert!(topk_id == 2);
+                                assert!(elt == &[7, 8]);
+                                assert!(n == 5);
+                        }
+                        _ => { assert!(false) }
+                }
+
+                let inbytes: &[u8] = &[8, 3, 255, 255, 2, 0, 0, 0, 0, 2];
+                assert!(matches!(decode_cmd(&CmdTLV::new(inbytes).unwrap()).unwrap(), Cmd::Read(ReadCmd::TopK(ReadCmdTopK { op: ReadOpTopK::List(_), .. }))));
+
+                let inbytes: &[u8] = &[2, 6, 255, 255, 7, 0, 0, 0, 0, 5, 2, 0, 0, 0, 0];
+                assert!(decode_cmd(&CmdTLV::new(inbytes).unwrap()).is_err());
+        }
+
+        #[test]
+        fn test_decode_errors() {
+                let decode_err = |inbytes: &[u8]| {
+                        let e = CmdTLV::new(inbytes).and_then(|tlv| decode_cmd(&tlv).map(|_| ())).unwrap_err();
+                        CmdError::from_decode_error(&e)
+                };
+                assert!(decode_err(&[9, 0, 255, 255, 0, 0, 0, 0]) == CmdError::UnknownCommand);
+                assert!(decode_err(&[3, 9, 255, 255, 4, 0, 0, 0, 0, 1, 1, 7]) == CmdError::UnknownCommand);
+                assert!(decode_err(&[1, 0, 255]) == CmdError::TruncatedFrame);
+                // The LV claims more bytes than the frame carries
+                assert!(decode_err(&[3, 0, 255, 255, 4, 0, 0, 0, 0, 1, 9, 7]) == CmdError::TruncatedFrame);
+                assert!(decode_err(&[2, 0, 255, 255, 2, 0, 0, 0, 0, 0, 0]) == CmdError::TruncatedFrame);
+        }
+
+        #[test]
+        fn test_topk_list() {
+                let mut topks = TopKStructure::new(1, 0, 2);
+                let mut resp = CmdResponseTLV::new();
+                WriteOpTopKAdd { elt: b"ab", n: 3 }.execute(&mut topks, &mut resp);
+                WriteOpTopKAdd { elt: b"c", n: 1 }.execute(&mut topks, &mut resp);
+                assert!(resp.val.is_empty());
+                WriteOpTopKAdd { elt: b"d", n: 1 }.execute(&mut topks, &mut resp);
+                assert!(resp.val == [1, 0, 0, 0, b'c']);
+
+                let mut resp = CmdResponseTLV::new();
+                ReadOpTopKList.execute(&topks, &mut resp);
+                let mut expected = vec![2, 0, 0, 0];
+                expected.extend([2, 0, 0, 0, b'a', b'b']);
+                expected.extend(3u64.to_le_bytes());
+                expected.extend([1, 0, 0, 0, b'd']);
+                expected.extend(2u64.to_le_bytes());
+                assert!(resp.val == expected);
         }
 }
\ No newline at end of file
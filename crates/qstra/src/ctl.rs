@@ -5,10 +5,18 @@
 //! Define the main control structure and its bridges to the filesystem.
 
 
+use std::ffi::OsString;
 use std::fs;
 use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 use qstra_prob::bf::BloomFilterStructure;
+use qstra_prob::cbf::CountingBloomFilterStructure;
+use qstra_prob::cf::CuckooFilterStructure;
+use qstra_prob::cms::CountMinSketchStructure;
+use qstra_prob::hll::HyperLogLogStructure;
+use qstra_prob::sbf::ScalableBloomFilterStructure;
+use qstra_prob::topk::TopKStructure;
 use qstra_stor::srl::{self, Deserializable, Serializable};
 
 use crate::cfg;
@@ -22,6 +30,9 @@ pub struct Ctl {
         pub db_registry: reg::Registry<db::Database>,
         cfg: cfg::Config,
         wal: wal::WriteAheadLog,
+        // The sequence number of the first log record the snapshot loaded or
+        // last checkpointed doesn't cover
+        snapshot_seq: u64,
 }
 
 
@@ -41,12 +52,19 @@ impl Ctl {
                         db_registry: reg::Registry::<db::Database>::new_blank(),
                         cfg: conf,
                         wal,
+                        snapshot_seq: 0,
                 })
         }
 
+        #[must_use]
+        pub fn snapshot_seq(&self) -> u64 {
+                self.snapshot_seq
+        }
+
         fn clear_state(&mut self) {
                 self.curr_db = 0;
                 self.db_registry.clear_state();
+                self.snapshot_seq = 0;
         }
 
         pub fn load_from_storage(&mut self) -> io::Result<()> {
@@ -61,10 +79,8 @@ impl Ctl {
                         self.init()?;
                         return Err(io::Error::new(io::ErrorKind::Other, msg));
                 }
-                if buf.is_empty() {
-                        self.init()?;
-                        return Ok(());
-                }
+                // Nothing may have been saved yet, in which case the state is
+                // what the log holds
                 self.load_state(&mut buf)?;
                 Ok(())
         }
@@ -76,22 +92,85 @@ impl Ctl {
                 Ok(())
         }
 
+        /// Create an empty database. Returns false if the id or the name
+        /// is taken.
+        pub fn create_database(&mut self, id: u8, name: Option<&str>) -> io::Result<bool> {
+                if self.db_registry.get(&[id]).is_some() || name.is_some_and(|name| self.lookup_database(name).is_some()) {
+                        return Ok(false);
+                }
+                let mut db = db::Database::new(id);
+                db.name = name.map(str::to_owned);
+                self.db_registry.add(db, &[id])?;
+                Ok(true)
+        }
+
+        pub fn lookup_database(&self, name: &str) -> Option<u8> {
+                self.db_registry.list().iter()
+                        .find(|db| db.name.as_deref() == Some(name))
+                        .map(|db| db.id)
+        }
+
+        /// Drop a database along with every object in it. Returns false if
+        /// there was no such database.
+        pub fn drop_database(&mut self, id: u8) -> bool {
+                if self.db_registry.remove(&[id]).is_none() {
+                        return false;
+                }
+                if self.curr_db == id as usize {
+                        self.curr_db = 0;
+                }
+                true
+        }
+
         fn init(&mut self) -> io::Result<()> {
                 self.curr_db = 0;
                 self.db_registry.add(db::Database::new(0), &[0])?;
                 Ok(())
         }
 
+        /// Write a snapshot of the state, which covers every command logged
+        /// so far. The snapshot goes to a file next to the data file and is
+        /// then moved over it, so that a crash leaves either the old snapshot
+        /// or the new one.
         pub fn write_to_storage(&self) -> io::Result<()> {
                 let mut buf = Vec::<u8>::new();
                 let tlv = self.serialize()?;
                 tlv.serialize_into_buf(&mut buf)?;
-                let mut file = fs::OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .truncate(true)
-                        .open(&self.config().db_file)?;
+
+                let db_file = &self.config().db_file;
+                let mut tmp_file = OsString::from(db_file);
+                tmp_file.push(".tmp");
+                let tmp_file = PathBuf::from(tmp_file);
+                let mut file = fs::File::create(&tmp_file)?;
                 file.write_all(&buf)?;
+                file.sync_all()?;
+                fs::rename(&tmp_file, db_file)?;
+                let dir = db_file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                fs::File::open(dir)?.sync_all()?;
+                Ok(())
+        }
+
+        /// Write a snapshot of the state and empty the write-ahead log, whose
+        /// commands the snapshot now holds. Were the log not emptied, the
+        /// commands the snapshot covers are still skipped on replay.
+        pub fn checkpoint(&mut self) -> io::Result<()> {
+                self.write_to_storage()?;
+                self.snapshot_seq = self.wal.next_seq();
+                self.wal.clear()
+        }
+
+        /// Log a command that changed the state, and checkpoint if the log
+        /// has grown past either limit of the configuration. The command is
+        /// safe in the log by then, so a failed checkpoint is only reported,
+        /// and tried again on the next command.
+        pub fn log_cmd(&mut self, bytes: &[u8]) -> io::Result<()> {
+                self.wal.log(bytes)?;
+                let (max_sz, max_records) = (self.cfg.checkpoint_wal_sz, self.cfg.checkpoint_wal_records);
+                if (max_sz > 0 && self.wal.size() >= max_sz) || (max_records > 0 && self.wal.record_cnt() >= max_records) {
+                        if let Err(e) = self.checkpoint() {
+                                eprintln!("Failed to checkpoint the write-ahead log: {e}");
+                        }
+                }
                 Ok(())
         }
 
@@ -99,7 +178,6 @@ impl Ctl {
                 let mut loc = 9;
                 if buf.is_empty() || buf[loc] == 0 /* num_dbs */ {
                         self.init()?;
-                        return Ok(());
                 }
                 loc += 1;
 
@@ -120,14 +198,69 @@ impl Ctl {
                                                 db.bf_registry.add(bfs, &[id])?;
                                         }
                                 }
-                                srl::SerializableType::Ctl | srl::SerializableType::BitVec => {}
+                                srl::SerializableType::CountingBloomFilterStructure => {
+                                        let cbfs = CountingBloomFilterStructure::deserialize(&tlv)?;
+                                        let dbid = cbfs.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = cbfs.id;
+                                                db.cbf_registry.add(cbfs, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::ScalableBloomFilterStructure => {
+                                        let sbfs = ScalableBloomFilterStructure::deserialize(&tlv)?;
+                                        let dbid = sbfs.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = sbfs.id;
+                                                db.sbf_registry.add(sbfs, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::CuckooFilterStructure => {
+                                        let cfs = CuckooFilterStructure::deserialize(&tlv)?;
+                                        let dbid = cfs.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = cfs.id;
+                                                db.cf_registry.add(cfs, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::HyperLogLogStructure => {
+                                        let hlls = HyperLogLogStructure::deserialize(&tlv)?;
+                                        let dbid = hlls.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = hlls.id;
+                                                db.hll_registry.add(hlls, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::CountMinSketchStructure => {
+                                        let cmss = CountMinSketchStructure::deserialize(&tlv)?;
+                                        let dbid = cmss.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = cmss.id;
+                                                db.cms_registry.add(cmss, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::TopKStructure => {
+                                        let topks = TopKStructure::deserialize(&tlv)?;
+                                        let dbid = topks.dbid;
+                                        if let Some(ref mut db) = self.db_registry.get_mut(&[dbid]) {
+                                                let id = topks.id;
+                                                db.topk_registry.add(topks, &[id])?;
+                                        }
+                                }
+                                srl::SerializableType::LogSequence => {
+                                        let bytes = tlv.val.try_into()
+                                                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "impl Ctl: deserialize: malformed log sequence"))?;
+                                        self.snapshot_seq = u64::from_le_bytes(bytes);
+                                }
+                                srl::SerializableType::Ctl
+                                | srl::SerializableType::BitVec
+                                | srl::SerializableType::BloomFilter => {}
                         }
                 }
 
                 Ok(())
         }
 
-        pub fn replay_logging_data(&mut self) -> io::Result<()> {
+        fn replay_logging_data(&mut self) -> io::Result<()> {
                 wal::WriteAheadLog::replay(self)?;
                 Ok(())
         }
@@ -137,7 +270,7 @@ impl Ctl {
 impl srl::Serializable<Ctl> for Ctl {
         fn serialize(&self) -> io::Result<srl::SerTLV> {
                 let mut tlv = srl::SerTLV::new(srl::SerializableType::Ctl);
-                tlv.serialize_u8(2u8);
+                tlv.serialize_u8(u8::try_from(self.db_registry.count()).unwrap_or(u8::MAX));
 
                 for db in self.db_registry.list() {
                         let db_tlv = db.serialize()?;
@@ -149,8 +282,212 @@ impl srl::Serializable<Ctl> for Ctl {
                                 let bf_tlv = bf.serialize()?;
                                 tlv.serialize_sertlv(&bf_tlv)?;
                         }
+                        for cbf in db.cbf_registry.list() {
+                                let cbf_tlv = cbf.serialize()?;
+                                tlv.serialize_sertlv(&cbf_tlv)?;
+                        }
+                        for sbf in db.sbf_registry.list() {
+                                let sbf_tlv = sbf.serialize()?;
+                                tlv.serialize_sertlv(&sbf_tlv)?;
+                        }
+                        for cf in db.cf_registry.list() {
+                                let cf_tlv = cf.serialize()?;
+                                tlv.serialize_sertlv(&cf_tlv)?;
+                        }
+                        for hll in db.hll_registry.list() {
+                                let hll_tlv = hll.serialize()?;
+                                tlv.serialize_sertlv(&hll_tlv)?;
+                        }
+                        for cms in db.cms_registry.list() {
+                                let cms_tlv = cms.serialize()?;
+                                tlv.serialize_sertlv(&cms_tlv)?;
+                        }
+                        for topk in db.topk_registry.list() {
+                                let topk_tlv = topk.serialize()?;
+                                tlv.serialize_sertlv(&topk_tlv)?;
+                        }
                 }
 
+                // The snapshot covers every record logged before it
+                let mut seq_tlv = srl::SerTLV::new(srl::SerializableType::LogSequence);
+                seq_tlv.serialize_slice_u8(&self.wal.next_seq().to_le_bytes())?;
+                tlv.serialize_sertlv(&seq_tlv)?;
+
                 Ok(tlv)
         }
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use qstra_prob::bf::BloomFilterParams;
+        use crate::testing::TempDir;
+
+        #[test]
+        fn test_bf_roundtrip() {
+                let keys: Vec<Vec<u8>> = (0..200u32).map(|i| i.to_le_bytes().to_vec()).collect();
+
+                let dir = TempDir::new("bf_roundtrip");
+                let mut ctl = Ctl::new_blank(dir.config()).unwrap();
+                ctl.init().unwrap();
+                let db = ctl.db_registry.get_mut(&[0]).unwrap();
+                let mut bfs = BloomFilterStructure::with_params(1, 0, &BloomFilterParams::new(4096, 7).unwrap());
+                bfs.inner.seed = 0xdead_beef;
+                for key in &keys {
+                        bfs.inner.add(key).unwrap();
+                }
+                db.bf_registry.add(bfs, &[1]).unwrap();
+                db.bf_registry.add(BloomFilterStructure::new_default(2, 0), &[2]).unwrap();
+                ctl.write_to_storage().unwrap();
+
+                let conf = cfg::Config {
+                        db_file: ctl.config().db_file.clone(),
+                        wal_file: ctl.config().wal_file.clone(),
+                        ..cfg::Config::default()
+                };
+                let mut restored = Ctl::new_blank(conf).unwrap();
+                restored.load_from_storage().unwrap();
+
+                let db = restored.db_registry.get(&[0]).unwrap();
+                assert!(db.bf_registry.count() == 2);
+                for id in [1, 2] {
+                        let expected = &ctl.db_registry.get(&[0]).unwrap().bf_registry.get(&[id]).unwrap().inner;
+                        let bf = &db.bf_registry.get(&[id]).unwrap().inner;
+                        assert!(bf.hfn_cnt == expected.hfn_cnt, "{} != {}", bf.hfn_cnt, expected.hfn_cnt);
+                        assert!(bf.bit_cnt == expected.bit_cnt);
+                        assert!(bf.hfn_family == expected.hfn_family);
+                        assert!(bf.seed == expected.seed);
+                        assert!(bf.insert_cnt == expected.insert_cnt);
+                }
+
+                let bf = &db.bf_registry.get(&[1]).unwrap().inner;
+                assert!(bf.insert_cnt == keys.len());
+                for key in &keys {
+                        assert!(bf.has(key).unwrap());
+                }
+        }
+
+        #[test]
+        fn test_drop_replay() {
+                let dir = TempDir::new("drop_replay");
+                let mut ctl = Ctl::new_blank(dir.config()).unwrap();
+                // Create bloom filters 1 and 2 in database 0, then drop 1
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 1]).unwrap();
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 2]).unwrap();
+                ctl.wa_log().log(&[2, 7, 255, 255, 4, 0, 0, 0, 0, 2, 3, 1]).unwrap();
+
+                ctl.init().unwrap();
+                ctl.replay_logging_data().unwrap();
+                let db = ctl.db_registry.get(&[0]).unwrap();
+                assert!(!db.has_bloom_filter(1));
+                assert!(db.has_bloom_filter(2));
+
+                ctl.wa_log().log(&[1, 3, 255, 255, 1, 0, 0, 0, 0]).unwrap();
+                ctl.replay_logging_data().unwrap();
+                assert!(ctl.db_registry.get(&[0]).is_none());
+        }
+
+        #[test]
+        fn test_create_database() {
+                let dir = TempDir::new("create_database");
+                let mut ctl = Ctl::new_blank(dir.config()).unwrap();
+                ctl.init().unwrap();
+                // Create database 7 and a bloom filter in it
+                ctl.wa_log().log(&[1, 4, 255, 255, 1, 0, 0, 0, 7]).unwrap();
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 7, 1, 1]).unwrap();
+                ctl.replay_logging_data().unwrap();
+                assert!(!ctl.create_database(7, None).unwrap());
+                assert!(ctl.db_registry.get(&[7]).unwrap().has_bloom_filter(1));
+                ctl.write_to_storage().unwrap();
+
+                let restored_dir = TempDir::new("create_database_restored");
+                let conf = cfg::Config {
+                        db_file: ctl.config().db_file.clone(),
+                        wal_file: restored_dir.config().wal_file,
+                        ..cfg::Config::default()
+                };
+                let mut restored = Ctl::new_blank(conf).unwrap();
+                restored.load_from_storage().unwrap();
+                let ids: Vec<u8> = restored.db_registry.list().iter().map(|db| db.id).collect();
+                assert!(ids == [0, 7]);
+                assert!(restored.db_registry.get(&[7]).unwrap().has_bloom_filter(1));
+        }
+
+        #[test]
+        fn test_names() {
+                let dir = TempDir::new("names");
+                let mut ctl = Ctl::new_blank(dir.config()).unwrap();
+                ctl.init().unwrap();
+                let mut create_db = vec![1, 4, 255, 255, 9, 0, 0, 0, 3];
+                create_db.extend(b"tenant-a");
+                let mut name_bf = vec![2, 8, 255, 255, 9, 0, 0, 0, 3, 7, 3, 1];
+                name_bf.extend(b"users");
+                ctl.wa_log().log(&create_db).unwrap();
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 3, 1, 1]).unwrap();
+                ctl.wa_log().log(&name_bf).unwrap();
+                ctl.replay_logging_data().unwrap();
+                ctl.write_to_storage().unwrap();
+
+                let restored_dir = TempDir::new("names_restored");
+                let conf = cfg::Config {
+                        db_file: ctl.config().db_file.clone(),
+                        wal_file: restored_dir.config().wal_file,
+                        ..cfg::Config::default()
+                };
+                let mut restored = Ctl::new_blank(conf).unwrap();
+                restored.load_from_storage().unwrap();
+                assert!(restored.lookup_database("tenant-a") == Some(3));
+                assert!(restored.lookup_database("tenant-b").is_none());
+                let db = restored.db_registry.get_mut(&[3]).unwrap();
+                assert!(db.lookup("users") == Some((db::ObjectKind::BloomFilter, 1)));
+
+                db.set_name(db::ObjectKind::BloomFilter, 1, "members");
+                assert!(db.lookup("users").is_none());
+                assert!(db.lookup("members") == Some((db::ObjectKind::BloomFilter, 1)));
+                assert!(db.drop_object(db::ObjectKind::BloomFilter, 1));
+                assert!(db.names.is_empty());
+        }
+
+        #[test]
+        fn test_checkpoint() {
+                let dir = TempDir::new("checkpoint");
+                let conf = cfg::Config { checkpoint_wal_records: 3, ..dir.config() };
+                let reopen = || Ctl::new_blank(cfg::Config {
+                        db_file: conf.db_file.clone(),
+                        wal_file: conf.wal_file.clone(),
+                        checkpoint_wal_records: 3,
+                        ..cfg::Config::default()
+                }).unwrap();
+
+                // The commands are logged but not applied, so what comes back
+                // shows which records were replayed
+                let mut ctl = reopen();
+                ctl.load_from_storage().unwrap();
+                ctl.log_cmd(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 1]).unwrap();
+                ctl.log_cmd(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 2]).unwrap();
+                ctl.write_to_storage().unwrap();
+                assert!(!conf.db_file.with_extension("db.tmp").exists());
+
+                let mut restored = reopen();
+                restored.load_from_storage().unwrap();
+                assert!(restored.snapshot_seq() == 2 && restored.wa_log().record_cnt() == 2);
+                assert!(!restored.db_registry.get(&[0]).unwrap().has_bloom_filter(1));
+
+                // The third record makes for a checkpoint, and the records are
+                // numbered on from it once the log is empty
+                restored.log_cmd(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 3]).unwrap();
+                assert!(restored.snapshot_seq() == 3 && restored.wa_log().size() == wal::FILE_HEADER_SZ as u64);
+                assert!(fs::metadata(&conf.wal_file).unwrap().len() == wal::FILE_HEADER_SZ as u64);
+                let mut restored = reopen();
+                restored.load_from_storage().unwrap();
+                assert!(restored.wa_log().next_seq() == 3);
+                assert!(!restored.db_registry.get(&[0]).unwrap().has_bloom_filter(3));
+
+                // A checkpoint that fails leaves the logged command in place
+                restored.cfg.db_file = conf.db_file.join("missing").join("qstra.db");
+                for i in 4..7 {
+                        restored.log_cmd(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, i]).unwrap();
+                }
+                assert!(restored.snapshot_seq() == 3 && restored.wa_log().record_cnt() == 3);
+        }
 }
\ No newline at end of file
@@ -2,18 +2,107 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 
+use std::collections::BTreeMap;
 use std::io;
 
-use qstra_prob::bf::BloomFilterStructure;
+use qstra_prob::bf::{BloomFilterStructure, MembershipFilter};
+use qstra_prob::cbf::CountingBloomFilterStructure;
+use qstra_prob::cf::CuckooFilterStructure;
+use qstra_prob::cms::CountMinSketchStructure;
+use qstra_prob::hll::HyperLogLogStructure;
+use qstra_prob::sbf::ScalableBloomFilterStructure;
+use qstra_prob::topk::TopKStructure;
 use qstra_stor::srl;
 
 use crate::reg;
 
 
+/// The kinds of objects a database holds, numbered after the command family
+/// that serves them.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+        BloomFilter = 3,
+        CountingBloomFilter = 4,
+        CuckooFilter = 5,
+        HyperLogLog = 6,
+        CountMinSketch = 7,
+        TopK = 8,
+}
+
+
+impl TryFrom<u8> for ObjectKind {
+        type Error = io::Error;
+
+        fn try_from(byte: u8) -> io::Result<Self> {
+                match byte {
+                        3 => Ok(ObjectKind::BloomFilter),
+                        4 => Ok(ObjectKind::CountingBloomFilter),
+                        5 => Ok(ObjectKind::CuckooFilter),
+                        6 => Ok(ObjectKind::HyperLogLog),
+                        7 => Ok(ObjectKind::CountMinSketch),
+                        8 => Ok(ObjectKind::TopK),
+                        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown discriminant for ObjectKind")),
+                }
+        }
+}
+
+
+impl ObjectKind {
+        pub const ALL: [ObjectKind; 6] = [
+                ObjectKind::BloomFilter,
+                ObjectKind::CountingBloomFilter,
+                ObjectKind::CuckooFilter,
+                ObjectKind::HyperLogLog,
+                ObjectKind::CountMinSketch,
+                ObjectKind::TopK,
+        ];
+
+        #[must_use]
+        pub fn value(&self) -> u8 {
+                *self as u8
+        }
+
+        #[must_use]
+        pub fn name(&self) -> &'static str {
+                match self {
+                        ObjectKind::BloomFilter => "bloom filter",
+                        ObjectKind::CountingBloomFilter => "counting bloom filter",
+                        ObjectKind::CuckooFilter => "cuckoo filter",
+                        ObjectKind::HyperLogLog => "HyperLogLog",
+                        ObjectKind::CountMinSketch => "Count-Min sketch",
+                        ObjectKind::TopK => "Top-K structure",
+                }
+        }
+}
+
+
+/// The longest name, in bytes, of a database or an object.
+pub const MAX_NAME_LEN: usize = u8::MAX as usize;
+
+
+/// Check that a name is non-empty UTF-8 of at most `MAX_NAME_LEN` bytes.
+pub fn parse_name(bytes: &[u8]) -> io::Result<&str> {
+        if bytes.is_empty() || bytes.len() > MAX_NAME_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "parse_name: invalid name length"));
+        }
+        std::str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
 #[derive(Debug)]
 pub struct Database {
         pub id: u8,
+        pub name: Option<String>,
+        // The names of the objects, each bound to at most one object
+        pub names: BTreeMap<String, (ObjectKind, u8)>,
         pub bf_registry: reg::Registry<BloomFilterStructure>,
+        pub cbf_registry: reg::Registry<CountingBloomFilterStructure>,
+        pub sbf_registry: reg::Registry<ScalableBloomFilterStructure>,
+        pub cf_registry: reg::Registry<CuckooFilterStructure>,
+        pub hll_registry: reg::Registry<HyperLogLogStructure>,
+        pub cms_registry: reg::Registry<CountMinSketchStructure>,
+        pub topk_registry: reg::Registry<TopKStructure>,
 }
 
 
@@ -22,26 +111,134 @@ impl Database {
         pub fn new(id: u8) -> Self {
                 Self {
                         id,
+                        name: None,
+                        names: BTreeMap::new(),
                         bf_registry: reg::Registry::<BloomFilterStructure>::new_blank(),
+                        cbf_registry: reg::Registry::<CountingBloomFilterStructure>::new_blank(),
+                        sbf_registry: reg::Registry::<ScalableBloomFilterStructure>::new_blank(),
+                        cf_registry: reg::Registry::<CuckooFilterStructure>::new_blank(),
+                        hll_registry: reg::Registry::<HyperLogLogStructure>::new_blank(),
+                        cms_registry: reg::Registry::<CountMinSketchStructure>::new_blank(),
+                        topk_registry: reg::Registry::<TopKStructure>::new_blank(),
+                }
+        }
+
+        // Plain and scalable bloom filters share one id space, so that the
+        // bloom filter commands can serve either kind.
+        pub fn has_bloom_filter(&self, id: u8) -> bool {
+                self.bf_registry.get(&[id]).is_some() || self.sbf_registry.get(&[id]).is_some()
+        }
+
+        pub fn bloom_filter(&self, id: u8) -> Option<&dyn MembershipFilter> {
+                if let Some(bfs) = self.bf_registry.get(&[id]) {
+                        return Some(&bfs.inner);
+                }
+                self.sbf_registry.get(&[id]).map(|sbfs| &sbfs.inner as &dyn MembershipFilter)
+        }
+
+        pub fn bloom_filter_mut(&mut self, id: u8) -> Option<&mut dyn MembershipFilter> {
+                if let Some(bfs) = self.bf_registry.get_mut(&[id]) {
+                        return Some(&mut bfs.inner);
+                }
+                self.sbf_registry.get_mut(&[id]).map(|sbfs| &mut sbfs.inner as &mut dyn MembershipFilter)
+        }
+
+        pub fn has_object(&self, kind: ObjectKind, id: u8) -> bool {
+                match kind {
+                        ObjectKind::BloomFilter => { self.has_bloom_filter(id) }
+                        ObjectKind::CountingBloomFilter => { self.cbf_registry.get(&[id]).is_some() }
+                        ObjectKind::CuckooFilter => { self.cf_registry.get(&[id]).is_some() }
+                        ObjectKind::HyperLogLog => { self.hll_registry.get(&[id]).is_some() }
+                        ObjectKind::CountMinSketch => { self.cms_registry.get(&[id]).is_some() }
+                        ObjectKind::TopK => { self.topk_registry.get(&[id]).is_some() }
+                }
+        }
+
+        pub fn lookup(&self, name: &str) -> Option<(ObjectKind, u8)> {
+                self.names.get(name).copied()
+        }
+
+        /// Bind a name to an object, replacing any name it had. An empty
+        /// name leaves the object unnamed. The caller checks that the name
+        /// isn't bound to another object.
+        pub fn set_name(&mut self, kind: ObjectKind, id: u8, name: &str) {
+                self.names.retain(|_, obj| *obj != (kind, id));
+                if !name.is_empty() {
+                        self.names.insert(name.to_owned(), (kind, id));
+                }
+        }
+
+        /// Drop an object, freeing its id and name. Returns false if there
+        /// was no such object.
+        pub fn drop_object(&mut self, kind: ObjectKind, id: u8) -> bool {
+                self.names.retain(|_, obj| *obj != (kind, id));
+                match kind {
+                        ObjectKind::BloomFilter => {
+                                self.bf_registry.remove(&[id]).is_some() || self.sbf_registry.remove(&[id]).is_some()
+                        }
+                        ObjectKind::CountingBloomFilter => { self.cbf_registry.remove(&[id]).is_some() }
+                        ObjectKind::CuckooFilter => { self.cf_registry.remove(&[id]).is_some() }
+                        ObjectKind::HyperLogLog => { self.hll_registry.remove(&[id]).is_some() }
+                        ObjectKind::CountMinSketch => { self.cms_registry.remove(&[id]).is_some() }
+                        ObjectKind::TopK => { self.topk_registry.remove(&[id]).is_some() }
                 }
         }
 }
 
 
+// A serialized database is laid out as
+//
+//   id (u8) | [name_len (u8) | name | names (kind (u8) | id (u8) | name_len (u8) | name)...]
+//
+// where a zero name_len marks an unnamed database. Snapshots taken before
+// names existed end after the id.
 impl srl::Deserializable for Database {
         fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
         where Self: Sized
         {
                 let buf = &tlv.val;
-                Ok(Database::new(srl::DeserTLV::deserialize_u8(buf)?))
+                let mut db = Database::new(srl::DeserTLV::deserialize_u8(buf)?);
+                let malformed = || io::Error::new(io::ErrorKind::InvalidData, "impl Database: deserialize: malformed names");
+                let read_name = |loc: &mut usize| -> io::Result<Option<String>> {
+                        let len = *buf.get(*loc).ok_or_else(malformed)? as usize;
+                        let bytes = buf.get(*loc+1..*loc+1+len).ok_or_else(malformed)?;
+                        *loc += 1 + len;
+                        if len == 0 {
+                                return Ok(None);
+                        }
+                        Ok(Some(parse_name(bytes)?.to_owned()))
+                };
+
+                let mut loc = srl::U8_OFFSET;
+                if loc < buf.len() {
+                        db.name = read_name(&mut loc)?;
+                }
+                while loc < buf.len() {
+                        let kind = ObjectKind::try_from(buf[loc])?;
+                        let id = *buf.get(loc+1).ok_or_else(malformed)?;
+                        loc += 2;
+                        let name = read_name(&mut loc)?.ok_or_else(malformed)?;
+                        db.names.insert(name, (kind, id));
+                }
+                Ok(db)
         }
 }
 
 
 impl srl::Serializable<Database> for Database {
+        #[allow(clippy::cast_possible_truncation)]
         fn serialize(&self) -> io::Result<srl::SerTLV> {
                 let mut tlv = srl::SerTLV::new(srl::SerializableType::Database);
                 tlv.serialize_u8(self.id);
+                let name = self.name.as_deref().unwrap_or_default();
+                tlv.serialize_u8(name.len() as u8);
+                tlv.serialize_slice_u8(name.as_bytes())?;
+                for (name, (kind, id)) in &self.names {
+                        tlv.serialize_u8(kind.value());
+                        tlv.serialize_u8(*id);
+                        tlv.serialize_u8(name.len() as u8);
+                        tlv.serialize_slice_u8(name.as_bytes())?;
+                }
                 Ok(tlv)
         }
 }
\ No newline at end of file
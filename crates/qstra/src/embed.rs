@@ -0,0 +1,202 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Open the databases in-process, without a server.
+//!
+//! The store runs the same commands the server does, through the same
+//! dispatch, so the state it leaves in its data directory can be served later
+//! and vice versa. Reads are dispatched as they are; a change is framed, as
+//! the write-ahead log holds frames, and run from the frame it's logged as,
+//! so what is applied is what replay applies. A checkpoint writes the
+//! databases out and empties the log. Errors are reported as a client
+//! reports error responses, wrapping a `ServerError`.
+
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use qstra_proto::frame as proto;
+use qstra_proto::{FilterInfo, FilterParams, ServerError};
+
+use crate::cfg;
+use crate::cmd;
+use crate::ctl;
+
+
+pub const DB_FILE: &str = "qstra.db";
+pub const WAL_FILE: &str = "qstra.wal";
+
+
+/// The databases of a data directory, opened in-process.
+pub struct Store {
+        ctl: ctl::Ctl,
+}
+
+
+impl Store {
+        /// Open the databases kept in the directory, creating it if need be,
+        /// and replay the write-ahead log onto them.
+        pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+                let dir = dir.as_ref();
+                fs::create_dir_all(dir)?;
+                Self::with_config(cfg::Config {
+                        db_file: dir.join(DB_FILE),
+                        wal_file: dir.join(WAL_FILE),
+                        ..cfg::Config::default()
+                })
+        }
+
+        /// Open the databases at the files of the configuration, of which the
+        /// listeners are ignored.
+        pub fn with_config(conf: cfg::Config) -> io::Result<Self> {
+                let mut ctl = ctl::Ctl::new_blank(conf)?;
+                ctl.load_from_storage()?;
+                Ok(Self { ctl })
+        }
+
+        #[must_use]
+        pub fn config(&self) -> &cfg::Config {
+                self.ctl.config()
+        }
+
+        /// Write the databases to storage and empty the write-ahead log,
+        /// whose commands the written state now holds.
+        pub fn checkpoint(&mut self) -> io::Result<()> {
+                self.ctl.checkpoint()
+        }
+
+        /// Create a database, optionally named.
+        pub fn create_database(&mut self, db_id: u8, name: Option<&str>) -> io::Result<()> {
+                let val = [&[db_id], name.unwrap_or_default().as_bytes()].concat();
+                self.write(proto::FAMILY_CTL, proto::OP_CTL_CREATE_DATABASE, &val)?;
+                Ok(())
+        }
+
+        pub fn create_filter(&mut self, db_id: u8, bf_id: u8, params: &FilterParams) -> io::Result<()> {
+                let mut spec = vec![bf_id];
+                params.encode_into(&mut spec);
+                let mut val = vec![db_id];
+                proto::encode_lv(&mut val, &spec)?;
+                self.write(proto::FAMILY_DB, params.op(), &val)?;
+                Ok(())
+        }
+
+        pub fn filter_info(&self, db_id: u8, bf_id: u8) -> io::Result<FilterInfo> {
+                FilterInfo::decode(&self.read(&cmd::ReadCmd::bf_info(db_id, bf_id))?)
+        }
+
+        pub fn add(&mut self, db_id: u8, bf_id: u8, elt: &[u8]) -> io::Result<()> {
+                let mut val = vec![db_id, bf_id];
+                proto::encode_lv(&mut val, elt)?;
+                self.write(proto::FAMILY_BF, proto::OP_BF_ADD, &val)?;
+                Ok(())
+        }
+
+        /// Add the elements, in as few logged commands as they fit in.
+        pub fn add_batch(&mut self, db_id: u8, bf_id: u8, elts: &[&[u8]]) -> io::Result<()> {
+                for batch in proto::encode_batches(elts)? {
+                        let mut val = vec![db_id, bf_id];
+                        proto::encode_lv(&mut val, &batch)?;
+                        self.write(proto::FAMILY_BF, proto::OP_BF_ADD_BATCH, &val)?;
+                }
+                Ok(())
+        }
+
+        pub fn has(&self, db_id: u8, bf_id: u8, elt: &[u8]) -> io::Result<bool> {
+                let ans = self.read(&cmd::ReadCmd::bf_has(db_id, bf_id, elt))?;
+                Ok(ans == [proto::TOKEN_TRUE])
+        }
+
+        /// Check the elements. The answers are in the order of the elements.
+        pub fn has_batch(&self, db_id: u8, bf_id: u8, elts: &[&[u8]]) -> io::Result<Vec<bool>> {
+                let mut found = Vec::with_capacity(elts.len());
+                for batch in proto::encode_batches(elts)? {
+                        let ans = self.read(&cmd::ReadCmd::bf_has_batch(db_id, bf_id, &batch))?;
+                        found.extend(ans.iter().map(|ans| *ans == proto::TOKEN_TRUE));
+                }
+                Ok(found)
+        }
+
+        fn read(&self, read_cmd: &cmd::ReadCmd) -> io::Result<Vec<u8>> {
+                let mut resp = cmd::CmdResponseTLV::new();
+                cmd::dispatch_read_cmd(read_cmd, &self.ctl, &mut resp)?;
+                into_result(&resp)
+        }
+
+        // Run a command that changes the state, and log it to the write-ahead
+        // log once it succeeded, as the server does
+        fn write(&mut self, family: u8, op: u8, val: &[u8]) -> io::Result<Vec<u8>> {
+                let mut frame = Vec::new();
+                proto::encode_frame(&mut frame, family, op, proto::NO_REQUEST_ID, val)?;
+                let tlv = cmd::CmdTLV::new(&frame)?;
+                let cmd::Cmd::Write(write_cmd) = cmd::decode_cmd(&tlv)? else {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "impl Store: write: not a write command"));
+                };
+                if self.ctl.config().read_only {
+                        return Err(ServerError { code: ServerError::READ_ONLY, detail: "the store is read-only".to_owned() }.into_io_error());
+                }
+                let mut resp = cmd::CmdResponseTLV::new();
+                cmd::dispatch_write_cmd(&write_cmd, &mut self.ctl, &mut resp)?;
+                if let cmd::CmdResponseCode::Success = resp.status() {
+                        if self.ctl.config().wal_mode > 0 && write_cmd.is_logged() {
+                                self.ctl.log_cmd(tlv.bytes())?;
+                        }
+                }
+                into_result(&resp)
+        }
+}
+
+
+fn into_result(resp: &cmd::CmdResponseTLV) -> io::Result<Vec<u8>> {
+        match resp.status() {
+                cmd::CmdResponseCode::Success => Ok(resp.value().to_vec()),
+                rc @ cmd::CmdResponseCode::Error(_) => Err(ServerError {
+                        code: rc.as_u8(),
+                        detail: resp.detail().to_owned(),
+                }.into_io_error()),
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use crate::testing::TempDir;
+
+        #[test]
+        fn test_store() {
+                let dir = TempDir::new("store");
+                let params = FilterParams::Estimate { capacity: 1000, fpr: 0.01 };
+
+                let mut store = Store::open(dir.path()).unwrap();
+                store.create_filter(0, 1, &params).unwrap();
+                store.add(0, 1, b"a").unwrap();
+                store.add_batch(0, 1, &[b"b", b"c"]).unwrap();
+                assert!(store.has_batch(0, 1, &[b"a", b"c", b"d"]).unwrap() == [true, true, false]);
+                let e = store.create_filter(0, 1, &params).unwrap_err();
+                assert!(e.kind() == io::ErrorKind::AlreadyExists);
+                let e = store.has(0, 2, b"a").unwrap_err();
+                assert!(e.get_ref().and_then(|e| e.downcast_ref::<ServerError>()).unwrap().code == ServerError::OBJECT_NOT_FOUND);
+                drop(store);
+
+                // Nothing was saved, so the filter comes back from the log
+                let mut store = Store::open(dir.path()).unwrap();
+                assert!(store.has(0, 1, b"b").unwrap());
+                store.checkpoint().unwrap();
+                assert!(fs::metadata(dir.path().join(WAL_FILE)).unwrap().len() == crate::wal::FILE_HEADER_SZ as u64);
+                store.create_database(1, Some("other")).unwrap();
+                store.create_filter(1, 0, &FilterParams::Default).unwrap();
+                drop(store);
+
+                let store = Store::open(dir.path()).unwrap();
+                assert!(store.has(0, 1, b"c").unwrap() && !store.has(1, 0, b"c").unwrap());
+                assert!(store.filter_info(0, 1).unwrap().insert_cnt == 3);
+                drop(store);
+
+                let mut store = Store::with_config(cfg::Config { read_only: true, ..dir.config() }).unwrap();
+                assert!(store.add(0, 1, b"d").unwrap_err().kind() == io::ErrorKind::PermissionDenied);
+                assert!(store.has(0, 1, b"a").unwrap());
+        }
+}
\ No newline at end of file
@@ -0,0 +1,797 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Serve a JSON API over HTTP.
+//!
+//! The API covers the bloom filters, plain and scalable, of every database:
+//!
+//!   GET  /databases                              list the databases
+//!   GET  /databases/{db}/filters                 list the named filters
+//!   POST /databases/{db}/filters                 create a filter
+//!   GET  /databases/{db}/filters/{name}          describe a filter
+//!   POST /databases/{db}/filters/{name}/add      add one or many keys
+//!   POST /databases/{db}/filters/{name}/check    check one or many keys
+//!
+//! where a database goes by its id or its name. Every request is served by
+//! commands of the native protocol, so the writes are logged like any other.
+
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use qstra_prob::sbf::ScalableBloomFilterParams;
+use qstra_proto::frame as proto;
+
+use crate::cfg;
+use crate::cmd;
+use crate::ctl;
+use crate::db;
+use crate::srv;
+
+
+const READ_CHUNK_SZ: usize = 4096;
+const MAX_HEAD_LEN: usize = 8192;
+const MAX_JSON_DEPTH: usize = 32;
+
+
+struct Request {
+        method: String,
+        path: String,
+        body: Vec<u8>,
+        keep_alive: bool,
+}
+
+
+struct Response {
+        status: u16,
+        body: String,
+}
+
+
+impl Response {
+        fn ok(body: String) -> Self {
+                Self { status: 200, body }
+        }
+
+        fn error(status: u16, msg: impl AsRef<str>) -> Self {
+                Self { status, body: format!("{{\"error\":{}}}", json_string(msg.as_ref())) }
+        }
+
+        fn bad_request(msg: impl AsRef<str>) -> Self {
+                Self::error(400, msg)
+        }
+
+        fn encode_into(&self, buf: &mut Vec<u8>, keep_alive: bool) {
+                let reason = match self.status {
+                        200 => "OK",
+                        201 => "Created",
+                        400 => "Bad Request",
+                        403 => "Forbidden",
+                        404 => "Not Found",
+                        405 => "Method Not Allowed",
+                        409 => "Conflict",
+                        411 => "Length Required",
+                        413 => "Content Too Large",
+                        431 => "Request Header Fields Too Large",
+                        _ => "Internal Server Error",
+                };
+                let connection = if keep_alive { "keep-alive" } else { "close" };
+                buf.extend(format!(
+                        "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {connection}\r\n\r\n",
+                        self.status,
+                        self.body.len()
+                ).as_bytes());
+                buf.extend(self.body.as_bytes());
+        }
+}
+
+
+pub async fn handle_client<S>(mut stream: S, ctl_rc: Rc<RefCell<ctl::Ctl>>) -> io::Result<()>
+where S: AsyncRead + AsyncWrite + Unpin,
+{
+        let max_body_len = ctl_rc.try_borrow()
+                .map_or(cfg::DEFAULT_MAX_FRAME_SZ, |c| c.config().max_frame_sz);
+        let mut chunk = [0; READ_CHUNK_SZ];
+        // The bytes received but not yet parsed
+        let mut inbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+        // The responses not yet written back
+        let mut outbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+
+        loop {
+                let read_cnt = match stream.read(&mut chunk).await {
+                        Ok(0) => {
+                                println!("HTTP client connection closed.");
+                                return Ok(());
+                        }
+                        Ok(n) => n,
+                        Err(e) => {
+                                eprintln!("Error reading from HTTP client stream: {e}");
+                                return Ok(());
+                        }
+                };
+                inbuf.extend_from_slice(&chunk[..read_cnt]);
+
+                let mut start = 0;
+                let mut close = false;
+                loop {
+                        let (request, len) = match parse_request(&inbuf[start..], max_body_len) {
+                                Ok(Some(request)) => request,
+                                Ok(None) => break,
+                                Err(resp) => {
+                                        // There is no telling where the next
+                                        // request starts, so give up on the client
+                                        resp.encode_into(&mut outbuf, false);
+                                        close = true;
+                                        break;
+                                }
+                        };
+                        start += len;
+                        let resp = match route(&ctl_rc, &request).await {
+                                Ok(resp) | Err(resp) => resp,
+                        };
+                        resp.encode_into(&mut outbuf, request.keep_alive);
+                        if !request.keep_alive {
+                                close = true;
+                                break;
+                        }
+                }
+                inbuf.drain(..start);
+
+                if !outbuf.is_empty() {
+                        if stream.write_all(&outbuf).await.is_err() {
+                                eprintln!("Error responding to HTTP client stream.");
+                                return Ok(());
+                        }
+                        outbuf.clear();
+                }
+                if close {
+                        return Ok(());
+                }
+        }
+}
+
+
+// Parse the request at the start of the buffer, and return it along with the
+// number of bytes it took, or None if it hasn't fully arrived. The body must
+// come with its length, as chunked bodies aren't supported.
+fn parse_request(buf: &[u8], max_body_len: usize) -> Result<Option<(Request, usize)>, Response> {
+        let Some(head_len) = buf.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4) else {
+                if buf.len() > MAX_HEAD_LEN {
+                        return Err(Response::error(431, "request head too large"));
+                }
+                return Ok(None);
+        };
+        if head_len > MAX_HEAD_LEN {
+                return Err(Response::error(431, "request head too large"));
+        }
+        let malformed = || Response::bad_request("malformed request head");
+        let head = std::str::from_utf8(&buf[..head_len-4]).map_err(|_| malformed())?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+                (request_line.next(), request_line.next(), request_line.next(), request_line.next()) else {
+                return Err(malformed());
+        };
+        let mut keep_alive = match version {
+                "HTTP/1.1" => true,
+                "HTTP/1.0" => false,
+                _ => { return Err(malformed()); }
+        };
+
+        let mut body_len = 0;
+        for line in lines {
+                let (name, val) = line.split_once(':').ok_or_else(malformed)?;
+                let val = val.trim();
+                if name.eq_ignore_ascii_case("Content-Length") {
+                        body_len = val.parse().map_err(|_| malformed())?;
+                } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                        return Err(Response::error(411, "chunked bodies are not supported"));
+                } else if name.eq_ignore_ascii_case("Connection") {
+                        keep_alive = match val.to_ascii_lowercase().as_str() {
+                                "close" => false,
+                                "keep-alive" => true,
+                                _ => keep_alive,
+                        };
+                }
+        }
+        if body_len > max_body_len {
+                return Err(Response::error(413, format!("body of {body_len} bytes exceeds the limit of {max_body_len}")));
+        }
+        let Some(body) = buf.get(head_len..head_len+body_len) else {
+                return Ok(None);
+        };
+
+        let request = Request {
+                method: method.to_owned(),
+                path: target.split('?').next().unwrap_or_default().to_owned(),
+                body: body.to_vec(),
+                keep_alive,
+        };
+        Ok(Some((request, head_len + body_len)))
+}
+
+
+async fn route(ctl_rc: &Rc<RefCell<ctl::Ctl>>, request: &Request) -> Result<Response, Response> {
+        let segments = request.path.split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(percent_decode)
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| Response::bad_request("malformed path"))?;
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+                ("GET", ["databases"]) => list_databases(ctl_rc).await,
+                ("GET", ["databases", db, "filters"]) => {
+                        list_filters(ctl_rc, resolve_database(ctl_rc, db).await?).await
+                }
+                ("POST", ["databases", db, "filters"]) => {
+                        let body = Json::parse(&request.body).ok_or_else(|| Response::bad_request("malformed JSON body"))?;
+                        create_filter(ctl_rc, resolve_database(ctl_rc, db).await?, &body).await
+                }
+                ("GET", ["databases", db, "filters", name]) => {
+                        let db_id = resolve_database(ctl_rc, db).await?;
+                        describe_filter(ctl_rc, db_id, name).await
+                }
+                ("POST", ["databases", db, "filters", name, action @ ("add" | "check")]) => {
+                        let body = Json::parse(&request.body).ok_or_else(|| Response::bad_request("malformed JSON body"))?;
+                        let db_id = resolve_database(ctl_rc, db).await?;
+                        let bf_id = resolve_filter(ctl_rc, db_id, name).await?;
+                        if *action == "add" {
+                                add_keys(ctl_rc, db_id, bf_id, &body).await
+                        } else {
+                                check_keys(ctl_rc, db_id, bf_id, &body).await
+                        }
+                }
+                (_, ["databases"] | ["databases", _, "filters"] | ["databases", _, "filters", _]
+                        | ["databases", _, "filters", _, "add" | "check"]) => {
+                        Err(Response::error(405, format!("{} is not allowed on {}", request.method, request.path)))
+                }
+                _ => Err(Response::error(404, format!("no endpoint at {}", request.path))),
+        }
+}
+
+
+fn percent_decode(segment: &str) -> Option<String> {
+        let bytes = segment.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut idx = 0;
+        while idx < bytes.len() {
+                if bytes[idx] == b'%' {
+                        let hex = std::str::from_utf8(bytes.get(idx+1..idx+3)?).ok()?;
+                        decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                        idx += 3;
+                } else {
+                        decoded.push(bytes[idx]);
+                        idx += 1;
+                }
+        }
+        String::from_utf8(decoded).ok()
+}
+
+
+// Run a command of the native protocol. Only a failure to run it at all is
+// an error here; an error response is the caller's to interpret.
+async fn request(ctl_rc: &Rc<RefCell<ctl::Ctl>>, family: u8, op: u8, val: &[u8]) -> Result<cmd::CmdResponseTLV, Response> {
+        srv::execute_request(ctl_rc, family, op, val).await
+                .map_err(|e| Response::error(500, e.to_string()))
+}
+
+
+// Run a command of the native protocol and return the value of its
+// response, or the error response as the matching HTTP error.
+async fn run(ctl_rc: &Rc<RefCell<ctl::Ctl>>, family: u8, op: u8, val: &[u8]) -> Result<Vec<u8>, Response> {
+        let resp = request(ctl_rc, family, op, val).await?;
+        match resp.status() {
+                cmd::CmdResponseCode::Success => Ok(resp.value().to_vec()),
+                cmd::CmdResponseCode::Error(err) => Err(error_response(err, &resp)),
+        }
+}
+
+
+fn error_response(err: cmd::CmdError, resp: &cmd::CmdResponseTLV) -> Response {
+        let status = match err {
+                cmd::CmdError::NameNotFound | cmd::CmdError::DatabaseNotFound | cmd::CmdError::ObjectNotFound => 404,
+                cmd::CmdError::ObjectExists => 409,
+                cmd::CmdError::ReadOnly => 403,
+                cmd::CmdError::FrameTooLarge => 413,
+                cmd::CmdError::RequestBytesMalformed
+                        | cmd::CmdError::TruncatedFrame
+                        | cmd::CmdError::InvalidParameters
+                        | cmd::CmdError::FilterFull => 400,
+                cmd::CmdError::UnknownCommand | cmd::CmdError::UnsupportedVersion | cmd::CmdError::InternalError => 500,
+        };
+        if resp.detail().is_empty() {
+                Response::error(status, format!("{err:?}"))
+        } else {
+                Response::error(status, resp.detail())
+        }
+}
+
+
+fn lv(bytes: &[u8]) -> Result<Vec<u8>, Response> {
+        let len = u8::try_from(bytes.len())
+                .map_err(|_| Response::bad_request(format!("{} is longer than 255 bytes", String::from_utf8_lossy(bytes))))?;
+        Ok([&[len], bytes].concat())
+}
+
+
+// A database goes by its id, or by its name if that isn't a number
+async fn resolve_database(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db: &str) -> Result<u8, Response> {
+        if let Ok(db_id) = db.parse() {
+                return Ok(db_id);
+        }
+        let val = run(ctl_rc, proto::FAMILY_CTL, proto::OP_CTL_LOOKUP_DATABASE, db.as_bytes()).await?;
+        val.first().copied().ok_or_else(|| Response::error(500, "malformed database lookup"))
+}
+
+
+async fn resolve_filter(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, name: &str) -> Result<u8, Response> {
+        let val = run(ctl_rc, proto::FAMILY_DB, proto::OP_DB_LOOKUP_OBJECT, &[&[db_id], lv(name.as_bytes())?.as_slice()].concat()).await?;
+        match val[..] {
+                [proto::FAMILY_BF, bf_id] => Ok(bf_id),
+                [family, _] => {
+                        let kind = db::ObjectKind::try_from(family).map_or("object", |kind| kind.name());
+                        Err(Response::error(409, format!("{name} is a {kind}, not a bloom filter")))
+                }
+                _ => Err(Response::error(500, "malformed object lookup")),
+        }
+}
+
+
+// Walk a list of id (u8) | name_len (u8) | name entries, each optionally led
+// by a family byte.
+fn named_entries(mut val: &[u8], with_family: bool) -> Vec<(u8, u8, String)> {
+        let mut entries = Vec::new();
+        loop {
+                let (family, rest) = match (with_family, val) {
+                        (false, rest) => (0, rest),
+                        (true, [family, rest @ ..]) => (*family, rest),
+                        (true, []) => { break; }
+                };
+                let [id, name_len, rest @ ..] = rest else {
+                        break;
+                };
+                let Some(name) = rest.get(..*name_len as usize) else {
+                        break;
+                };
+                entries.push((family, *id, String::from_utf8_lossy(name).into_owned()));
+                val = &rest[name.len()..];
+        }
+        entries
+}
+
+
+async fn list_databases(ctl_rc: &Rc<RefCell<ctl::Ctl>>) -> Result<Response, Response> {
+        let val = run(ctl_rc, proto::FAMILY_CTL, proto::OP_CTL_LIST_DATABASES, &[]).await?;
+        let databases: Vec<String> = named_entries(&val, false).into_iter()
+                .map(|(_, db_id, name)| {
+                        let name = if name.is_empty() { "null".to_owned() } else { json_string(&name) };
+                        format!("{{\"id\":{db_id},\"name\":{name}}}")
+                })
+                .collect();
+        Ok(Response::ok(format!("{{\"databases\":[{}]}}", databases.join(","))))
+}
+
+
+async fn list_filters(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8) -> Result<Response, Response> {
+        let val = run(ctl_rc, proto::FAMILY_DB, proto::OP_DB_LIST_OBJECTS, &[db_id]).await?;
+        let filters: Vec<String> = named_entries(&val, true).into_iter()
+                .filter(|(family, _, _)| *family == proto::FAMILY_BF)
+                .map(|(_, bf_id, name)| format!("{{\"id\":{bf_id},\"name\":{}}}", json_string(&name)))
+                .collect();
+        Ok(Response::ok(format!("{{\"filters\":[{}]}}", filters.join(","))))
+}
+
+
+// The body names the filter, and may give its id, its capacity and target
+// false-positive rate, and whether it scales and by which growth factor.
+async fn create_filter(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, body: &Json) -> Result<Response, Response> {
+        let defaults = ScalableBloomFilterParams::default();
+        let Some(Json::String(name)) = body.get("name") else {
+                return Err(Response::bad_request("the filter needs a name"));
+        };
+        if name.len() > db::MAX_NAME_LEN {
+                return Err(Response::bad_request(format!("the name is longer than {} bytes", db::MAX_NAME_LEN)));
+        }
+        let capacity = body.uint_field("capacity", defaults.initial_cpty as u64)?;
+        let error_rate = match body.get("error_rate") {
+                None => defaults.fpr,
+                Some(Json::Number(error_rate)) => *error_rate,
+                Some(_) => { return Err(Response::bad_request("error_rate must be a number")); }
+        };
+        let scalable = match body.get("scalable") {
+                None => false,
+                Some(Json::Bool(scalable)) => *scalable,
+                Some(_) => { return Err(Response::bad_request("scalable must be a boolean")); }
+        };
+        let growth = u8::try_from(body.uint_field("growth", defaults.growth as u64)?)
+                .map_err(|_| Response::bad_request("growth is out of range"))?;
+        let bf_id = match body.get("id") {
+                None => None,
+                Some(_) => Some(u8::try_from(body.uint_field("id", 0)?).map_err(|_| Response::bad_request("id is out of range"))?),
+        };
+
+        // Without an id given, take the first one that is free. The filter
+        // is created and named in one command, which fails if the name is
+        // taken.
+        let bf_id = match bf_id {
+                Some(bf_id) => bf_id,
+                None => {
+                        let ctl = ctl_rc.try_borrow().map_err(|e| Response::error(500, e.to_string()))?;
+                        let db = ctl.db_registry.get(&[db_id])
+                                .ok_or_else(|| Response::error(404, format!("no database {db_id}")))?;
+                        (0..=u8::MAX).find(|bf_id| !db.has_bloom_filter(*bf_id))
+                                .ok_or_else(|| Response::error(409, format!("no bloom filter id left in database {db_id}")))?
+                }
+        };
+        let mut params = vec![bf_id];
+        if scalable {
+                params.extend(capacity.to_le_bytes());
+                params.extend(error_rate.to_le_bytes());
+                params.push(growth);
+                params.extend(defaults.tightening.to_le_bytes());
+        } else {
+                params.push(proto::PARAMS_ESTIMATE);
+                params.extend(capacity.to_le_bytes());
+                params.extend(error_rate.to_le_bytes());
+        }
+        let op = if scalable { proto::OP_DB_NEW_SBF } else { proto::OP_DB_NEW_BF };
+        run(ctl_rc, proto::FAMILY_DB, op, &[&[db_id], lv(&params)?.as_slice(), name.as_bytes()].concat()).await?;
+        Ok(Response {
+                status: 201,
+                body: format!("{{\"id\":{bf_id},\"name\":{}}}", json_string(name)),
+        })
+}
+
+
+// The body gives either a single key or a list of keys
+fn parse_keys(body: &Json) -> Result<(Vec<&str>, bool), Response> {
+        let keys = match (body.get("key"), body.get("keys")) {
+                (Some(Json::String(key)), None) => (vec![key.as_str()], true),
+                (None, Some(Json::Array(keys))) => {
+                        let keys = keys.iter()
+                                .map(|key| if let Json::String(key) = key { Some(key.as_str()) } else { None })
+                                .collect::<Option<Vec<&str>>>()
+                                .ok_or_else(|| Response::bad_request("keys must be strings"))?;
+                        (keys, false)
+                }
+                _ => { return Err(Response::bad_request("the body needs either a key or a list of keys")); }
+        };
+        if let Some(key) = keys.0.iter().find(|key| key.len() >= proto::MAX_BATCH_LEN) {
+                return Err(Response::bad_request(format!("key {key} is longer than {} bytes", proto::MAX_BATCH_LEN - 1)));
+        }
+        Ok(keys)
+}
+
+
+fn batches(keys: &[&str]) -> Result<Vec<Vec<u8>>, Response> {
+        proto::encode_batches(keys).map_err(|e| Response::bad_request(e.to_string()))
+}
+
+
+async fn add_keys(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, bf_id: u8, body: &Json) -> Result<Response, Response> {
+        let (keys, _) = parse_keys(body)?;
+        for batch in batches(&keys)? {
+                run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_ADD_BATCH, &[&[db_id, bf_id], lv(&batch)?.as_slice()].concat()).await?;
+        }
+        Ok(Response::ok(format!("{{\"added\":{}}}", keys.len())))
+}
+
+
+async fn check_keys(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, bf_id: u8, body: &Json) -> Result<Response, Response> {
+        let (keys, single) = parse_keys(body)?;
+        let mut present = Vec::with_capacity(keys.len());
+        for batch in batches(&keys)? {
+                let val = run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_HAS_BATCH, &[&[db_id, bf_id], lv(&batch)?.as_slice()].concat()).await?;
+                present.extend(val.iter().map(|ans| if *ans == proto::TOKEN_TRUE { "true" } else { "false" }));
+        }
+        if single {
+                return Ok(Response::ok(format!("{{\"present\":{}}}", present.first().unwrap_or(&"false"))));
+        }
+        Ok(Response::ok(format!("{{\"present\":[{}]}}", present.join(","))))
+}
+
+
+async fn describe_filter(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, name: &str) -> Result<Response, Response> {
+        let bf_id = resolve_filter(ctl_rc, db_id, name).await?;
+        let val = run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_INFO, &[db_id, bf_id]).await?;
+        let stats: Vec<u64> = val.chunks_exact(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+        let &[capacity, size, slice_cnt, insert_cnt, growth] = &stats[..] else {
+                return Err(Response::error(500, "malformed filter description"));
+        };
+        // A plain filter doesn't grow
+        let growth = if growth == 0 { "null".to_owned() } else { growth.to_string() };
+        Ok(Response::ok(format!(
+                "{{\"id\":{bf_id},\"name\":{},\"capacity\":{capacity},\"size\":{size},\"filters\":{slice_cnt},\"items\":{insert_cnt},\"growth\":{growth}}}",
+                json_string(name)
+        )))
+}
+
+
+/// Quote and escape a string as a JSON string.
+#[must_use]
+pub fn json_string(s: &str) -> String {
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push('"');
+        for c in s.chars() {
+                match c {
+                        '"' => { out.push_str("\\\""); }
+                        '\\' => { out.push_str("\\\\"); }
+                        '\n' => { out.push_str("\\n"); }
+                        '\r' => { out.push_str("\\r"); }
+                        '\t' => { out.push_str("\\t"); }
+                        c if u32::from(c) < 0x20 => { out.push_str(&format!("\\u{:04x}", u32::from(c))); }
+                        c => { out.push(c); }
+                }
+        }
+        out.push('"');
+        out
+}
+
+
+#[derive(Debug, PartialEq)]
+enum Json {
+        Null,
+        Bool(bool),
+        Number(f64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+}
+
+
+impl Json {
+        fn parse(bytes: &[u8]) -> Option<Json> {
+                let mut parser = JsonParser { bytes, pos: 0 };
+                let json = parser.value(0)?;
+                parser.skip_ws();
+                (parser.pos == bytes.len()).then_some(json)
+        }
+
+        fn get(&self, key: &str) -> Option<&Json> {
+                match self {
+                        Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+                        _ => None,
+                }
+        }
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        fn uint_field(&self, key: &str, default: u64) -> Result<u64, Response> {
+                match self.get(key) {
+                        None => Ok(default),
+                        Some(Json::Number(n)) if n.fract() == 0.0 && *n >= 0.0 && *n < u64::MAX as f64 => Ok(*n as u64),
+                        Some(_) => Err(Response::bad_request(format!("{key} must be a non-negative integer"))),
+                }
+        }
+}
+
+
+struct JsonParser<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+}
+
+
+impl JsonParser<'_> {
+        fn skip_ws(&mut self) {
+                while matches!(self.bytes.get(self.pos), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+                        self.pos += 1;
+                }
+        }
+
+        fn eat(&mut self, token: &[u8]) -> bool {
+                self.skip_ws();
+                if self.bytes[self.pos..].starts_with(token) {
+                        self.pos += token.len();
+                        return true;
+                }
+                false
+        }
+
+        fn value(&mut self, depth: usize) -> Option<Json> {
+                if depth > MAX_JSON_DEPTH {
+                        return None;
+                }
+                self.skip_ws();
+                match self.bytes.get(self.pos)? {
+                        b'n' if self.eat(b"null") => Some(Json::Null),
+                        b't' if self.eat(b"true") => Some(Json::Bool(true)),
+                        b'f' if self.eat(b"false") => Some(Json::Bool(false)),
+                        b'"' => self.string().map(Json::String),
+                        b'[' => {
+                                self.pos += 1;
+                                let mut items = Vec::new();
+                                if self.eat(b"]") {
+                                        return Some(Json::Array(items));
+                                }
+                                loop {
+                                        items.push(self.value(depth + 1)?);
+                                        if self.eat(b"]") {
+                                                return Some(Json::Array(items));
+                                        }
+                                        if !self.eat(b",") {
+                                                return None;
+                                        }
+                                }
+                        }
+                        b'{' => {
+                                self.pos += 1;
+                                let mut entries = Vec::new();
+                                if self.eat(b"}") {
+                                        return Some(Json::Object(entries));
+                                }
+                                loop {
+                                        self.skip_ws();
+                                        if self.bytes.get(self.pos) != Some(&b'"') {
+                                                return None;
+                                        }
+                                        let key = self.string()?;
+                                        if !self.eat(b":") {
+                                                return None;
+                                        }
+                                        entries.push((key, self.value(depth + 1)?));
+                                        if self.eat(b"}") {
+                                                return Some(Json::Object(entries));
+                                        }
+                                        if !self.eat(b",") {
+                                                return None;
+                                        }
+                                }
+                        }
+                        b'-' | b'0'..=b'9' => {
+                                let start = self.pos;
+                                while matches!(self.bytes.get(self.pos), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                                        self.pos += 1;
+                                }
+                                let number = std::str::from_utf8(&self.bytes[start..self.pos]).ok()?;
+                                number.parse().ok().map(Json::Number)
+                        }
+                        _ => None,
+                }
+        }
+
+        // A string, starting at its opening quote
+        fn string(&mut self) -> Option<String> {
+                self.pos += 1;
+                let mut out = Vec::new();
+                loop {
+                        let byte = *self.bytes.get(self.pos)?;
+                        self.pos += 1;
+                        match byte {
+                                b'"' => { return String::from_utf8(out).ok(); }
+                                b'\\' => {
+                                        let esc = *self.bytes.get(self.pos)?;
+                                        self.pos += 1;
+                                        let c = match esc {
+                                                b'"' => '"',
+                                                b'\\' => '\\',
+                                                b'/' => '/',
+                                                b'b' => '\u{8}',
+                                                b'f' => '\u{c}',
+                                                b'n' => '\n',
+                                                b'r' => '\r',
+                                                b't' => '\t',
+                                                b'u' => self.unicode_escape()?,
+                                                _ => { return None; }
+                                        };
+                                        out.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                                }
+                                0..=0x1F => { return None; }
+                                _ => { out.push(byte); }
+                        }
+                }
+        }
+
+        // The code point of a \u escape, which takes a pair of escapes
+        // outside the basic multilingual plane
+        fn unicode_escape(&mut self) -> Option<char> {
+                let hi = self.hex4()?;
+                if !(0xD800..0xDC00).contains(&hi) {
+                        return char::from_u32(hi);
+                }
+                if !self.bytes[self.pos..].starts_with(b"\\u") {
+                        return None;
+                }
+                self.pos += 2;
+                let lo = self.hex4()?;
+                if !(0xDC00..0xE000).contains(&lo) {
+                        return None;
+                }
+                char::from_u32(0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00))
+        }
+
+        fn hex4(&mut self) -> Option<u32> {
+                let digits = std::str::from_utf8(self.bytes.get(self.pos..self.pos+4)?).ok()?;
+                self.pos += 4;
+                if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                        return None;
+                }
+                u32::from_str_radix(digits, 16).ok()
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use crate::testing::TempDir;
+
+        #[test]
+        fn test_json() {
+                let json = Json::parse(br#" {"name": "a\"b\u00e9\ud83d\ude00", "keys": [1, -2.5e1, true, null, []]} "#).unwrap();
+                assert!(json.get("name") == Some(&Json::String("a\"b\u{e9}\u{1F600}".into())));
+                assert!(json.get("keys") == Some(&Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Number(-25.0),
+                        Json::Bool(true),
+                        Json::Null,
+                        Json::Array(Vec::new()),
+                ])));
+                for malformed in [&b"{"[..], b"[1,]", b"{\"a\" 1}", b"\"\\x\"", b"1 2", b"\"\\ud83d\""] {
+                        assert!(Json::parse(malformed).is_none());
+                }
+                assert!(json_string("a\"\n\u{1}") == r#""a\"\n\u0001""#);
+        }
+
+        // Read a response and return its status and body
+        async fn read_response(client: &mut tokio::io::DuplexStream) -> (u16, String) {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                        head.push(client.read_u8().await.unwrap());
+                }
+                let head = String::from_utf8(head).unwrap();
+                let status = head[9..12].parse().unwrap();
+                let len = head.lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                let mut body = vec![0; len];
+                client.read_exact(&mut body).await.unwrap();
+                (status, String::from_utf8(body).unwrap())
+        }
+
+        #[tokio::test]
+        async fn test_endpoints() {
+                let dir = TempDir::new("http");
+                let mut ctl = ctl::Ctl::new_blank(dir.config()).unwrap();
+                ctl.create_database(0, Some("main")).unwrap();
+                let ctl_rc = Rc::new(RefCell::new(ctl));
+                let (mut client, server) = tokio::io::duplex(4096);
+
+                let client_task = async move {
+                        let exchanges = [
+                                ("GET", "/databases", "", 200, r#"{"databases":[{"id":0,"name":"main"}]}"#),
+                                ("POST", "/databases/main/filters", r#"{"name":"fruit"}"#, 201, r#"{"id":0,"name":"fruit"}"#),
+                                ("POST", "/databases/0/filters", r#"{"name":"fruit"}"#, 409, r#"{"error":"an object named fruit exists in database 0"}"#),
+                                ("POST", "/databases/0/filters", r#"{"name":"big fruit","scalable":true}"#, 201, r#"{"id":1,"name":"big fruit"}"#),
+                                ("POST", "/databases/0/filters/fruit/add", r#"{"keys":["apple","pear"]}"#, 200, r#"{"added":2}"#),
+                                ("POST", "/databases/0/filters/fruit/check", r#"{"keys":["apple","plum"]}"#, 200, r#"{"present":[true,false]}"#),
+                                ("POST", "/databases/0/filters/big%20fruit/check", r#"{"key":"apple"}"#, 200, r#"{"present":false}"#),
+                                ("GET", "/databases/0/filters", "", 200, r#"{"filters":[{"id":1,"name":"big fruit"},{"id":0,"name":"fruit"}]}"#),
+                                ("GET", "/databases/0/filters/big%20fruit", "", 200,
+                                        r#"{"id":1,"name":"big fruit","capacity":1000,"size":1692,"filters":1,"items":0,"growth":2}"#),
+                                ("GET", "/databases/0/filters/plum", "", 404, r#"{"error":"no object named plum in database 0"}"#),
+                                ("DELETE", "/databases/0/filters", "", 405, r#"{"error":"DELETE is not allowed on /databases/0/filters"}"#),
+                        ];
+                        for (method, path, body, status, expected) in exchanges {
+                                let request = format!("{method} {path} HTTP/1.1\r\nHost: qstra\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+                                client.write_all(request.as_bytes()).await.unwrap();
+                                let (got_status, got) = read_response(&mut client).await;
+                                assert!(got_status == status && got == expected, "{got_status} {got}");
+                        }
+                        client.write_all(b"GET /databases HTTP/1.0\r\n\r\n").await.unwrap();
+                        let mut resp = String::new();
+                        client.read_to_string(&mut resp).await.unwrap();
+                        assert!(resp.contains("Connection: close"));
+                };
+                let (res, ()) = tokio::join!(handle_client(server, ctl_rc), client_task);
+                res.unwrap();
+        }
+}
\ No newline at end of file
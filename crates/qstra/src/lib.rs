@@ -0,0 +1,27 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! The databases, the write-ahead log and the command dispatch behind the
+//! server, and the front ends that serve them.
+//!
+//! The server binary is a thin wrapper that spawns the front ends over a
+//! shared control structure. An application can instead open a data
+//! directory in-process through `embed::Store`, without a server.
+
+
+pub mod cfg;
+pub mod cmd;
+pub mod ctl;
+pub mod db;
+pub mod embed;
+pub mod http;
+pub mod reg;
+pub mod resp;
+pub mod srv;
+pub mod wal;
+
+#[cfg(test)]
+mod testing;
+
+pub use embed::Store;
\ No newline at end of file
@@ -4,16 +4,11 @@
 
 use std::cell::RefCell;
 use std::env;
+use std::future::Future;
 use std::io;
 use std::rc::Rc;
 
-mod cfg;
-mod cmd;
-mod ctl;
-mod db;
-mod reg;
-mod srv;
-mod wal;
+use qstra::{cfg, ctl, http, resp, srv};
 
 
 struct SocketGuard(String);
@@ -27,30 +22,57 @@ impl Drop for SocketGuard {
 }
 
 
-async fn serve_local(
-        addr: String,
+trait Listener {
+        type Stream;
+
+        fn accept(&self) -> impl Future<Output = io::Result<Self::Stream>>;
+}
+
+
+impl Listener for tokio::net::UnixListener {
+        type Stream = tokio::net::UnixStream;
+
+        async fn accept(&self) -> io::Result<Self::Stream> {
+                Ok(self.accept().await?.0)
+        }
+}
+
+
+impl Listener for tokio::net::TcpListener {
+        type Stream = tokio::net::TcpStream;
+
+        async fn accept(&self) -> io::Result<Self::Stream> {
+                Ok(self.accept().await?.0)
+        }
+}
+
+
+// Accept connections until shut down, handing each to a task of its own
+async fn serve<L, F>(
+        listener: L,
+        what: &str,
+        handle_client: fn(L::Stream, Rc<RefCell<ctl::Ctl>>) -> F,
         ctl_rc: Rc<RefCell<ctl::Ctl>>,
         mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
 ) -> io::Result<()>
+where
+        L: Listener,
+        F: Future<Output = io::Result<()>> + 'static,
 {
-        let _ = std::fs::remove_file(&addr);
-        let _guard = SocketGuard(addr.clone());
-        let listener = tokio::net::UnixListener::bind(&addr)?;
-
         loop {
                 tokio::select! {
                         biased;
                         _ = shutdown_rx.recv() => {
                                 break;
                         }
-                        res = listener.accept() => {
+                        res = Listener::accept(&listener) => {
                                 match res {
-                                        Ok((stream, _unix_addr)) => {
+                                        Ok(stream) => {
                                                 let ctl_clone = Rc::clone(&ctl_rc);
-                                                tokio::task::spawn_local(srv::handle_client(stream, ctl_clone));
+                                                tokio::task::spawn_local(handle_client(stream, ctl_clone));
                                         }
                                         Err(e) => {
-                                                eprintln!("Error accepting a local connection: {e}");
+                                                eprintln!("Error accepting {what} connection: {e}");
                                         }
                                 }
                         }
@@ -61,35 +83,31 @@ async fn serve_local(
 }
 
 
-async fn serve_network(
+async fn serve_local(
         addr: String,
         ctl_rc: Rc<RefCell<ctl::Ctl>>,
-        mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+        shutdown_rx: tokio::sync::broadcast::Receiver<()>,
 ) -> io::Result<()>
 {
-        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        let _ = std::fs::remove_file(&addr);
+        let _guard = SocketGuard(addr.clone());
+        let listener = tokio::net::UnixListener::bind(&addr)?;
+        serve(listener, "a local", srv::handle_client, ctl_rc, shutdown_rx).await
+}
 
-        loop {
-                tokio::select! {
-                        biased;
-                        _ = shutdown_rx.recv() => {
-                                break;
-                        }
-                        res = listener.accept() => {
-                                match res {
-                                        Ok((stream, _peer_addr)) => {
-                                                let ctl_clone = Rc::clone(&ctl_rc);
-                                                tokio::task::spawn_local(srv::handle_client(stream, ctl_clone));
-                                        }
-                                        Err(e) => {
-                                                eprintln!("Error accepting a network connection: {e}");
-                                        }
-                                }
-                        }
-                }
-        }
 
-        Ok(())
+async fn serve_network<F>(
+        addr: String,
+        what: &str,
+        handle_client: fn(tokio::net::TcpStream, Rc<RefCell<ctl::Ctl>>) -> F,
+        ctl_rc: Rc<RefCell<ctl::Ctl>>,
+        shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+) -> io::Result<()>
+where
+        F: Future<Output = io::Result<()>> + 'static,
+{
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        serve(listener, what, handle_client, ctl_rc, shutdown_rx).await
 }
 
 
@@ -125,6 +143,10 @@ async fn main() -> io::Result<()> {
                                 rctl.config().sock_addr.clone(),
                                 rctl.config().listen_network,
                                 rctl.config().inet_addr.clone(),
+                                rctl.config().listen_resp,
+                                rctl.config().resp_addr.clone(),
+                                rctl.config().listen_http,
+                                rctl.config().http_addr.clone(),
                         )
                 };
 
@@ -132,6 +154,10 @@ async fn main() -> io::Result<()> {
                 let local_addr = listener_cfg.1;
                 let listen_network = listener_cfg.2;
                 let network_addr = listener_cfg.3;
+                let listen_resp = listener_cfg.4;
+                let resp_addr = listener_cfg.5;
+                let listen_http = listener_cfg.6;
+                let http_addr = listener_cfg.7;
 
                 let mut handles = Vec::new();
 
@@ -147,9 +173,26 @@ async fn main() -> io::Result<()> {
                         let ctl_clone = Rc::clone(&pctl);
                         let shutdown_rx = shutdown_tx.subscribe();
                         handles.push(
-                                tokio::task::spawn_local(serve_network(network_addr, ctl_clone, shutdown_rx))
+                                tokio::task::spawn_local(serve_network(network_addr, "a network", srv::handle_client, ctl_clone, shutdown_rx))
+                        );
+                }
+
+                if listen_resp {
+                        let ctl_clone = Rc::clone(&pctl);
+                        let shutdown_rx = shutdown_tx.subscribe();
+                        handles.push(
+                                tokio::task::spawn_local(serve_network(resp_addr, "a RESP", resp::handle_client, ctl_clone, shutdown_rx))
                         );
                 }
+
+                if listen_http {
+                        let ctl_clone = Rc::clone(&pctl);
+                        let shutdown_rx = shutdown_tx.subscribe();
+                        handles.push(
+                                tokio::task::spawn_local(serve_network(http_addr, "an HTTP", http::handle_client, ctl_clone, shutdown_rx))
+                        );
+                }
+
                 if handles.is_empty() {
                         return Err(io::Error::new(io::ErrorKind::InvalidInput, "No listeners configured"));
                 }
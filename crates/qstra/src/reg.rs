@@ -52,6 +52,19 @@ impl<T> Registry<T> {
                 Ok(())
         }
 
+        // Remove an item, keeping the remaining items in insertion order so
+        // that snapshots stay stable.
+        pub fn remove(&mut self, id: &[u8]) -> Option<T> {
+                let idx = self.items_index.remove(id)?;
+                let item = self.items.remove(idx);
+                for val in self.items_index.values_mut() {
+                        if *val > idx {
+                                *val -= 1;
+                        }
+                }
+                Some(item)
+        }
+
         pub fn count(&self) -> usize {
                 self.items.len()
         }
@@ -60,7 +73,6 @@ impl<T> Registry<T> {
                 &self.items
         }
 
-        #[expect(dead_code)]
         pub fn list_mut(&mut self) -> &mut Vec<T> {
                 &mut self.items
         }
@@ -69,4 +81,28 @@ impl<T> Registry<T> {
                 self.items = Vec::new();
                 self.items_index = HashMap::new();
         }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_remove() {
+                let mut reg = Registry::<u32>::new_blank();
+                for id in 0..5u8 {
+                        reg.add(u32::from(id) * 10, &[id]).unwrap();
+                }
+                assert!(reg.remove(&[1]) == Some(10));
+                assert!(reg.remove(&[1]).is_none());
+                assert!(reg.count() == 4);
+                assert!(reg.list() == &vec![0, 20, 30, 40]);
+                for id in [0u8, 2, 3, 4] {
+                        assert!(reg.get(&[id]) == Some(&(u32::from(id) * 10)));
+                }
+                reg.add(11, &[1]).unwrap();
+                assert!(reg.get(&[1]) == Some(&11));
+                assert!(reg.get(&[4]) == Some(&40));
+        }
 }
\ No newline at end of file
@@ -0,0 +1,626 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Serve the Redis serialization protocol (RESP).
+//!
+//! A connection speaks RESP2 until the client switches to RESP3 with HELLO.
+//! The RedisBloom commands are mapped onto the bloom filter commands of the
+//! native protocol: a key names a bloom filter in the selected database,
+//! database 0 unless SELECT says otherwise, and a filter added to before it
+//! was reserved is created with the defaults of RedisBloom.
+
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use qstra_prob::sbf::ScalableBloomFilterParams;
+use qstra_proto::frame as proto;
+
+use crate::cfg;
+use crate::cmd;
+use crate::ctl;
+use crate::db;
+use crate::srv;
+
+
+const READ_CHUNK_SZ: usize = 4096;
+const MAX_ARG_CNT: usize = 1 << 20;
+// The most a bulk string header, "$" and a length, can take before its CRLF
+const MAX_BULK_HEADER_LEN: usize = 32;
+
+// The name of an object is stored after its family and id in a single LV
+const MAX_KEY_LEN: usize = db::MAX_NAME_LEN - 2;
+
+const DEFAULT_ERROR_RATE: f64 = 0.01;
+const DEFAULT_CAPACITY: u64 = 100;
+const DEFAULT_EXPANSION: u8 = 2;
+
+
+enum Reply {
+        Status(&'static str),
+        Error(String),
+        Int(i64),
+        Bool(bool),
+        Bulk(Vec<u8>),
+        Null,
+        Array(Vec<Reply>),
+        Map(Vec<(&'static str, Reply)>),
+}
+
+
+impl Reply {
+        fn error(msg: impl std::fmt::Display) -> Self {
+                Reply::Error(format!("ERR {msg}"))
+        }
+
+        // The types RESP2 lacks are sent as their closest RESP2 equivalent
+        fn encode_into(&self, buf: &mut Vec<u8>, resp3: bool) {
+                match self {
+                        Reply::Status(msg) => { buf.extend(format!("+{msg}\r\n").as_bytes()); }
+                        Reply::Error(msg) => { buf.extend(format!("-{msg}\r\n").as_bytes()); }
+                        Reply::Int(n) => { buf.extend(format!(":{n}\r\n").as_bytes()); }
+                        Reply::Bool(b) if resp3 => { buf.extend(if *b { b"#t\r\n" } else { b"#f\r\n" }); }
+                        Reply::Bool(b) => { buf.extend(if *b { b":1\r\n" } else { b":0\r\n" }); }
+                        Reply::Bulk(bytes) => {
+                                buf.extend(format!("${}\r\n", bytes.len()).as_bytes());
+                                buf.extend(bytes);
+                                buf.extend(b"\r\n");
+                        }
+                        Reply::Null if resp3 => { buf.extend(b"_\r\n"); }
+                        Reply::Null => { buf.extend(b"$-1\r\n"); }
+                        Reply::Array(items) => {
+                                buf.extend(format!("*{}\r\n", items.len()).as_bytes());
+                                for item in items {
+                                        item.encode_into(buf, resp3);
+                                }
+                        }
+                        Reply::Map(entries) => {
+                                if resp3 {
+                                        buf.extend(format!("%{}\r\n", entries.len()).as_bytes());
+                                } else {
+                                        buf.extend(format!("*{}\r\n", 2*entries.len()).as_bytes());
+                                }
+                                for (key, val) in entries {
+                                        Reply::Bulk(key.as_bytes().to_vec()).encode_into(buf, resp3);
+                                        val.encode_into(buf, resp3);
+                                }
+                        }
+                }
+        }
+}
+
+
+struct Session {
+        db_id: u8,
+        resp3: bool,
+}
+
+
+// A request parsed as far as it has arrived. The arguments parsed so far are
+// kept, and their bytes consumed, so every read only parses what it brought.
+#[derive(Default)]
+struct Parser {
+        args: Vec<Vec<u8>>,
+        // The arguments yet to come of a multibulk request
+        pending: usize,
+        // The bytes the request took so far
+        size: usize,
+}
+
+
+pub async fn handle_client<S>(mut stream: S, ctl_rc: Rc<RefCell<ctl::Ctl>>) -> io::Result<()>
+where S: AsyncRead + AsyncWrite + Unpin,
+{
+        let max_request_sz = ctl_rc.try_borrow()
+                .map_or(cfg::DEFAULT_MAX_FRAME_SZ, |c| c.config().max_frame_sz);
+        let mut session = Session { db_id: 0, resp3: false };
+        let mut parser = Parser::default();
+        let mut chunk = [0; READ_CHUNK_SZ];
+        // The bytes received but not yet parsed
+        let mut inbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+        // The replies not yet written back
+        let mut outbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+
+        loop {
+                let read_cnt = match stream.read(&mut chunk).await {
+                        Ok(0) => {
+                                println!("RESP client connection closed.");
+                                return Ok(());
+                        }
+                        Ok(n) => n,
+                        Err(e) => {
+                                eprintln!("Error reading from RESP client stream: {e}");
+                                return Ok(());
+                        }
+                };
+                inbuf.extend_from_slice(&chunk[..read_cnt]);
+
+                // Serve every complete request in order, and write the
+                // replies back together.
+                let mut start = 0;
+                let mut quit = false;
+                loop {
+                        let (len, args) = match parser.parse(&inbuf[start..], max_request_sz) {
+                                Ok(parsed) => parsed,
+                                Err(e) => {
+                                        // There is no telling where the next
+                                        // request starts, so give up on the client
+                                        Reply::error(format!("Protocol error: {e}")).encode_into(&mut outbuf, session.resp3);
+                                        quit = true;
+                                        break;
+                                }
+                        };
+                        start += len;
+                        let Some(args) = args else {
+                                break;
+                        };
+                        if args.is_empty() {
+                                continue;
+                        }
+                        if args[0].eq_ignore_ascii_case(b"QUIT") {
+                                Reply::Status("OK").encode_into(&mut outbuf, session.resp3);
+                                quit = true;
+                                break;
+                        }
+                        let reply = match execute(&ctl_rc, &mut session, &args).await {
+                                Ok(reply) | Err(reply) => reply,
+                        };
+                        reply.encode_into(&mut outbuf, session.resp3);
+                }
+                inbuf.drain(..start);
+
+                if !outbuf.is_empty() {
+                        if stream.write_all(&outbuf).await.is_err() {
+                                eprintln!("Error responding to RESP client stream.");
+                                return Ok(());
+                        }
+                        outbuf.clear();
+                }
+                if quit {
+                        return Ok(());
+                }
+        }
+}
+
+
+impl Parser {
+        // Parse as much of the request at the start of the buffer as has
+        // arrived, and return the number of bytes consumed along with the
+        // arguments once the request is complete. A request may take up to
+        // max_request_sz bytes in all. Clients send arrays of bulk strings,
+        // but a line of words as typed into telnet is accepted too.
+        fn parse(&mut self, buf: &[u8], max_request_sz: usize) -> io::Result<(usize, Option<Vec<Vec<u8>>>)> {
+                let malformed = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+                let mut pos = 0;
+                if self.pending == 0 {
+                        let Some((line, next)) = read_line(buf, 0) else {
+                                if buf.len() > max_request_sz {
+                                        return Err(malformed("too big request"));
+                                }
+                                return Ok((0, None));
+                        };
+                        if next > max_request_sz {
+                                return Err(malformed("too big request"));
+                        }
+                        if line.first() != Some(&b'*') {
+                                let args = line.split(u8::is_ascii_whitespace)
+                                        .filter(|word| !word.is_empty())
+                                        .map(<[u8]>::to_vec)
+                                        .collect();
+                                return Ok((next, Some(args)));
+                        }
+                        // The count is only trusted as far as the arguments
+                        // arrive, each taking some bytes of the request
+                        let arg_cnt = parse_int(&line[1..])
+                                .ok()
+                                .filter(|n| *n <= MAX_ARG_CNT as i64)
+                                .ok_or_else(|| malformed("invalid multibulk length"))?;
+                        self.pending = usize::try_from(arg_cnt).unwrap_or(0);
+                        self.size = next;
+                        pos = next;
+                }
+
+                while self.pending > 0 {
+                        let Some((line, next)) = read_line(buf, pos) else {
+                                if buf.len() - pos > MAX_BULK_HEADER_LEN {
+                                        return Err(malformed("invalid bulk length"));
+                                }
+                                return Ok((pos, None));
+                        };
+                        if line.first() != Some(&b'$') {
+                                return Err(malformed("expected '$'"));
+                        }
+                        let end = parse_int(&line[1..])
+                                .ok()
+                                .and_then(|n| usize::try_from(n).ok())
+                                .and_then(|len| next.checked_add(len + 2))
+                                .ok_or_else(|| malformed("invalid bulk length"))?;
+                        if self.size + end - pos > max_request_sz {
+                                return Err(malformed("too big request"));
+                        }
+                        let Some(arg) = buf.get(next..end) else {
+                                return Ok((pos, None));
+                        };
+                        let Some(arg) = arg.strip_suffix(b"\r\n") else {
+                                return Err(malformed("expected CRLF after bulk string"));
+                        };
+                        self.args.push(arg.to_vec());
+                        self.size += end - pos;
+                        self.pending -= 1;
+                        pos = end;
+                }
+                self.size = 0;
+                Ok((pos, Some(std::mem::take(&mut self.args))))
+        }
+}
+
+
+// The line starting at pos without its line ending, and the position after it
+fn read_line(buf: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+        let len = buf.get(pos..)?.iter().position(|b| *b == b'\n')?;
+        let line = &buf[pos..pos+len];
+        Some((line.strip_suffix(b"\r").unwrap_or(line), pos + len + 1))
+}
+
+
+fn parse_int(bytes: &[u8]) -> Result<i64, Reply> {
+        std::str::from_utf8(bytes).ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| Reply::error("value is not an integer or out of range"))
+}
+
+
+fn parse_float(bytes: &[u8]) -> Result<f64, Reply> {
+        std::str::from_utf8(bytes).ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| Reply::error("value is not a valid float"))
+}
+
+
+async fn execute(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &mut Session, args: &[Vec<u8>]) -> Result<Reply, Reply> {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let args = &args[1..];
+        match (name.as_str(), args.len()) {
+                ("PING", 0) => Ok(Reply::Status("PONG")),
+                ("PING" | "ECHO", 1) => Ok(Reply::Bulk(args[0].clone())),
+                ("HELLO", _) => hello(session, args),
+                ("SELECT", 1) => select(ctl_rc, session, &args[0]),
+                // Clients look up the commands of a server for hints only
+                ("COMMAND", _) => Ok(Reply::Array(Vec::new())),
+                ("BF.RESERVE", 3..) => bf_reserve(ctl_rc, session, args).await,
+                ("BF.ADD", 2) => bf_add(ctl_rc, session, &args[0], &args[1..]).await?.pop().ok_or(Reply::Null),
+                ("BF.MADD", 2..) => Ok(Reply::Array(bf_add(ctl_rc, session, &args[0], &args[1..]).await?)),
+                ("BF.EXISTS", 2) => bf_exists(ctl_rc, session, &args[0], &args[1..]).await?.pop().ok_or(Reply::Null),
+                ("BF.MEXISTS", 2..) => Ok(Reply::Array(bf_exists(ctl_rc, session, &args[0], &args[1..]).await?)),
+                ("BF.INFO", 1 | 2) => bf_info(ctl_rc, session, &args[0], args.get(1)).await,
+                ("PING" | "ECHO" | "SELECT" | "BF.RESERVE" | "BF.ADD" | "BF.MADD" | "BF.EXISTS" | "BF.MEXISTS" | "BF.INFO", _) => {
+                        Err(Reply::error(format!("wrong number of arguments for '{}' command", name.to_lowercase())))
+                }
+                _ => Err(Reply::error(format!("unknown command '{name}'"))),
+        }
+}
+
+
+// HELLO [protover] switches the protocol of the connection; any options
+// following the version are accepted and ignored.
+fn hello(session: &mut Session, args: &[Vec<u8>]) -> Result<Reply, Reply> {
+        if let Some(protover) = args.first() {
+                session.resp3 = match parse_int(protover) {
+                        Ok(2) => false,
+                        Ok(3) => true,
+                        _ => { return Err(Reply::Error("NOPROTO unsupported protocol version".into())); }
+                };
+        }
+        Ok(Reply::Map(vec![
+                ("server", Reply::Bulk(b"qstra".to_vec())),
+                ("version", Reply::Bulk(env!("CARGO_PKG_VERSION").as_bytes().to_vec())),
+                ("proto", Reply::Int(if session.resp3 { 3 } else { 2 })),
+                ("mode", Reply::Bulk(b"standalone".to_vec())),
+                ("role", Reply::Bulk(b"master".to_vec())),
+                ("modules", Reply::Array(Vec::new())),
+        ]))
+}
+
+
+fn select(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &mut Session, db_id: &[u8]) -> Result<Reply, Reply> {
+        let db_id = parse_int(db_id)?;
+        let exists = |db_id: u8| with_db(ctl_rc, db_id, |_| Ok(())).is_ok();
+        match u8::try_from(db_id) {
+                Ok(db_id) if exists(db_id) => {
+                        session.db_id = db_id;
+                        Ok(Reply::Status("OK"))
+                }
+                _ => Err(Reply::error("DB index is out of range")),
+        }
+}
+
+
+fn with_db<T>(ctl_rc: &Rc<RefCell<ctl::Ctl>>, db_id: u8, f: impl FnOnce(&db::Database) -> Result<T, Reply>) -> Result<T, Reply> {
+        let ctl = ctl_rc.try_borrow().map_err(Reply::error)?;
+        let db = ctl.db_registry.get(&[db_id]).ok_or_else(|| Reply::error(format!("no database {db_id}")))?;
+        f(db)
+}
+
+
+// The id of the bloom filter a key names, if there is one
+fn lookup(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, key: &[u8]) -> Result<Option<u8>, Reply> {
+        if key.len() > MAX_KEY_LEN {
+                return Err(Reply::error(format!("key is longer than {MAX_KEY_LEN} bytes")));
+        }
+        let name = db::parse_name(key).map_err(|_| Reply::error("key is not a valid name"))?;
+        with_db(ctl_rc, session.db_id, |db| match db.lookup(name) {
+                None => Ok(None),
+                Some((db::ObjectKind::BloomFilter, bf_id)) => Ok(Some(bf_id)),
+                Some(_) => Err(Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())),
+        })
+}
+
+
+// Create a bloom filter named after the key, scalable by the given growth
+// factor or plain if there is none.
+async fn create(
+        ctl_rc: &Rc<RefCell<ctl::Ctl>>,
+        session: &Session,
+        key: &[u8],
+        error_rate: f64,
+        capacity: u64,
+        expansion: Option<u8>
+) -> Result<u8, Reply>
+{
+        let bf_id = with_db(ctl_rc, session.db_id, |db| {
+                (0..=u8::MAX).find(|bf_id| !db.has_bloom_filter(*bf_id))
+                        .ok_or_else(|| Reply::error("no bloom filter id left in the database"))
+        })?;
+
+        let mut params = vec![bf_id];
+        let op = match expansion {
+                Some(growth) => {
+                        params.extend(capacity.to_le_bytes());
+                        params.extend(error_rate.to_le_bytes());
+                        params.push(growth);
+                        params.extend(ScalableBloomFilterParams::default().tightening.to_le_bytes());
+                        proto::OP_DB_NEW_SBF
+                }
+                None => {
+                        params.push(proto::PARAMS_ESTIMATE);
+                        params.extend(capacity.to_le_bytes());
+                        params.extend(error_rate.to_le_bytes());
+                        proto::OP_DB_NEW_BF
+                }
+        };
+        // The filter is named as it is created
+        run(ctl_rc, proto::FAMILY_DB, op, &[&[session.db_id], lv(&params)?.as_slice(), key].concat()).await?;
+        Ok(bf_id)
+}
+
+
+// BF.RESERVE key error_rate capacity [EXPANSION expansion] [NONSCALING]
+async fn bf_reserve(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, args: &[Vec<u8>]) -> Result<Reply, Reply> {
+        let error_rate = parse_float(&args[1])?;
+        if !(error_rate > 0.0 && error_rate < 1.0) {
+                return Err(Reply::error("(0 < error rate range < 1)"));
+        }
+        let capacity = parse_int(&args[2])?;
+        let capacity = u64::try_from(capacity).ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| Reply::error("(capacity should be larger than 0)"))?;
+
+        let mut expansion = Some(DEFAULT_EXPANSION);
+        let mut opts = args[3..].iter();
+        while let Some(opt) = opts.next() {
+                if opt.eq_ignore_ascii_case(b"NONSCALING") {
+                        expansion = None;
+                } else if opt.eq_ignore_ascii_case(b"EXPANSION") {
+                        let growth = parse_int(opts.next().ok_or_else(|| Reply::error("no expansion given"))?)?;
+                        let max = ScalableBloomFilterParams::MAX_GROWTH;
+                        let growth = usize::try_from(growth).ok()
+                                .filter(|growth| (1..=max).contains(growth))
+                                .and_then(|growth| u8::try_from(growth).ok())
+                                .ok_or_else(|| Reply::error(format!("expansion should be between 1 and {max}")))?;
+                        expansion = expansion.map(|_| growth);
+                } else {
+                        return Err(Reply::error(format!("unknown option '{}'", String::from_utf8_lossy(opt))));
+                }
+        }
+
+        if lookup(ctl_rc, session, &args[0])?.is_some() {
+                return Err(Reply::error("item exists"));
+        }
+        create(ctl_rc, session, &args[0], error_rate, capacity, expansion).await?;
+        Ok(Reply::Status("OK"))
+}
+
+
+// Add the items, and tell for each whether it was new to the filter. Only
+// the new items are added, so that the filter counts each item once.
+async fn bf_add(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, key: &[u8], items: &[Vec<u8>]) -> Result<Vec<Reply>, Reply> {
+        let bf_id = match lookup(ctl_rc, session, key)? {
+                Some(bf_id) => bf_id,
+                None => create(ctl_rc, session, key, DEFAULT_ERROR_RATE, DEFAULT_CAPACITY, Some(DEFAULT_EXPANSION)).await?,
+        };
+        let found = has_batch(ctl_rc, session, bf_id, items).await?;
+        let mut new_items: Vec<&Vec<u8>> = Vec::new();
+        let mut added = Vec::with_capacity(items.len());
+        for (item, found) in items.iter().zip(found) {
+                let new = !found && !new_items.contains(&item);
+                if new {
+                        new_items.push(item);
+                }
+                added.push(Reply::Bool(new));
+        }
+        for batch in batches(&new_items)? {
+                run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_ADD_BATCH, &[&[session.db_id, bf_id], lv(&batch)?.as_slice()].concat()).await?;
+        }
+        Ok(added)
+}
+
+
+async fn bf_exists(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, key: &[u8], items: &[Vec<u8>]) -> Result<Vec<Reply>, Reply> {
+        let Some(bf_id) = lookup(ctl_rc, session, key)? else {
+                return Ok(items.iter().map(|_| Reply::Bool(false)).collect());
+        };
+        Ok(has_batch(ctl_rc, session, bf_id, items).await?.into_iter().map(Reply::Bool).collect())
+}
+
+
+async fn has_batch(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, bf_id: u8, items: &[Vec<u8>]) -> Result<Vec<bool>, Reply> {
+        let mut found = Vec::with_capacity(items.len());
+        for batch in batches(items)? {
+                let val = run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_HAS_BATCH, &[&[session.db_id, bf_id], lv(&batch)?.as_slice()].concat()).await?;
+                found.extend(val.iter().map(|ans| *ans == proto::TOKEN_TRUE));
+        }
+        Ok(found)
+}
+
+
+// Pack the items into as few batches as fit in an LV each
+fn batches<T: AsRef<[u8]>>(items: &[T]) -> Result<Vec<Vec<u8>>, Reply> {
+        proto::encode_batches(items)
+                .map_err(|_| Reply::error(format!("item is longer than {} bytes", proto::MAX_BATCH_LEN - 1)))
+}
+
+
+// BF.INFO key [CAPACITY | SIZE | FILTERS | ITEMS | EXPANSION]
+async fn bf_info(ctl_rc: &Rc<RefCell<ctl::Ctl>>, session: &Session, key: &[u8], field: Option<&Vec<u8>>) -> Result<Reply, Reply> {
+        let Some(bf_id) = lookup(ctl_rc, session, key)? else {
+                return Err(Reply::error("not found"));
+        };
+        let val = run(ctl_rc, proto::FAMILY_BF, proto::OP_BF_INFO, &[session.db_id, bf_id]).await?;
+        let mut stats = val.chunks_exact(8).map(|bytes| {
+                i64::try_from(u64::from_le_bytes(bytes.try_into().unwrap())).unwrap_or(i64::MAX)
+        });
+        let mut next = || stats.next().ok_or_else(|| Reply::error("malformed filter description"));
+        let (capacity, size, filter_cnt, item_cnt, expansion) = (next()?, next()?, next()?, next()?, next()?);
+        // A plain filter doesn't grow
+        let expansion = if expansion == 0 { Reply::Null } else { Reply::Int(expansion) };
+
+        let Some(field) = field else {
+                return Ok(Reply::Map(vec![
+                        ("Capacity", Reply::Int(capacity)),
+                        ("Size", Reply::Int(size)),
+                        ("Number of filters", Reply::Int(filter_cnt)),
+                        ("Number of items inserted", Reply::Int(item_cnt)),
+                        ("Expansion rate", expansion),
+                ]));
+        };
+        let stat = match String::from_utf8_lossy(field).to_uppercase().as_str() {
+                "CAPACITY" => Reply::Int(capacity),
+                "SIZE" => Reply::Int(size),
+                "FILTERS" => Reply::Int(filter_cnt),
+                "ITEMS" => Reply::Int(item_cnt),
+                "EXPANSION" => expansion,
+                _ => { return Err(Reply::error("invalid information value")); }
+        };
+        Ok(Reply::Array(vec![stat]))
+}
+
+
+fn lv(bytes: &[u8]) -> Result<Vec<u8>, Reply> {
+        let len = u8::try_from(bytes.len()).map_err(|_| Reply::error("value is longer than 255 bytes"))?;
+        Ok([&[len], bytes].concat())
+}
+
+
+// Run a command of the native protocol, logging it like any other, and
+// return the value of its response.
+async fn run(ctl_rc: &Rc<RefCell<ctl::Ctl>>, family: u8, op: u8, val: &[u8]) -> Result<Vec<u8>, Reply> {
+        let resp = srv::execute_request(ctl_rc, family, op, val).await.map_err(Reply::error)?;
+        match resp.status() {
+                cmd::CmdResponseCode::Success => Ok(resp.value().to_vec()),
+                cmd::CmdResponseCode::Error(cmd::CmdError::ReadOnly) => {
+                        Err(Reply::Error(format!("READONLY {}", resp.detail())))
+                }
+                cmd::CmdResponseCode::Error(err) if resp.detail().is_empty() => Err(Reply::error(format!("{err:?}"))),
+                cmd::CmdResponseCode::Error(_) => Err(Reply::error(resp.detail())),
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use crate::testing::TempDir;
+
+        #[test]
+        fn test_parse_request() {
+                // Arriving a byte at a time, the request is parsed an argument
+                // at a time
+                let request = b"*3\r\n$6\r\nBF.ADD\r\n$3\r\nkey\r\n$4\r\nitem\r\n";
+                let mut parser = Parser::default();
+                let mut inbuf = Vec::new();
+                let mut parsed = None;
+                for b in request {
+                        assert!(parsed.is_none());
+                        inbuf.push(*b);
+                        let (len, args) = parser.parse(&inbuf, 64).unwrap();
+                        inbuf.drain(..len);
+                        parsed = args;
+                }
+                assert!(parsed.unwrap() == [b"BF.ADD".to_vec(), b"key".to_vec(), b"item".to_vec()]);
+                assert!(inbuf.is_empty());
+
+                let (len, args) = Parser::default().parse(b"PING  hi\r\nPING", 64).unwrap();
+                assert!(args.unwrap() == [b"PING".to_vec(), b"hi".to_vec()] && len == 10);
+
+                // The request is limited as a whole, whatever its counts say
+                assert!(Parser::default().parse(b"*1\r\n$100\r\n", 64).is_err());
+                let mut parser = Parser::default();
+                assert!(parser.parse(b"*1048576\r\n$20\r\naaaaaaaaaaaaaaaaaaaa\r\n", 64).unwrap() == (37, None));
+                assert!(parser.parse(b"$20\r\naaaaaaaaaaaaaaaaaaaa\r\n$1\r\n", 64).is_err());
+                assert!(Parser::default().parse(&[b'a'; 65], 64).is_err());
+        }
+
+        #[tokio::test]
+        async fn test_bloom_commands() {
+                let dir = TempDir::new("resp");
+                let mut ctl = ctl::Ctl::new_blank(dir.config()).unwrap();
+                ctl.create_database(0, None).unwrap();
+                let ctl_rc = Rc::new(RefCell::new(ctl));
+                let (mut client, server) = tokio::io::duplex(4096);
+
+                let client_task = async move {
+                        let request = |line: &str, reply: &str| {
+                                let mut bytes = format!("*{}\r\n", line.split(' ').count());
+                                for arg in line.split(' ') {
+                                        bytes += &format!("${}\r\n{arg}\r\n", arg.len());
+                                }
+                                (bytes, reply.to_owned())
+                        };
+                        let exchanges = [
+                                request("BF.ADD fruit apple", ":1\r\n"),
+                                request("BF.ADD fruit apple", ":0\r\n"),
+                                request("BF.MEXISTS fruit apple pear", "*2\r\n:1\r\n:0\r\n"),
+                                request("BF.MADD fruit pear pear apple", "*3\r\n:1\r\n:0\r\n:0\r\n"),
+                                request("BF.RESERVE fruit 0.01 100", "-ERR item exists\r\n"),
+                                request("BF.RESERVE exact 0.01 1000 NONSCALING", "+OK\r\n"),
+                                request("BF.INFO exact EXPANSION", "*1\r\n$-1\r\n"),
+                                request("BF.INFO fruit ITEMS", "*1\r\n:2\r\n"),
+                                request("HELLO 3", ""),
+                                request("BF.EXISTS exact apple", "#f\r\n"),
+                                request("SELECT 1", "-ERR DB index is out of range\r\n"),
+                        ];
+                        for (bytes, reply) in exchanges {
+                                client.write_all(bytes.as_bytes()).await.unwrap();
+                                if reply.is_empty() {
+                                        let mut head = [0; 3];
+                                        client.read_exact(&mut head).await.unwrap();
+                                        assert!(&head == b"%6\r");
+                                        let mut rest = vec![0; 1024];
+                                        let n = client.read(&mut rest).await.unwrap();
+                                        assert!(rest[..n].ends_with(b"$7\r\nmodules\r\n*0\r\n"));
+                                        continue;
+                                }
+                                let mut got = vec![0; reply.len()];
+                                client.read_exact(&mut got).await.unwrap();
+                                assert!(got == reply.as_bytes(), "{}", String::from_utf8_lossy(&got));
+                        }
+                        client.write_all(b"QUIT\r\n").await.unwrap();
+                };
+                let (res, ()) = tokio::join!(handle_client(server, ctl_rc), client_task);
+                res.unwrap();
+        }
+}
\ No newline at end of file
@@ -5,26 +5,44 @@
 //! Define the server-side logic.
 
 
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::io;
 use std::rc::Rc;
 
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use qstra_proto::frame as proto;
+
+use crate::cfg;
 use crate::cmd;
 use crate::ctl;
 
 
-const MAX_BUF_SZ: usize = 2048;
+const READ_CHUNK_SZ: usize = 4096;
+// The size past which pending responses are written back before the rest of
+// the batch is served
+const MAX_BATCH_SZ: usize = 16*READ_CHUNK_SZ;
 
 
 pub async fn handle_client<S>(mut stream: S, ctl_rc: Rc<RefCell<ctl::Ctl>>) -> io::Result<()>
 where S: AsyncRead + AsyncWrite + Unpin,
 {
-        let mut inbuf = [0; MAX_BUF_SZ];
+        let max_frame_sz = ctl_rc.try_borrow()
+                .map_or(cfg::DEFAULT_MAX_FRAME_SZ, |c| c.config().max_frame_sz);
+        let mut chunk = [0; READ_CHUNK_SZ];
+        // The bytes received but not yet framed
+        let mut inbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+        // The responses not yet written back
+        let mut outbuf = Vec::<u8>::with_capacity(READ_CHUNK_SZ);
+        // The bytes yet to arrive of a frame that was rejected as too large
+        let mut discard = 0;
+        // The protocol version the responses are framed in, which only a
+        // handshake changes
+        let mut version = proto::PROTOCOL_V1;
 
         loop {
-                let read_cnt = match stream.read(&mut inbuf).await {
+                let read_cnt = match stream.read(&mut chunk).await {
                         Ok(0) => {
                                 println!("Client connection closed.");
                                 return Ok(());
@@ -35,23 +53,175 @@ where S: AsyncRead + AsyncWrite + Unpin,
                                 return Ok(());
                         }
                 };
-                let inbytes = &inbuf[..read_cnt];
-                let tlv = cmd::CmdTLV::new(inbytes)?;
-                let cmd = cmd::decode_cmd(&tlv)?;
-                let mut resp = cmd::CmdResponseTLV::new();
-                cmd::dispatch_cmd(&ctl_rc, &cmd, &mut resp).await?;
-
-                if resp.respond(&mut stream).await.is_err() {
-                        eprintln!("Error responding to client stream.");
+                let skip = discard.min(read_cnt);
+                discard -= skip;
+                inbuf.extend_from_slice(&chunk[skip..read_cnt]);
+
+                // Serve every complete frame in order, as a read may carry
+                // several pipelined commands or only part of one. Their
+                // responses go back together once the frames run out.
+                let mut start = 0;
+                while let Some(frame_len) = cmd::CmdTLV::frame_len(&inbuf[start..]) {
+                        let avail = inbuf.len() - start;
+                        if frame_len > max_frame_sz {
+                                cmd::CmdResponseTLV::with_error(cmd::CmdError::FrameTooLarge)
+                                        .with_detail(format!("frame of {frame_len} bytes exceeds the limit of {max_frame_sz}"))
+                                        .with_request_id(cmd::CmdTLV::peek_request_id(&inbuf[start..]))
+                                        .encode_into(&mut outbuf, version);
+                                discard = frame_len.saturating_sub(avail);
+                                start += frame_len.min(avail);
+                                continue;
+                        }
+                        if avail < frame_len {
+                                break;
+                        }
+                        let frame = &inbuf[start..start+frame_len];
+                        start += frame_len;
+                        handle_frame(&ctl_rc, frame, &mut outbuf, &mut version).await;
+                        if outbuf.len() >= MAX_BATCH_SZ && !flush(&mut stream, &mut outbuf).await {
+                                return Ok(());
+                        }
+                }
+                inbuf.drain(..start);
+                if !flush(&mut stream, &mut outbuf).await {
                         return Ok(());
                 }
+        }
+}
 
-                println!("Response sent to client.");
 
-                if ctl_rc.try_borrow().ok().map_or(false, |c| c.config().wal_mode > 0) {
-                        postprocess_cmd(&ctl_rc, &cmd, &resp, &tlv).await?;
+// Write back the pending responses. Returns false if the connection should
+// be closed.
+async fn flush<S>(stream: &mut S, outbuf: &mut Vec<u8>) -> bool
+where S: AsyncWrite + Unpin,
+{
+        if outbuf.is_empty() {
+                return true;
+        }
+        if stream.write_all(outbuf).await.is_err() {
+                eprintln!("Error responding to client stream.");
+                return false;
+        }
+        println!("Responses sent to client.");
+        outbuf.clear();
+        true
+}
+
+
+// Serve one framed command, appending the response to the output buffer. A
+// frame that can't be decoded, or a command that fails on the server side,
+// is answered with an error, and the connection stays open for the frames
+// after it, so the responses queued before it still go back.
+async fn handle_frame(
+        ctl_rc: &Rc<RefCell<ctl::Ctl>>,
+        frame: &[u8],
+        outbuf: &mut Vec<u8>,
+        version: &mut u8
+)
+{
+        let req_id = cmd::CmdTLV::peek_request_id(frame);
+        let frame = match resolve_name(ctl_rc, frame) {
+                Ok(frame) => frame,
+                Err(resp) => {
+                        resp.with_request_id(req_id).encode_into(outbuf, *version);
+                        return;
+                }
+        };
+        let tlv = match cmd::CmdTLV::new(&frame) {
+                Ok(tlv) => tlv,
+                Err(e) => {
+                        decode_error_response(&e, req_id).encode_into(outbuf, *version);
+                        return;
                 }
+        };
+        let cmd = match cmd::decode_cmd(&tlv) {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                        decode_error_response(&e, req_id).encode_into(outbuf, *version);
+                        return;
+                }
+        };
+        let mut resp = match execute_cmd(ctl_rc, &cmd, &tlv).await {
+                Ok(resp) => resp,
+                Err(e) => cmd::CmdResponseTLV::with_error(cmd::CmdError::InternalError).with_detail(e.to_string()),
+        };
+        resp.set_request_id(req_id);
+        match resp.status() {
+                cmd::CmdResponseCode::Success => {
+                        if let Some(handshake_version) = cmd.handshake_version() {
+                                *version = handshake_version;
+                        }
+                }
+                cmd::CmdResponseCode::Error(err) => {
+                        println!("Command failed with {err:?}: {}", resp.detail());
+                }
+        }
+        resp.encode_into(outbuf, *version);
+}
+
+
+/// Execute a command given by its family, operation and value, for the
+/// front ends that speak other protocols. A command that can't be decoded is
+/// answered with an error response, as it would be over the wire.
+pub(crate) async fn execute_request(
+        ctl_rc: &Rc<RefCell<ctl::Ctl>>,
+        family: u8,
+        op: u8,
+        val: &[u8]
+) -> io::Result<cmd::CmdResponseTLV>
+{
+        let len = u32::try_from(val.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "execute_request: value too long"))?;
+        let mut frame = vec![family, op];
+        frame.extend(proto::NO_REQUEST_ID.to_le_bytes());
+        frame.extend(len.to_le_bytes());
+        frame.extend(val);
+
+        let frame = match resolve_name(ctl_rc, &frame) {
+                Ok(frame) => frame,
+                Err(resp) => { return Ok(resp); }
+        };
+        let tlv = match cmd::CmdTLV::new(&frame) {
+                Ok(tlv) => tlv,
+                Err(e) => { return Ok(decode_error_response(&e, None)); }
+        };
+        let cmd = match cmd::decode_cmd(&tlv) {
+                Ok(cmd) => cmd,
+                Err(e) => { return Ok(decode_error_response(&e, None)); }
+        };
+        execute_cmd(ctl_rc, &cmd, &tlv).await
+}
+
+
+// Execute a decoded command, and log it to the write-ahead log if it changed
+// the state.
+async fn execute_cmd(
+        ctl_rc: &Rc<RefCell<ctl::Ctl>>,
+        cmd: &cmd::Cmd<'_>,
+        tlv: &cmd::CmdTLV<'_>
+) -> io::Result<cmd::CmdResponseTLV>
+{
+        let mut resp = cmd::CmdResponseTLV::new();
+        cmd::dispatch_cmd(ctl_rc, cmd, &mut resp).await?;
+        if ctl_rc.try_borrow().ok().map_or(false, |c| c.config().wal_mode > 0) {
+                postprocess_cmd(ctl_rc, cmd, &resp, tlv).await?;
         }
+        Ok(resp)
+}
+
+
+fn resolve_name<'a>(ctl_rc: &Rc<RefCell<ctl::Ctl>>, frame: &'a [u8]) -> Result<Cow<'a, [u8]>, cmd::CmdResponseTLV> {
+        match ctl_rc.try_borrow() {
+                Ok(ctl) => cmd::resolve_name(frame, &ctl),
+                Err(e) => Err(cmd::CmdResponseTLV::with_error(cmd::CmdError::InternalError).with_detail(e.to_string())),
+        }
+}
+
+
+fn decode_error_response(e: &io::Error, req_id: Option<u16>) -> cmd::CmdResponseTLV {
+        cmd::CmdResponseTLV::with_error(cmd::CmdError::from_decode_error(e))
+                .with_detail(e.to_string())
+                .with_request_id(req_id)
 }
 
 
@@ -62,21 +232,163 @@ async fn postprocess_cmd(
         tlv: &cmd::CmdTLV<'_>
 ) -> io::Result<()>
 {
+        // A command that failed left the state as it was, batches included,
+        // so there's nothing to replay
         if let cmd::CmdResponseCode::Error(_) = resp.status() {
                 return Ok(())
         }
         match cmd {
-                cmd::Cmd::Write(cmd::WriteCmd::BloomFilter(_) | cmd::WriteCmd::Database(_)) => {
-                        let mut ctl_guard = match ctl_rc.try_borrow_mut () {
-                                Ok(guard) => guard,
-                                Err(e) => {
-                                        eprintln!("FATAL: Failed to borrow Ctl mutably: {e}");
-                                        return Ok(());
-                                }
-                        };
-                        ctl_guard.wa_log().log(tlv.value())?;
+                cmd::Cmd::Write(write_cmd) if write_cmd.is_logged() => {
+                        log_cmd(ctl_rc, tlv)?;
                 }
                 cmd::Cmd::Write(_) | cmd::Cmd::Read(_) => {}
         }
         Ok(())
+}
+
+
+fn log_cmd(ctl_rc: &Rc<RefCell<ctl::Ctl>>, tlv: &cmd::CmdTLV<'_>) -> io::Result<()> {
+        let mut ctl_guard = match ctl_rc.try_borrow_mut () {
+                Ok(guard) => guard,
+                Err(e) => {
+                        eprintln!("FATAL: Failed to borrow Ctl mutably: {e}");
+                        return Ok(());
+                }
+        };
+        ctl_guard.log_cmd(tlv.bytes())
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use crate::testing::TempDir;
+
+        // Read an error response, which carries no detail in the first
+        // version of the protocol
+        async fn read_error(client: &mut tokio::io::DuplexStream, rc: u8) {
+                let mut resp = [0; 2];
+                client.read_exact(&mut resp).await.unwrap();
+                assert!(resp == [rc, 255]);
+        }
+
+        // Read an error response in the second version, and return its
+        // detail
+        async fn read_error_v2(client: &mut tokio::io::DuplexStream, rc: u8) -> String {
+                let mut resp = [0; 8];
+                client.read_exact(&mut resp).await.unwrap();
+                assert!(resp[..2] == [rc, 255]);
+                let mut detail = vec![0; u32::from_le_bytes(resp[4..].try_into().unwrap()) as usize];
+                client.read_exact(&mut detail).await.unwrap();
+                String::from_utf8(detail).unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_framing() {
+                let dir = TempDir::new("framing");
+                let conf = cfg::Config { max_frame_sz: 64, ..dir.config() };
+                let ctl_rc = Rc::new(RefCell::new(ctl::Ctl::new_blank(conf).unwrap()));
+                let (mut client, server) = tokio::io::duplex(256);
+
+                let list: &[u8] = &[1, 5, 255, 255, 1, 0, 0, 0, 0];
+                let client_task = async move {
+                        let mut resp = [0; 2];
+
+                        // Two pipelined commands, the second split across writes
+                        let mut bytes = list.to_vec();
+                        bytes.extend(&list[..4]);
+                        client.write_all(&bytes).await.unwrap();
+                        client.write_all(&list[4..]).await.unwrap();
+                        for _ in 0..2 {
+                                client.read_exact(&mut resp).await.unwrap();
+                                assert!(resp == [0, 255]);
+                        }
+
+                        // A frame over the limit is rejected and skipped
+                        client.write_all(&[1, 5, 255, 255, 100, 0, 0, 0]).await.unwrap();
+                        read_error(&mut client, 5).await;
+                        client.write_all(&[0; 100]).await.unwrap();
+                        client.write_all(list).await.unwrap();
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [0, 255]);
+
+                        // Frames that can't be decoded are answered with an
+                        // error and the connection stays open
+                        for (bytes, rc) in [
+                                (&[1, 99, 255, 255, 0, 0, 0, 0][..], 6),
+                                (&[3, 0, 255, 255, 1, 0, 0, 0, 0][..], 7),
+                        ] {
+                                client.write_all(bytes).await.unwrap();
+                                read_error(&mut client, rc).await;
+                        }
+                        client.write_all(list).await.unwrap();
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [0, 255]);
+
+                        // Request ids are echoed in order, errors included
+                        let mut bytes = Vec::new();
+                        for req_id in [7u16, 8, 9] {
+                                let op = if req_id == 8 { 99 } else { 5 };
+                                bytes.extend([1, op]);
+                                bytes.extend(req_id.to_le_bytes());
+                                bytes.extend([1, 0, 0, 0, 0]);
+                        }
+                        client.write_all(&bytes).await.unwrap();
+                        let mut resp = [0; 4];
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [7, 0, 0, 255]);
+                        client.read_exact(&mut resp[..2]).await.unwrap();
+                        assert!(resp[..2] == [8, 0]);
+                        read_error(&mut client, 6).await;
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [9, 0, 0, 255]);
+
+                        // An unknown version is refused in the current
+                        // framing, and a known one switches to its framing
+                        client.write_all(&[1, 8, 255, 255, 1, 0, 0, 0, 9]).await.unwrap();
+                        read_error(&mut client, 8).await;
+                        client.write_all(&[1, 8, 255, 255, 1, 0, 0, 0, 2]).await.unwrap();
+                        let mut resp = [0; 9];
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [0, 255, 255, 255, 1, 0, 0, 0, 2]);
+                        client.write_all(&[1, 99, 4, 0, 0, 0, 0, 0]).await.unwrap();
+                        assert!(read_error_v2(&mut client, 6).await == "decode_ctl_cmd: unrecognized command");
+                        client.write_all(&[1, 5, 255, 255, 100, 0, 0, 0]).await.unwrap();
+                        assert!(read_error_v2(&mut client, 5).await == "frame of 108 bytes exceeds the limit of 64");
+                };
+                let (res, ()) = tokio::join!(handle_client(server, ctl_rc), client_task);
+                res.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_failed_logging() {
+                let dir = TempDir::new("failed-logging");
+                let conf = dir.config();
+                let wal_file = conf.wal_file.clone();
+                let mut ctl = ctl::Ctl::new_blank(conf).unwrap();
+                ctl.wa_log().writer = io::BufWriter::new(std::fs::File::open(&wal_file).unwrap());
+                let ctl_rc = Rc::new(RefCell::new(ctl));
+                let (mut client, server) = tokio::io::duplex(256);
+
+                // A command that can't be logged is answered with an error in
+                // its place among the pipelined responses
+                let client_task = async move {
+                        let mut bytes = Vec::new();
+                        bytes.extend([1, 5, 1, 0, 1, 0, 0, 0, 0]);
+                        bytes.extend([1, 4, 2, 0, 1, 0, 0, 0, 3]);
+                        bytes.extend([1, 5, 3, 0, 1, 0, 0, 0, 0]);
+                        client.write_all(&bytes).await.unwrap();
+                        let mut resp = [0; 4];
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [1, 0, 0, 255]);
+                        client.read_exact(&mut resp[..2]).await.unwrap();
+                        assert!(resp[..2] == [2, 0]);
+                        read_error(&mut client, 12).await;
+                        let mut resp = [0; 6];
+                        client.read_exact(&mut resp).await.unwrap();
+                        assert!(resp == [3, 0, 0, 3, 0, 255]);
+                };
+                let (res, ()) = tokio::join!(handle_client(server, ctl_rc), client_task);
+                res.unwrap();
+        }
 }
\ No newline at end of file
@@ -0,0 +1,49 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Fixtures shared by the tests.
+
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg;
+use crate::embed;
+
+
+/// A directory of a test's own, removed with what it holds once dropped.
+pub(crate) struct TempDir(PathBuf);
+
+
+impl TempDir {
+        /// Create an empty directory named after the test and the process,
+        /// so that tests running side by side don't share files.
+        pub(crate) fn new(name: &str) -> Self {
+                let dir = std::env::temp_dir().join(format!("qstra-test-{}-{name}", std::process::id()));
+                let _ = fs::remove_dir_all(&dir);
+                fs::create_dir_all(&dir).unwrap();
+                Self(dir)
+        }
+
+        pub(crate) fn path(&self) -> &Path {
+                &self.0
+        }
+
+        /// A configuration that keeps the databases and the write-ahead log
+        /// in the directory.
+        pub(crate) fn config(&self) -> cfg::Config {
+                cfg::Config {
+                        db_file: self.0.join(embed::DB_FILE),
+                        wal_file: self.0.join(embed::WAL_FILE),
+                        ..cfg::Config::default()
+                }
+        }
+}
+
+
+impl Drop for TempDir {
+        fn drop(&mut self) {
+                let _ = fs::remove_dir_all(&self.0);
+        }
+}
\ No newline at end of file
@@ -1,95 +1,458 @@
 // Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
 // SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Log the commands that change the state, and replay them onto it.
+//!
+//! The log starts with the magic bytes `QSTRWAL` and the version of its
+//! format (u8). A log without them was written before the records were
+//! checked, as commands each after their length (u16); it's rewritten in the
+//! current format once, replayed, and checkpointed. Each
+//! command goes to one record, or is split across several if it's
+//! larger than a fragment, each laid out as
+//!
+//!   crc (u32) | len (u32) | seq (u64) | kind (u8) | fragment (u8...)
+//!
+//! all little-endian, where the CRC-32C covers everything after itself, the
+//! sequence numbers of consecutive records are consecutive, and the kind says
+//! whether the record holds a whole command or the first, a middle or the
+//! last fragment of one. A command is replayed only once all its fragments
+//! are in, and only if the snapshot doesn't cover it already. A crash can
+//! leave the last command half-written, which replay cuts off whole; a record
+//! that fails its check anywhere else, or claims more than a fragment, is
+//! corruption, and nothing is replayed nor cut off.
 
 
+use std::borrow::Cow;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Read, Seek, SeekFrom, Write, ErrorKind};
+use std::io::{self, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 
 use crate::cmd;
 use crate::ctl;
 
 
+const MAGIC: &[u8] = b"QSTRWAL";
+const VERSION: u8 = 1;
+pub(crate) const FILE_HEADER_SZ: usize = MAGIC.len() + 1;
+
+const CRC_SZ: usize = 4;
+const HEADER_SZ: usize = CRC_SZ + 4 + 8 + 1;
+// The most of a command a single record holds
+const MAX_FRAGMENT_SZ: usize = 1 << 15;
+
+const KIND_FULL: u8 = 0;
+const KIND_FIRST: u8 = 1;
+const KIND_MIDDLE: u8 = 2;
+const KIND_LAST: u8 = 3;
+
+// The CRC-32C (Castagnoli) table, for the reflected polynomial
+const CRC32C_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+                let mut crc = i;
+                let mut bit = 0;
+                while bit < 8 {
+                        crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+                        bit += 1;
+                }
+                table[i as usize] = crc;
+                i += 1;
+        }
+        table
+};
+
+
+fn crc32c(bytes: &[u8]) -> u32 {
+        !bytes.iter().fold(!0, |crc, b| CRC32C_TABLE[((crc ^ u32::from(*b)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+
 pub struct WriteAheadLog {
         file_path: PathBuf,
         pub writer: io::BufWriter<fs::File>,
+        // The sequence number of the next record
+        next_seq: u64,
+        // The size of the log in bytes and in records, as far as it's known
+        // before a replay
+        size: u64,
+        record_cnt: u64,
 }
 
 
 impl WriteAheadLog {
         pub fn new(wal_file: &PathBuf) -> io::Result<Self> {
-                let file = fs::OpenOptions::new()
+                let mut file = fs::OpenOptions::new()
                         .create(true)
                         .append(true)
                         .read(true)
                         .open(wal_file)?;
-                Ok(Self { file_path: wal_file.clone(), writer: io::BufWriter::new(file) })
+                let mut size = file.metadata()?.len();
+                if size == 0 {
+                        file.write_all(MAGIC)?;
+                        file.write_all(&[VERSION])?;
+                        size = FILE_HEADER_SZ as u64;
+                }
+                Ok(Self { file_path: wal_file.clone(), writer: io::BufWriter::new(file), next_seq: 0, size, record_cnt: 0 })
+        }
+
+        /// The sequence number the next record will carry.
+        #[must_use]
+        pub fn next_seq(&self) -> u64 {
+                self.next_seq
+        }
+
+        #[must_use]
+        pub fn size(&self) -> u64 {
+                self.size
         }
 
+        #[must_use]
+        pub fn record_cnt(&self) -> u64 {
+                self.record_cnt
+        }
+
+        /// Log a command, in as many records as it takes. The records are
+        /// written out together.
         pub fn log(&mut self, bytes: &[u8]) -> io::Result<()> {
-                #[allow(clippy::cast_possible_truncation)]
-                self.writer.write_all(&u16::to_le_bytes(bytes.len() as u16))?;
-                self.writer.write_all(bytes)?;
+                let fragment_cnt = bytes.len().div_ceil(MAX_FRAGMENT_SZ).max(1);
+                let mut records = Vec::with_capacity(fragment_cnt*HEADER_SZ + bytes.len());
+                for i in 0..fragment_cnt {
+                        let fragment = &bytes[i*MAX_FRAGMENT_SZ..bytes.len().min((i + 1)*MAX_FRAGMENT_SZ)];
+                        let kind = match i {
+                                _ if fragment_cnt == 1 => KIND_FULL,
+                                0 => KIND_FIRST,
+                                _ if i + 1 == fragment_cnt => KIND_LAST,
+                                _ => KIND_MIDDLE,
+                        };
+                        let len = u32::try_from(fragment.len())
+                                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "log: fragment too long for a record"))?;
+                        let start = records.len();
+                        records.extend([0; CRC_SZ]);
+                        records.extend(len.to_le_bytes());
+                        records.extend((self.next_seq + i as u64).to_le_bytes());
+                        records.push(kind);
+                        records.extend(fragment);
+                        let crc = crc32c(&records[start+CRC_SZ..]);
+                        records[start..start+CRC_SZ].copy_from_slice(&crc.to_le_bytes());
+                }
+
+                self.writer.write_all(&records)?;
                 self.writer.flush()?;
+                self.next_seq += fragment_cnt as u64;
+                self.size += records.len() as u64;
+                self.record_cnt += fragment_cnt as u64;
                 Ok(())
         }
 
-        #[expect(dead_code)]
+        /// Empty the log of its records. The sequence numbers carry on from
+        /// where they were.
         pub fn clear(&mut self) -> io::Result<()> {
+                self.truncate(FILE_HEADER_SZ as u64)?;
+                self.record_cnt = 0;
+                Ok(())
+        }
+
+        fn truncate(&mut self, len: u64) -> io::Result<()> {
                 self.writer.flush()?;
                 let file = self.writer.get_mut();
-                file.set_len(0)?;
-                file.seek(SeekFrom::Start(0))?;
+                file.set_len(len)?;
+                file.seek(SeekFrom::Start(len))?;
+                self.size = len;
                 Ok(())
         }
 
+        /// Check every record, then apply the commands in order that the
+        /// snapshot doesn't cover. A torn command at the end is cut off the
+        /// log first. A log of the older format is rewritten in the current
+        /// one before it's replayed, and checkpointed after.
         pub fn replay(ctl: &mut ctl::Ctl) -> io::Result<()> {
                 ctl.wa_log().writer.flush()?;
 
-                let file = fs::File::open(&ctl.wa_log().file_path)?;
-                let mut reader = io::BufReader::new(file);
-
-                let mut prefix_len_buf = [0u8; 2];
-                let mut cmd_buf = Vec::new();
-
-                loop {
-                        match reader.read_exact(&mut prefix_len_buf) {
-                                Ok(()) => {
-                                        let cmd_len = u16::from_le_bytes(prefix_len_buf);
-
-                                        if cmd_len == 0 {
-                                                continue;
-                                        }
-
-                                        cmd_buf.clear();
-                                        cmd_buf.try_reserve(cmd_len as usize)?;
-
-                                        if reader.by_ref().take(cmd_len as u64).read_to_end(&mut cmd_buf).is_err() {
-                                                return Err(io::Error::new(io::ErrorKind::Other, "replay"));
-                                        }
-
-                                        if cmd_buf.len() != cmd_len as usize {
-                                                return Err(io::Error::new(io::ErrorKind::Other, "replay"));
-                                        }
-
-                                        let tlv = cmd::CmdTLV::new(&cmd_buf[0..])?;
-                                        let cmd = cmd::decode_cmd(&tlv)?;
-                                        let mut resp = cmd::CmdResponseTLV::new();
-                                        if let cmd::Cmd::Write(write_cmd) = cmd {
-                                                cmd::dispatch_write_cmd(&write_cmd, ctl, &mut resp)?;
-                                        }
-                                }
-                                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
-                                        break;
-                                }
-                                Err(_) => {
-                                        return Err(io::Error::new(io::ErrorKind::Other, "replay"));
-                                }
+                let mut buf = fs::read(&ctl.wa_log().file_path)?;
+                let legacy = !buf.is_empty() && !buf.starts_with(MAGIC);
+                if legacy {
+                        eprintln!("Rewriting the write-ahead log in the current format.");
+                        Self::migrate(ctl, &buf)?;
+                        buf = fs::read(&ctl.wa_log().file_path)?;
+                }
+                let (entries, end) = scan(&buf)?;
+                if end < buf.len() {
+                        eprintln!("Cutting a torn command of {} bytes off the end of the write-ahead log.", buf.len() - end);
+                        ctl.wa_log().truncate(end as u64)?;
+                }
+
+                let snapshot_seq = ctl.snapshot_seq();
+                for entry in entries.iter().filter(|entry| entry.seq >= snapshot_seq) {
+                        let tlv = cmd::CmdTLV::new(&entry.cmd)?;
+                        let cmd = cmd::decode_cmd(&tlv)?;
+                        let mut resp = cmd::CmdResponseTLV::new();
+                        if let cmd::Cmd::Write(write_cmd) = cmd {
+                                cmd::dispatch_write_cmd(&write_cmd, ctl, &mut resp)?;
                         }
+                }
 
+                // The records are numbered on from the snapshot even once the
+                // log was emptied
+                let wal = ctl.wa_log();
+                wal.size = end as u64;
+                wal.record_cnt = 0;
+                wal.next_seq = snapshot_seq;
+                if let Some(entry) = entries.last() {
+                        wal.record_cnt = entry.seq + 1 - record_seq(&buf[FILE_HEADER_SZ..]);
+                        wal.next_seq = wal.next_seq.max(entry.seq + 1);
+                }
+                if legacy {
+                        ctl.checkpoint()?;
                 }
+                Ok(())
+        }
 
-                ctl.wa_log().writer.flush()?;
+        // Rewrite a log of the older format, where each command follows its
+        // length (u16) and nothing is checked, as records of the current one.
+        // The records go to a file next to the log and are then moved over
+        // it, so that a crash leaves either log whole. A command that runs
+        // past the end was torn by a crash, and is dropped.
+        fn migrate(ctl: &mut ctl::Ctl, buf: &[u8]) -> io::Result<()> {
+                let wal_file = ctl.wa_log().file_path.clone();
+                let mut tmp_file = OsString::from(&wal_file);
+                tmp_file.push(".tmp");
+                let tmp_file = PathBuf::from(tmp_file);
+                fs::File::create(&tmp_file)?;
+
+                let mut wal = Self::new(&tmp_file)?;
+                let mut loc = 0;
+                while buf.len() - loc >= 2 {
+                        let len = u16::from_le_bytes([buf[loc], buf[loc+1]]) as usize;
+                        let end = loc + 2 + len;
+                        if end > buf.len() {
+                                eprintln!("Dropping a torn command of {} bytes off the end of the write-ahead log.", buf.len() - loc);
+                                break;
+                        }
+                        if len > 0 {
+                                wal.log(&buf[loc+2..end])?;
+                        }
+                        loc = end;
+                }
+                wal.writer.get_ref().sync_all()?;
+                drop(wal);
+
+                fs::rename(&tmp_file, &wal_file)?;
+                *ctl.wa_log() = Self::new(&wal_file)?;
                 Ok(())
         }
+}
+
+
+// A logged command, along with the sequence number of its last record
+#[derive(Debug)]
+struct Entry<'a> {
+        seq: u64,
+        cmd: Cow<'a, [u8]>,
+}
+
+
+fn record_seq(record: &[u8]) -> u64 {
+        u64::from_le_bytes(record[8..16].try_into().unwrap())
+}
+
+
+// Split the log into its commands, and find where the last whole one ends.
+// The last record was torn by a crash if it runs past the end of the log, or
+// fails its check, while claiming no more than a fragment; so were the
+// fragments before it of the same command. Any other failure is an error.
+fn scan(buf: &[u8]) -> io::Result<(Vec<Entry<'_>>, usize)> {
+        let Some(version) = buf.strip_prefix(MAGIC).and_then(|rest| rest.first()) else {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "replay: not a write-ahead log, or one of an older format"));
+        };
+        if *version != VERSION {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replay: unsupported write-ahead log version {version}")));
+        }
+
+        let mut entries: Vec<Entry> = Vec::new();
+        // The fragments so far of a command split across records
+        let mut pending: Option<Vec<u8>> = None;
+        let mut prev_seq: Option<u64> = None;
+        let mut loc = FILE_HEADER_SZ;
+        let mut whole_end = FILE_HEADER_SZ;
+        while buf.len() - loc >= HEADER_SZ {
+                let header = &buf[loc..loc+HEADER_SZ];
+                let crc = u32::from_le_bytes(header[..4].try_into().unwrap());
+                let len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+                let seq = record_seq(header);
+                let kind = header[16];
+                if len > MAX_FRAGMENT_SZ {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replay: record at offset {loc} claims {len} bytes")));
+                }
+                let end = loc + HEADER_SZ + len;
+                if end > buf.len() {
+                        break;
+                }
+                if crc32c(&buf[loc+CRC_SZ..end]) != crc {
+                        if end == buf.len() {
+                                break;
+                        }
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replay: corrupt record at offset {loc}")));
+                }
+                if let Some(prev) = prev_seq {
+                        if seq != prev + 1 {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replay: record {seq} at offset {loc} follows record {prev}")));
+                        }
+                }
+                let fragment = &buf[loc+HEADER_SZ..end];
+                match (kind, pending.as_mut()) {
+                        (KIND_FULL, None) => {
+                                entries.push(Entry { seq, cmd: Cow::Borrowed(fragment) });
+                                whole_end = end;
+                        }
+                        (KIND_FIRST, None) => { pending = Some(fragment.to_vec()); }
+                        (KIND_MIDDLE, Some(cmd)) => { cmd.extend(fragment); }
+                        (KIND_LAST, Some(cmd)) => {
+                                cmd.extend(fragment);
+                                entries.push(Entry { seq, cmd: Cow::Owned(pending.take().unwrap()) });
+                                whole_end = end;
+                        }
+                        _ => {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replay: record {seq} at offset {loc} is out of place")));
+                        }
+                }
+                prev_seq = Some(seq);
+                loc = end;
+        }
+        Ok((entries, whole_end))
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use crate::testing::TempDir;
+
+        #[test]
+        fn test_replay() {
+                let dir = TempDir::new("wal");
+                let conf = dir.config();
+                let wal_file = conf.wal_file.clone();
+                assert!(crc32c(b"123456789") == 0xE306_9283);
+
+                // Create bloom filters 1 and 2 in database 0
+                let mut ctl = ctl::Ctl::new_blank(conf).unwrap();
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 1]).unwrap();
+                ctl.wa_log().log(&[2, 0, 255, 255, 3, 0, 0, 0, 0, 1, 2]).unwrap();
+                let whole = fs::read(&wal_file).unwrap();
+                assert!(whole.len() == FILE_HEADER_SZ + 2*(HEADER_SZ + 11));
+                let (first, second) = (FILE_HEADER_SZ, FILE_HEADER_SZ + HEADER_SZ + 11);
+
+                // A torn last record is cut off, and its command is lost
+                fs::write(&wal_file, &whole[..whole.len()-3]).unwrap();
+                ctl.load_from_storage().unwrap();
+                let db = ctl.db_registry.get(&[0]).unwrap();
+                assert!(db.has_bloom_filter(1) && !db.has_bloom_filter(2));
+                assert!(fs::metadata(&wal_file).unwrap().len() == second as u64);
+                assert!(ctl.wa_log().next_seq() == 1);
+
+                // So is one of the right length that fails its check
+                let mut torn = whole.clone();
+                *torn.last_mut().unwrap() ^= 1;
+                let (records, end) = scan(&torn).unwrap();
+                assert!(records.len() == 1 && end == second);
+
+                // A bad record followed by others is reported, as are
+                // records out of sequence
+                let mut corrupt = whole.clone();
+                corrupt[first + HEADER_SZ] ^= 1;
+                let e = scan(&corrupt).unwrap_err();
+                assert!(e.kind() == io::ErrorKind::InvalidData && e.to_string() == format!("replay: corrupt record at offset {first}"));
+                let reordered = [&whole[..first], &whole[second..], &whole[first..second]].concat();
+                assert!(scan(&reordered).unwrap_err().to_string() == format!("replay: record 0 at offset {second} follows record 1"));
+
+                // A log without the header, as written before records were
+                // checked, is refused by the scan
+                let e = scan(&whole[first..]).unwrap_err();
+                assert!(e.kind() == io::ErrorKind::InvalidData);
+                let mut newer = whole.clone();
+                newer[first - 1] = VERSION + 1;
+                assert!(scan(&newer).unwrap_err().to_string() == format!("replay: unsupported write-ahead log version {}", VERSION + 1));
+
+                // A record claiming more than a fragment is corruption rather
+                // than a tear, even as the last record
+                let three = [&whole[..], &whole[first..second]].concat();
+                for (loc, len) in [(second, u32::MAX), (second, 1 << 20), (three.len() - (HEADER_SZ + 11), MAX_FRAGMENT_SZ as u32 + 1)] {
+                        let mut corrupt = three.clone();
+                        corrupt[loc+CRC_SZ..loc+CRC_SZ+4].copy_from_slice(&len.to_le_bytes());
+                        fs::write(&wal_file, &corrupt).unwrap();
+                        let e = ctl.load_from_storage().unwrap_err();
+                        assert!(e.to_string() == format!("replay: record at offset {loc} claims {len} bytes"));
+                        assert!(fs::read(&wal_file).unwrap() == corrupt);
+                }
+        }
+
+        #[test]
+        fn test_fragments() {
+                let dir = TempDir::new("wal-fragments");
+                let wal_file = dir.config().wal_file;
+                let big: Vec<u8> = (0..3*MAX_FRAGMENT_SZ + 100).map(|i| (i % 251) as u8).collect();
+
+                let mut wal = WriteAheadLog::new(&wal_file).unwrap();
+                wal.log(b"small").unwrap();
+                wal.log(&big).unwrap();
+                wal.log(b"").unwrap();
+                assert!(wal.next_seq() == 6);
+                let whole = fs::read(&wal_file).unwrap();
+                let (entries, end) = scan(&whole).unwrap();
+                assert!(end == whole.len());
+                assert!(entries.iter().map(|entry| entry.seq).collect::<Vec<u64>>() == [0, 4, 5]);
+                assert!(*entries[0].cmd == *b"small" && *entries[1].cmd == *big && entries[2].cmd.is_empty());
+
+                // A command missing its last fragment is cut off whole
+                let big_end = whole.len() - HEADER_SZ;
+                let second = FILE_HEADER_SZ + HEADER_SZ + 5;
+                let (entries, end) = scan(&whole[..big_end - 1]).unwrap();
+                assert!(entries.len() == 1 && end == second);
+
+                // A fragment missing from the middle of the log breaks the
+                // sequence, and one out of place is reported
+                let third = second + HEADER_SZ + MAX_FRAGMENT_SZ;
+                let skipped = [&whole[..third], &whole[third + HEADER_SZ + MAX_FRAGMENT_SZ..]].concat();
+                assert!(scan(&skipped).unwrap_err().to_string().ends_with("follows record 1"));
+                let mut misplaced = whole.clone();
+                misplaced[second + 16] = KIND_MIDDLE;
+                let crc = crc32c(&misplaced[second+CRC_SZ..third]);
+                misplaced[second..second+CRC_SZ].copy_from_slice(&crc.to_le_bytes());
+                assert!(scan(&misplaced).unwrap_err().to_string() == format!("replay: record 1 at offset {second} is out of place"));
+        }
+
+        #[test]
+        fn test_migrate() {
+                let dir = TempDir::new("wal-migrate");
+                let wal_file = dir.config().wal_file;
+
+                // Bloom filters 1 and 2 created in database 0 by the
+                // baseline server, whose log ended in a torn command
+                let create = |bf_id: u8| vec![2, 0, 255, 255, 3, 0, 0, 0, 0, 1, bf_id];
+                let mut legacy = Vec::new();
+                for cmd in [create(1), Vec::new(), create(2)] {
+                        legacy.extend((cmd.len() as u16).to_le_bytes());
+                        legacy.extend(cmd);
+                }
+                legacy.extend([11, 0, 2, 0]);
+                fs::write(&wal_file, &legacy).unwrap();
+
+                // The commands end up in a checkpoint, and the log starts
+                // over in the current format
+                let mut ctl = ctl::Ctl::new_blank(dir.config()).unwrap();
+                ctl.load_from_storage().unwrap();
+                let db = ctl.db_registry.get(&[0]).unwrap();
+                assert!(db.has_bloom_filter(1) && db.has_bloom_filter(2) && !db.has_bloom_filter(3));
+                assert!(ctl.snapshot_seq() == 2 && ctl.wa_log().next_seq() == 2);
+                assert!(fs::read(&wal_file).unwrap() == [MAGIC, &[VERSION]].concat());
+
+                ctl.wa_log().log(&create(3)).unwrap();
+                let mut restored = ctl::Ctl::new_blank(dir.config()).unwrap();
+                restored.load_from_storage().unwrap();
+                let db = restored.db_registry.get(&[0]).unwrap();
+                assert!(db.has_bloom_filter(1) && db.has_bloom_filter(2) && db.has_bloom_filter(3));
+        }
 }
\ No newline at end of file
@@ -0,0 +1,269 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! A command-line client for the server.
+//!
+//! Given a command, the client runs it and exits; without one, it reads
+//! commands line by line from stdin. The server is reached at the Unix socket
+//! of the configuration if the server listens on one, and at its network
+//! address otherwise, unless the command line says where.
+
+
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use qstra::cfg;
+use qstra::db::ObjectKind;
+use qstra::http::json_string;
+use qstra_client::{Client, Endpoint};
+
+
+const USAGE: &str = "\
+usage: qstra-cli [-c conf] [-s socket | -a host:port] [--json] [command [args...]]
+
+commands:
+  add <db> <bf> <key>...       add keys to a bloom filter
+  has <db> <bf> <key>          check a key
+  mhas <db> <bf> [<key>...]    check many keys, read from stdin if none are given
+  save                         write the databases to storage
+  checkpoint                   write the databases to storage and empty the log
+  replay                       reload the databases and replay the write-ahead log
+  info [<db> <bf>]             describe the server, or a bloom filter
+  help                         show this message
+  quit                         leave the prompt
+
+where a database is given as db0 or 0, and a bloom filter as bf3 or 3.";
+
+
+struct Options {
+        conf_path: String,
+        endpoint: Option<Endpoint>,
+        json: bool,
+        cmd: Vec<String>,
+}
+
+
+// The text and JSON renderings of a command's result
+struct Output {
+        text: String,
+        json: String,
+}
+
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+        let mut opts = Options {
+                conf_path: cfg::CONF_FILE.to_owned(),
+                endpoint: None,
+                json: false,
+                cmd: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+                let mut value = || args.next().cloned().ok_or_else(|| format!("{arg} needs a value"));
+                match arg.as_str() {
+                        "-c" | "--conf" => { opts.conf_path = value()?; }
+                        "-s" | "--socket" => { opts.endpoint = Some(Endpoint::unix(value()?)); }
+                        "-a" | "--addr" => { opts.endpoint = Some(Endpoint::tcp(value()?)); }
+                        "--json" => { opts.json = true; }
+                        "-h" | "--help" => { opts.cmd = vec!["help".to_owned()]; }
+                        flag if flag.starts_with('-') => { return Err(format!("unrecognized option {flag}")); }
+                        _ => {
+                                opts.cmd = std::iter::once(arg).chain(args).cloned().collect();
+                                break;
+                        }
+                }
+        }
+        Ok(opts)
+}
+
+
+fn parse_id(arg: &str, prefix: &str) -> io::Result<u8> {
+        arg.strip_prefix(prefix).unwrap_or(arg).parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid id {arg}")))
+}
+
+
+// Run one command. Keys are read from stdin only when stdin isn't where the
+// commands come from.
+async fn run(client: &Client, words: &[String], stdin_keys: bool) -> io::Result<Output> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        match (words[0].as_str(), &words[1..]) {
+                ("add", [db, bf, keys @ ..]) if !keys.is_empty() => {
+                        let (db_id, bf_id) = (parse_id(db, "db")?, parse_id(bf, "bf")?);
+                        let keys: Vec<&[u8]> = keys.iter().map(String::as_bytes).collect();
+                        if let [key] = keys[..] {
+                                client.add(db_id, bf_id, key).await?;
+                        } else {
+                                client.add_batch(db_id, bf_id, &keys).await?;
+                        }
+                        Ok(Output {
+                                text: format!("added {}", keys.len()),
+                                json: format!("{{\"added\":{}}}", keys.len()),
+                        })
+                }
+                ("has", [db, bf, key]) => {
+                        let present = client.has(parse_id(db, "db")?, parse_id(bf, "bf")?, key.as_bytes()).await?;
+                        Ok(Output {
+                                text: present.to_string(),
+                                json: format!("{{\"present\":{present}}}"),
+                        })
+                }
+                ("mhas", [db, bf, keys @ ..]) => {
+                        let (db_id, bf_id) = (parse_id(db, "db")?, parse_id(bf, "bf")?);
+                        let keys = match keys {
+                                [] if stdin_keys => io::stdin().lock().lines()
+                                        .filter(|line| !line.as_ref().is_ok_and(String::is_empty))
+                                        .collect::<io::Result<Vec<String>>>()?,
+                                [] => { return Err(invalid("mhas: no keys given".to_owned())); }
+                                keys => keys.to_vec(),
+                        };
+                        let elts: Vec<&[u8]> = keys.iter().map(String::as_bytes).collect();
+                        let present = client.has_batch(db_id, bf_id, &elts).await?;
+                        let text: Vec<String> = keys.iter().zip(&present)
+                                .map(|(key, present)| format!("{key}\t{present}"))
+                                .collect();
+                        let json: Vec<String> = keys.iter().zip(&present)
+                                .map(|(key, present)| format!("{{\"key\":{},\"present\":{present}}}", json_string(key)))
+                                .collect();
+                        Ok(Output {
+                                text: text.join("\n"),
+                                json: format!("{{\"results\":[{}]}}", json.join(",")),
+                        })
+                }
+                ("save" | "checkpoint" | "replay", []) => {
+                        match words[0].as_str() {
+                                "save" => { client.save().await?; }
+                                "checkpoint" => { client.checkpoint().await?; }
+                                _ => { client.replay().await?; }
+                        }
+                        Ok(Output { text: "OK".to_owned(), json: "{\"ok\":true}".to_owned() })
+                }
+                ("info", []) => {
+                        let info = client.info().await?;
+                        let kinds: Vec<&str> = info.kinds.iter().map(|family| ObjectKind::try_from(*family).map_or("unknown", |kind| kind.name())).collect();
+                        Ok(Output {
+                                text: format!(
+                                        "server version: {}\nprotocol versions: {} to {}\nmax frame size: {}\nkinds: {}",
+                                        info.server_version, info.min_version, info.max_version, info.max_frame_sz, kinds.join(", ")
+                                ),
+                                json: format!(
+                                        "{{\"server_version\":{},\"min_version\":{},\"max_version\":{},\"max_frame_size\":{},\"kinds\":[{}]}}",
+                                        json_string(&info.server_version), info.min_version, info.max_version, info.max_frame_sz,
+                                        kinds.iter().map(|kind| json_string(kind)).collect::<Vec<String>>().join(",")
+                                ),
+                        })
+                }
+                ("info", [db, bf]) => {
+                        let info = client.filter_info(parse_id(db, "db")?, parse_id(bf, "bf")?).await?;
+                        let growth = if info.growth == 0 { "null".to_owned() } else { info.growth.to_string() };
+                        Ok(Output {
+                                text: format!(
+                                        "capacity: {}\nsize: {} bytes\nfilters: {}\nitems: {}\ngrowth: {growth}",
+                                        info.capacity, info.size, info.slice_cnt, info.insert_cnt
+                                ),
+                                json: format!(
+                                        "{{\"capacity\":{},\"size\":{},\"filters\":{},\"items\":{},\"growth\":{growth}}}",
+                                        info.capacity, info.size, info.slice_cnt, info.insert_cnt
+                                ),
+                        })
+                }
+                ("help", []) => Ok(Output { text: USAGE.to_owned(), json: format!("{{\"help\":{}}}", json_string(USAGE)) }),
+                _ => Err(invalid(format!("unrecognized command: {} (try help)", words.join(" ")))),
+        }
+}
+
+
+// Run a command and print its result. Returns whether it succeeded.
+async fn execute(client: &Client, words: &[String], stdin_keys: bool, json: bool) -> bool {
+        match run(client, words, stdin_keys).await {
+                Ok(output) => {
+                        println!("{}", if json { output.json } else { output.text });
+                        true
+                }
+                Err(e) if json => {
+                        println!("{{\"error\":{}}}", json_string(&e.to_string()));
+                        false
+                }
+                Err(e) => {
+                        eprintln!("error: {e}");
+                        false
+                }
+        }
+}
+
+
+async fn repl(client: &Client, json: bool) -> io::Result<bool> {
+        let interactive = io::stdin().is_terminal();
+        let mut ok = true;
+        let mut line = String::new();
+        loop {
+                if interactive {
+                        print!("qstra> ");
+                        io::stdout().flush()?;
+                }
+                line.clear();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                        return Ok(ok);
+                }
+                let words: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+                match words.first().map(String::as_str) {
+                        None => {}
+                        Some("quit" | "exit") => { return Ok(ok); }
+                        Some(_) => { ok &= execute(client, &words, false, json).await; }
+                }
+        }
+}
+
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let opts = match parse_args(&args) {
+                Ok(opts) => opts,
+                Err(msg) => {
+                        eprintln!("{msg}\n{USAGE}");
+                        return ExitCode::FAILURE;
+                }
+        };
+        let endpoint = opts.endpoint.unwrap_or_else(|| {
+                let conf = cfg::Config::new(&opts.conf_path);
+                if conf.listen_local {
+                        Endpoint::unix(conf.sock_addr)
+                } else {
+                        Endpoint::tcp(conf.inet_addr)
+                }
+        });
+        let client = Client::new(endpoint);
+
+        let ok = if opts.cmd.is_empty() {
+                repl(&client, opts.json).await.unwrap_or_else(|e| {
+                        eprintln!("error: {e}");
+                        false
+                })
+        } else {
+                execute(&client, &opts.cmd, true, opts.json).await
+        };
+        if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_args() {
+                let args: Vec<String> = ["-a", "127.0.0.1:1234", "--json", "has", "db0", "bf3", "-key"]
+                        .iter().map(|arg| (*arg).to_owned()).collect();
+                let opts = parse_args(&args).unwrap();
+                assert!(opts.endpoint == Some(Endpoint::tcp("127.0.0.1:1234")) && opts.json);
+                assert!(opts.cmd == ["has", "db0", "bf3", "-key"]);
+                assert!(parse_args(&["-s".to_owned()]).is_err());
+
+                assert!(parse_id("db0", "db").unwrap() == 0 && parse_id("3", "bf").unwrap() == 3);
+                assert!(parse_id("bf256", "bf").is_err());
+                assert!(json_string("a\"b\n") == "\"a\\\"b\\n\"");
+        }
+}
\ No newline at end of file
@@ -0,0 +1,324 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Run typed commands over a pool of connections.
+//!
+//! A command takes an idle connection from the pool, or opens a new one, and
+//! puts it back once the command is done unless the connection failed. A
+//! pooled connection the server closed while it sat idle is replaced once by
+//! a fresh connection before the command gives up, as long as the command
+//! can't have run: either none of it went out, or it only reads. A command
+//! that changes the state may have run before the connection failed, and
+//! isn't run a second time.
+
+
+use std::io;
+use std::sync::{Mutex, PoisonError};
+
+use qstra_proto::{FilterInfo, FilterParams};
+
+use crate::conn::{Connection, Endpoint};
+use crate::proto;
+
+
+/// What a server supports.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+        pub min_version: u8,
+        pub max_version: u8,
+        /// The command families serving each kind of object
+        pub kinds: Vec<u8>,
+        pub max_frame_sz: u32,
+        pub server_version: String,
+}
+
+
+impl ServerInfo {
+        fn decode(val: &[u8]) -> io::Result<Self> {
+                let malformed = || io::Error::new(io::ErrorKind::InvalidData, "impl ServerInfo: decode: malformed response");
+                let [min_version, max_version, kind_cnt, rest @ ..] = val else {
+                        return Err(malformed());
+                };
+                let (kinds, rest) = rest.split_at_checked(*kind_cnt as usize).ok_or_else(malformed)?;
+                let [f0, f1, f2, f3, version_len, rest @ ..] = rest else {
+                        return Err(malformed());
+                };
+                if rest.len() != *version_len as usize {
+                        return Err(malformed());
+                }
+                Ok(Self {
+                        min_version: *min_version,
+                        max_version: *max_version,
+                        kinds: kinds.to_vec(),
+                        max_frame_sz: u32::from_le_bytes([*f0, *f1, *f2, *f3]),
+                        server_version: String::from_utf8_lossy(rest).into_owned(),
+                })
+        }
+}
+
+
+pub struct Client {
+        endpoint: Endpoint,
+        max_idle: usize,
+        idle: Mutex<Vec<Connection>>,
+}
+
+
+impl Client {
+        pub const DEFAULT_MAX_IDLE: usize = 8;
+
+        /// A client that connects lazily, keeping at most `DEFAULT_MAX_IDLE`
+        /// idle connections.
+        #[must_use]
+        pub fn new(endpoint: Endpoint) -> Self {
+                Self::with_max_idle(endpoint, Self::DEFAULT_MAX_IDLE)
+        }
+
+        #[must_use]
+        pub fn with_max_idle(endpoint: Endpoint, max_idle: usize) -> Self {
+                Self {
+                        endpoint,
+                        max_idle,
+                        idle: Mutex::new(Vec::new()),
+                }
+        }
+
+        /// Connect to a server, failing early if it can't be reached.
+        pub async fn connect(endpoint: Endpoint) -> io::Result<Self> {
+                let client = Self::new(endpoint);
+                let conn = Connection::connect(&client.endpoint).await?;
+                client.release(conn);
+                Ok(client)
+        }
+
+        #[must_use]
+        pub fn endpoint(&self) -> &Endpoint {
+                &self.endpoint
+        }
+
+        fn acquire(&self) -> Option<Connection> {
+                self.idle.lock().unwrap_or_else(PoisonError::into_inner).pop()
+        }
+
+        fn release(&self, conn: Connection) {
+                let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+                if idle.len() < self.max_idle {
+                        idle.push(conn);
+                }
+        }
+
+        /// Run a command and return the value of its response. An error
+        /// response is returned as an error wrapping a `ServerError`.
+        pub async fn call(&self, family: u8, op: u8, val: &[u8]) -> io::Result<Vec<u8>> {
+                let (mut conn, pooled) = match self.acquire() {
+                        Some(conn) => (conn, true),
+                        None => (Connection::connect(&self.endpoint).await?, false),
+                };
+                let res = match conn.exchange(family, op, val).await {
+                        Err(e) if pooled && is_disconnect(&e) && (!conn.request_sent() || is_read_only(family, op)) => {
+                                conn = Connection::connect(&self.endpoint).await?;
+                                conn.exchange(family, op, val).await
+                        }
+                        res => res,
+                };
+                let res = res?;
+                self.release(conn);
+                res.map_err(proto::ServerError::into_io_error)
+        }
+
+        pub async fn info(&self) -> io::Result<ServerInfo> {
+                ServerInfo::decode(&self.call(proto::FAMILY_CTL, proto::OP_CTL_INFO, &[]).await?)
+        }
+
+        /// Write the databases to storage.
+        pub async fn save(&self) -> io::Result<()> {
+                self.call(proto::FAMILY_CTL, proto::OP_CTL_WRITE_DATA, &[]).await?;
+                Ok(())
+        }
+
+        /// Write the databases to storage and empty the write-ahead log.
+        pub async fn checkpoint(&self) -> io::Result<()> {
+                self.call(proto::FAMILY_CTL, proto::OP_CTL_CHECKPOINT, &[]).await?;
+                Ok(())
+        }
+
+        /// Reload the databases from storage and replay the write-ahead log
+        /// onto them. The log is replayed onto the snapshot it follows, never
+        /// onto the state the server holds, so replaying twice is harmless.
+        pub async fn replay(&self) -> io::Result<()> {
+                self.call(proto::FAMILY_CTL, proto::OP_CTL_LOAD_DATA, &[]).await?;
+                Ok(())
+        }
+
+        /// Create a database, optionally named.
+        pub async fn create_database(&self, db_id: u8, name: Option<&str>) -> io::Result<()> {
+                let val = [&[db_id], name.unwrap_or_default().as_bytes()].concat();
+                self.call(proto::FAMILY_CTL, proto::OP_CTL_CREATE_DATABASE, &val).await?;
+                Ok(())
+        }
+
+        pub async fn create_filter(&self, db_id: u8, bf_id: u8, params: &FilterParams) -> io::Result<()> {
+                let mut spec = vec![bf_id];
+                params.encode_into(&mut spec);
+                let mut val = vec![db_id];
+                proto::encode_lv(&mut val, &spec)?;
+                self.call(proto::FAMILY_DB, params.op(), &val).await?;
+                Ok(())
+        }
+
+        pub async fn filter_info(&self, db_id: u8, bf_id: u8) -> io::Result<FilterInfo> {
+                FilterInfo::decode(&self.call(proto::FAMILY_BF, proto::OP_BF_INFO, &[db_id, bf_id]).await?)
+        }
+
+        pub async fn add(&self, db_id: u8, bf_id: u8, elt: &[u8]) -> io::Result<()> {
+                let mut val = vec![db_id, bf_id];
+                proto::encode_lv(&mut val, elt)?;
+                self.call(proto::FAMILY_BF, proto::OP_BF_ADD, &val).await?;
+                Ok(())
+        }
+
+        /// Add the elements, in as few commands as they fit in.
+        pub async fn add_batch(&self, db_id: u8, bf_id: u8, elts: &[&[u8]]) -> io::Result<()> {
+                for batch in proto::encode_batches(elts)? {
+                        let mut val = vec![db_id, bf_id];
+                        proto::encode_lv(&mut val, &batch)?;
+                        self.call(proto::FAMILY_BF, proto::OP_BF_ADD_BATCH, &val).await?;
+                }
+                Ok(())
+        }
+
+        pub async fn has(&self, db_id: u8, bf_id: u8, elt: &[u8]) -> io::Result<bool> {
+                let mut val = vec![db_id, bf_id];
+                proto::encode_lv(&mut val, elt)?;
+                let ans = self.call(proto::FAMILY_BF, proto::OP_BF_HAS, &val).await?;
+                Ok(ans == [proto::TOKEN_TRUE])
+        }
+
+        /// Check the elements, in as few commands as they fit in. The answers
+        /// are in the order of the elements.
+        pub async fn has_batch(&self, db_id: u8, bf_id: u8, elts: &[&[u8]]) -> io::Result<Vec<bool>> {
+                let mut found = Vec::with_capacity(elts.len());
+                for batch in proto::encode_batches(elts)? {
+                        let mut val = vec![db_id, bf_id];
+                        proto::encode_lv(&mut val, &batch)?;
+                        let ans = self.call(proto::FAMILY_BF, proto::OP_BF_HAS_BATCH, &val).await?;
+                        found.extend(ans.iter().map(|ans| *ans == proto::TOKEN_TRUE));
+                }
+                if found.len() != elts.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl Client: has_batch: answer count mismatch"));
+                }
+                Ok(found)
+        }
+}
+
+
+// Whether a command leaves the state as it is, so that running it twice is
+// harmless
+fn is_read_only(family: u8, op: u8) -> bool {
+        matches!((family, op),
+                (proto::FAMILY_CTL, proto::OP_CTL_HANDSHAKE | proto::OP_CTL_INFO)
+                | (proto::FAMILY_BF, proto::OP_BF_HAS | proto::OP_BF_HAS_BATCH | proto::OP_BF_INFO))
+}
+
+
+// Whether the connection was found closed, as an idle connection is once the
+// server has dropped it
+fn is_disconnect(e: &io::Error) -> bool {
+        matches!(e.kind(),
+                io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::UnexpectedEof)
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use proto::ServerError;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        #[test]
+        fn test_decode_info() {
+                let info = ServerInfo::decode(&[1, 2, 2, 3, 5, 0, 0, 16, 0, 3, b'0', b'.', b'1']).unwrap();
+                assert!(info.kinds == [3, 5] && info.max_frame_sz == 1 << 20 && info.server_version == "0.1");
+                assert!(ServerInfo::decode(&[1, 2, 3, 3]).is_err());
+        }
+
+        // Serve one connection like a server would, answering a has with
+        // whether the element starts with 'a', and handing the connection
+        // back after the given number of commands.
+        async fn serve(listener: &tokio::net::UnixListener, cmd_cnt: usize) -> tokio::net::UnixStream {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                for _ in 0..cmd_cnt {
+                        let mut head = [0; proto::HEADER_SZ];
+                        stream.read_exact(&mut head).await.unwrap();
+                        let mut val = vec![0; u32::from_le_bytes(head[4..8].try_into().unwrap()) as usize];
+                        stream.read_exact(&mut val).await.unwrap();
+                        let (rc, ans) = match (head[0], head[1]) {
+                                (proto::FAMILY_CTL, proto::OP_CTL_HANDSHAKE) => (0, val),
+                                (proto::FAMILY_BF, proto::OP_BF_HAS) => (0, vec![u8::from(val[3] == b'a')]),
+                                (proto::FAMILY_BF, proto::OP_BF_HAS_BATCH) => {
+                                        let mut ans = Vec::new();
+                                        let mut elts = &val[3..];
+                                        while let [len, rest @ ..] = elts {
+                                                ans.push(u8::from(rest[0] == b'a'));
+                                                elts = &rest[*len as usize..];
+                                        }
+                                        (0, ans)
+                                }
+                                _ => (ServerError::OBJECT_NOT_FOUND, b"no filter 1".to_vec()),
+                        };
+                        let mut resp = vec![rc, 0xFF, head[2], head[3]];
+                        resp.extend(u32::try_from(ans.len()).unwrap().to_le_bytes());
+                        resp.extend(ans);
+                        stream.write_all(&resp).await.unwrap();
+                }
+                stream
+        }
+
+        #[tokio::test]
+        async fn test_client() {
+                let path = std::env::temp_dir().join(format!("qstra-client-test-{}.sock", std::process::id()));
+                let _ = std::fs::remove_file(&path);
+                let listener = tokio::net::UnixListener::bind(&path).unwrap();
+                let endpoint = Endpoint::unix(&path);
+
+                let server = async {
+                        // The first connection is dropped after two commands
+                        // besides the handshake, and replaced by the second
+                        serve(&listener, 3).await;
+                        serve(&listener, 3).await;
+
+                        // The third reads a change without answering it, and
+                        // nothing listens to replace it
+                        let mut stream = serve(&listener, 2).await;
+                        let mut head = [0; proto::HEADER_SZ];
+                        stream.read_exact(&mut head).await.unwrap();
+                        let mut val = vec![0; u32::from_le_bytes(head[4..8].try_into().unwrap()) as usize];
+                        stream.read_exact(&mut val).await.unwrap();
+                        let _ = std::fs::remove_file(&path);
+                };
+                let client = async {
+                        let client = Client::connect(endpoint).await.unwrap();
+                        assert!(client.has(0, 1, b"apple").await.unwrap());
+                        let e = client.add(0, 1, b"pear").await.unwrap_err();
+                        assert!(e.kind() == io::ErrorKind::NotFound);
+                        let server_error = e.get_ref().and_then(|e| e.downcast_ref::<ServerError>()).unwrap();
+                        assert!(server_error.code == ServerError::OBJECT_NOT_FOUND && server_error.detail == "no filter 1");
+
+                        let elts: Vec<&[u8]> = vec![b"apple", b"plum", b"apricot"];
+                        assert!(client.has_batch(0, 1, &elts).await.unwrap() == [true, false, true]);
+                        assert!(!client.has(0, 1, b"plum").await.unwrap());
+
+                        // A read is retried on a fresh connection, but a
+                        // change the server may have run is not
+                        assert!(client.has(0, 1, b"avocado").await.unwrap());
+                        let e = client.add(0, 1, b"pear").await.unwrap_err();
+                        assert!(e.kind() == io::ErrorKind::UnexpectedEof);
+                };
+                tokio::join!(server, client);
+                let _ = std::fs::remove_file(&path);
+        }
+}
\ No newline at end of file
@@ -0,0 +1,136 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Connect to a server and exchange commands with it.
+
+
+use std::io;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::proto;
+
+
+// The largest response accepted, which bounds what a confused peer can make
+// the client allocate
+const MAX_RESPONSE_SZ: usize = 1 << 26;
+
+
+/// Where a server listens for connections.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+        Unix(PathBuf),
+        Tcp(String),
+}
+
+
+impl Endpoint {
+        #[must_use]
+        pub fn unix(path: impl Into<PathBuf>) -> Self {
+                Endpoint::Unix(path.into())
+        }
+
+        #[must_use]
+        pub fn tcp(addr: impl Into<String>) -> Self {
+                Endpoint::Tcp(addr.into())
+        }
+}
+
+
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> Stream for S {}
+
+
+/// A single connection to a server, which runs one command at a time.
+pub struct Connection {
+        stream: Box<dyn Stream>,
+        next_req_id: u16,
+        // Whether any of the last request was written
+        sent: bool,
+}
+
+
+impl Connection {
+        /// Connect to a server and agree on the protocol version with it.
+        pub async fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+                let stream: Box<dyn Stream> = match endpoint {
+                        Endpoint::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+                        Endpoint::Tcp(addr) => {
+                                let stream = tokio::net::TcpStream::connect(addr).await?;
+                                stream.set_nodelay(true)?;
+                                Box::new(stream)
+                        }
+                };
+                Self::handshake(stream).await
+        }
+
+        /// Agree on the protocol version over an established stream.
+        pub async fn from_stream<S>(stream: S) -> io::Result<Self>
+        where S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        {
+                Self::handshake(Box::new(stream)).await
+        }
+
+        // A server that refuses the version answers in the first version of
+        // the protocol, which fails to decode as a response frame.
+        async fn handshake(stream: Box<dyn Stream>) -> io::Result<Self> {
+                let mut conn = Self { stream, next_req_id: 0, sent: false };
+                let version = conn.call(proto::FAMILY_CTL, proto::OP_CTL_HANDSHAKE, &[proto::PROTOCOL_VERSION]).await?;
+                if version != [proto::PROTOCOL_VERSION] {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl Connection: handshake: unexpected protocol version"));
+                }
+                Ok(conn)
+        }
+
+        /// Run a command and return the value of its response. An error
+        /// response is returned as an error wrapping a `ServerError`.
+        pub async fn call(&mut self, family: u8, op: u8, val: &[u8]) -> io::Result<Vec<u8>> {
+                self.exchange(family, op, val).await?
+                        .map_err(proto::ServerError::into_io_error)
+        }
+
+        /// Whether any of the last request went out, so that the server may
+        /// have run it even if the connection failed.
+        #[must_use]
+        pub fn request_sent(&self) -> bool {
+                self.sent
+        }
+
+        // Run a command. Only a failure of the connection is an error here,
+        // after which the connection shouldn't be used again.
+        pub(crate) async fn exchange(&mut self, family: u8, op: u8, val: &[u8]) -> io::Result<Result<Vec<u8>, proto::ServerError>> {
+                let req_id = self.next_req_id;
+                self.next_req_id = (self.next_req_id + 1) % proto::NO_REQUEST_ID;
+
+                let mut frame = Vec::new();
+                proto::encode_frame(&mut frame, family, op, req_id, val)?;
+                self.sent = false;
+                let written = self.stream.write(&frame).await?;
+                self.sent = written > 0;
+                self.stream.write_all(&frame[written..]).await?;
+
+                let mut head = [0; proto::HEADER_SZ];
+                self.stream.read_exact(&mut head).await?;
+                let header = proto::ResponseHeader::decode(&head)?;
+                if header.len > MAX_RESPONSE_SZ {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl Connection: exchange: response too large"));
+                }
+                let mut val = vec![0; header.len];
+                self.stream.read_exact(&mut val).await?;
+                if header.req_id != req_id {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl Connection: exchange: response to another request"));
+                }
+
+                if header.is_success() {
+                        return Ok(Ok(val));
+                }
+                Ok(Err(proto::ServerError {
+                        code: header.rc,
+                        detail: String::from_utf8_lossy(&val).into_owned(),
+                }))
+        }
+}
\ No newline at end of file
@@ -0,0 +1,12 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+
+pub mod client;
+pub mod conn;
+
+pub use qstra_proto::frame as proto;
+pub use qstra_proto::{FilterInfo, FilterParams, ServerError};
+
+pub use client::{Client, ServerInfo};
+pub use conn::{Connection, Endpoint};
\ No newline at end of file
@@ -26,6 +26,12 @@ impl BitVec {
                 Self { words: vec![0; size.div_ceil(USIZE_BITS).max(1)], size }
         }
 
+        /// The number of bits in the vector.
+        #[must_use]
+        pub fn size(&self) -> usize {
+                self.size
+        }
+
         #[inline]
         fn get_idxs(&self, i: usize) -> io::Result<(usize, usize)> {
                 if i >= self.size {
@@ -54,10 +60,17 @@ impl srl::Deserializable for BitVec {
         where Self: Sized
         {
                 let buf = &tlv.val;
-                Ok(Self {
+                if buf.len() < srl::USIZE_OFFSET {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl BitVec: deserialize: too few bytes in buffer"));
+                }
+                let bv = Self {
                         words: srl::DeserTLV::deserialize_vec_usize(&buf[8..])?,
                         size: srl::DeserTLV::deserialize_usize(&buf[0..])?
-                })
+                };
+                if bv.words.len() != bv.size.div_ceil(USIZE_BITS).max(1) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl BitVec: deserialize: word count mismatch"));
+                }
+                Ok(bv)
         }
 }
 
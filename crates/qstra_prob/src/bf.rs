@@ -8,7 +8,31 @@
 use std::io;
 
 use qstra_prim::bv;
-use qstra_stor::srl;
+use qstra_stor::srl::{self, Deserializable, Serializable};
+
+use crate::hash;
+
+
+/// Membership operations shared by the plain and the scalable bloom filter.
+pub trait MembershipFilter {
+        fn add(&mut self, bytes: &[u8]) -> io::Result<()>;
+        fn has(&self, bytes: &[u8]) -> io::Result<bool>;
+
+        /// Add the elements in order. A filter that may fail to add one
+        /// checks first that it can add them all, and is otherwise left
+        /// as it was.
+        fn add_batch(&mut self, elts: &[&[u8]]) -> io::Result<()> {
+                for elt in elts {
+                        self.add(elt)?;
+                }
+                Ok(())
+        }
+}
+
+
+pub const MAX_BIT_CNT: usize = u32::MAX as usize;
+pub const MIN_HFN_CNT: usize = 2;
+pub const MAX_HFN_CNT: usize = u8::MAX as usize;
 
 
 #[derive(Debug)]
@@ -37,24 +61,80 @@ impl BloomFilterStructure {
                         inner: BloomFilter::new(cpty, bit_cnt, hfn_cnt),
                 }
         }
+
+        #[must_use]
+        pub fn with_params(id: u8, dbid: u8, params: &BloomFilterParams) -> Self {
+                Self::new(id, dbid, params.bit_cnt, params.bit_cnt, params.hfn_cnt)
+        }
 }
 
 
+/// Sizing of a bloom filter, either given explicitly or derived from an
+/// expected item count and a target false-positive rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomFilterParams {
+        pub bit_cnt: usize,
+        pub hfn_cnt: usize,
+}
+
+
+impl Default for BloomFilterParams {
+        fn default() -> Self {
+                Self { bit_cnt: 1000, hfn_cnt: 2 }
+        }
+}
+
+
+impl BloomFilterParams {
+        #[must_use]
+        pub fn new(bit_cnt: usize, hfn_cnt: usize) -> Option<Self> {
+                if !(1..=MAX_BIT_CNT).contains(&bit_cnt) || !(MIN_HFN_CNT..=MAX_HFN_CNT).contains(&hfn_cnt) {
+                        return None;
+                }
+                Some(Self { bit_cnt, hfn_cnt })
+        }
+
+        /// The size in bytes of the bits of a filter of these parameters.
+        #[must_use]
+        pub fn byte_len(&self) -> usize {
+                self.bit_cnt.div_ceil(8)
+        }
+
+        // m = -n ln(p) / ln(2)^2 and k = (m/n) ln(2)
+        #[must_use]
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        pub fn from_estimate(item_cnt: usize, fpr: f64) -> Option<Self> {
+                if item_cnt == 0 || !(fpr > 0.0 && fpr < 1.0) {
+                        return None;
+                }
+                let n = item_cnt as f64;
+                let ln2 = std::f64::consts::LN_2;
+                let m = (-n * fpr.ln() / (ln2 * ln2)).ceil();
+                if !m.is_finite() || m > MAX_BIT_CNT as f64 {
+                        return None;
+                }
+                let bit_cnt = (m as usize).max(1);
+                let k = ((bit_cnt as f64 / n) * ln2).round() as usize;
+                Self::new(bit_cnt, k.clamp(MIN_HFN_CNT, MAX_HFN_CNT))
+        }
+}
+
+
+// A serialized bloom filter structure is laid out as
+//
+//   id (u8) | dbid (u8) | bloom filter fields
 impl srl::Deserializable for BloomFilterStructure {
         fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
         where Self: Sized
         {
                 let buf = &tlv.val;
-                let bv_tlv = srl::DeserTLV::new(&buf[11..])?;
-                let bf = BloomFilter {
-                        hfn_cnt: 2,
-                        bit_cnt: srl::DeserTLV::deserialize_usize(&buf[3..])?,
-                        bits: bv::BitVec::deserialize(&bv_tlv)?
-                };
+                if buf.len() < 2*srl::U8_OFFSET {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl BloomFilterStructure: deserialize: too few bytes in buffer"));
+                }
                 Ok(Self {
                         dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
                         id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
-                        inner: bf,
+                        inner: BloomFilter::deserialize_fields(&buf[2..])?,
                 })
         }
 }
@@ -65,23 +145,75 @@ impl srl::Serializable<BloomFilterStructure> for BloomFilterStructure {
                 let mut tlv = srl::SerTLV::new(srl::SerializableType::BloomFilterStructure);
                 tlv.serialize_u8(self.id);
                 tlv.serialize_u8(self.dbid);
+                self.inner.serialize_fields(&mut tlv)?;
+                Ok(tlv)
+        }
+}
 
-                #[allow(clippy::cast_possible_truncation)]
-                tlv.serialize_u8(self.inner.hfn_cnt as u8);
 
-                tlv.serialize_usize(self.inner.bit_cnt)?;
-                let bv_tlv = self.inner.bits.serialize()?;
-                tlv.serialize_sertlv(&bv_tlv)?;
+impl srl::Deserializable for BloomFilter {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                BloomFilter::deserialize_fields(tlv.val)
+        }
+}
+
+
+impl srl::Serializable<BloomFilter> for BloomFilter {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::BloomFilter);
+                self.serialize_fields(&mut tlv)?;
                 Ok(tlv)
         }
 }
 
 
+/// The pair of base hash functions from which the filter derives its
+/// indices.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HashFamily {
+        /// djb2 and sdbm, combined with the Kirsch–Mitzenmacher optimization
+        #[default]
+        Djb2Sdbm = 0,
+        /// Two differently salted FNV-1a hashes with a splitmix64 finalizer
+        Fnv1aMix = 1,
+}
+
+
+impl TryFrom<u8> for HashFamily {
+        type Error = io::Error;
+
+        fn try_from(byte: u8) -> io::Result<Self> {
+                match byte {
+                        0 => Ok(HashFamily::Djb2Sdbm),
+                        1 => Ok(HashFamily::Fnv1aMix),
+                        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown discriminant for HashFamily")),
+                }
+        }
+}
+
+
+impl HashFamily {
+        #[must_use]
+        pub fn value(&self) -> u8 {
+                match self {
+                        HashFamily::Djb2Sdbm => 0,
+                        HashFamily::Fnv1aMix => 1,
+                }
+        }
+}
+
+
 #[derive(Debug)]
 pub struct BloomFilter {
         pub bits: bv::BitVec,
         pub hfn_cnt: usize,
         pub bit_cnt: usize,
+        pub hfn_family: HashFamily,
+        pub seed: usize,
+        pub insert_cnt: usize,
 }
 
 
@@ -92,6 +224,9 @@ impl Default for BloomFilter {
                         bits: bv::BitVec::with_capacity(1000),
                         bit_cnt: 1000,
                         hfn_cnt: 2,
+                        hfn_family: HashFamily::default(),
+                        seed: 0,
+                        insert_cnt: 0,
                 }
         }
 }
@@ -104,13 +239,70 @@ impl BloomFilter {
                         bits: bv::BitVec::with_capacity(cpty),
                         bit_cnt,
                         hfn_cnt,
+                        hfn_family: HashFamily::default(),
+                        seed: 0,
+                        insert_cnt: 0,
+                }
+        }
+
+        // The fields of a bloom filter are serialized as
+        //
+        //   hfn_cnt (u8) | bit_cnt (usize) | bits (BitVec TLV)
+        //   | hfn_family (u8) | seed (usize) | insert_cnt (usize)
+        //
+        // Snapshots written before the fields following the bit vector
+        // existed are still accepted; the missing fields then take their
+        // default values.
+        fn deserialize_fields(buf: &[u8]) -> io::Result<Self> {
+                if buf.len() < srl::U8_OFFSET + srl::USIZE_OFFSET {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl BloomFilter: deserialize_fields: too few bytes in buffer"));
+                }
+                let bv_tlv = srl::DeserTLV::new(&buf[9..])?;
+                let mut bf = BloomFilter {
+                        hfn_cnt: srl::DeserTLV::deserialize_u8(&buf[0..])? as usize,
+                        bit_cnt: srl::DeserTLV::deserialize_usize(&buf[1..])?,
+                        bits: bv::BitVec::deserialize(&bv_tlv)?,
+                        hfn_family: HashFamily::default(),
+                        seed: 0,
+                        insert_cnt: 0,
+                };
+                if bf.bit_cnt == 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl BloomFilter: deserialize_fields: zero bit count"));
+                }
+                if bf.bit_cnt != bf.bits.size() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl BloomFilter: deserialize_fields: bit vector length mismatch"));
+                }
+
+                let loc = 9 + bv_tlv.len();
+                if buf.len() > loc {
+                        if buf.len() < loc + srl::U8_OFFSET + 2*srl::USIZE_OFFSET {
+                                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl BloomFilter: deserialize_fields: truncated hash parameters"));
+                        }
+                        bf.hfn_family = srl::DeserTLV::deserialize_u8(&buf[loc..])?.try_into()?;
+                        bf.seed = srl::DeserTLV::deserialize_usize(&buf[loc+1..])?;
+                        bf.insert_cnt = srl::DeserTLV::deserialize_usize(&buf[loc+9..])?;
                 }
+                Ok(bf)
+        }
+
+        fn serialize_fields(&self, tlv: &mut srl::SerTLV) -> io::Result<()> {
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_u8(self.hfn_cnt as u8);
+
+                tlv.serialize_usize(self.bit_cnt)?;
+                let bv_tlv = self.bits.serialize()?;
+                tlv.serialize_sertlv(&bv_tlv)?;
+                tlv.serialize_u8(self.hfn_family.value());
+                tlv.serialize_usize(self.seed)?;
+                tlv.serialize_usize(self.insert_cnt)?;
+                Ok(())
         }
 
         #[inline]
         pub fn add(&mut self, bytes: &[u8]) -> io::Result<()> {
                 let h0 = self.hash0(bytes);
                 let h1 = self.hash1(bytes);
+                self.insert_cnt += 1;
                 self.bits.set(h0)?;
                 self.bits.set(h1)?;
                 if self.hfn_cnt < 3 {
@@ -142,23 +334,55 @@ impl BloomFilter {
                 Ok(true)
         }
 
-        // The djb2 hash function
         #[inline]
         fn hash0(&self, bytes: &[u8]) -> usize {
-                let mut h: usize = 5381;
-                for b in bytes {
-                        h = ((h << 5).wrapping_add(h)).wrapping_add(*b as usize);
+                match self.hfn_family {
+                        HashFamily::Djb2Sdbm => hash::djb2(bytes, self.seed) % self.bit_cnt,
+                        HashFamily::Fnv1aMix => hash::fnv1a_mix(bytes, self.seed, 0) % self.bit_cnt,
                 }
-                h % self.bit_cnt
         }
 
-        // The sdbm hash function
         #[inline]
         fn hash1(&self, bytes: &[u8]) -> usize {
-                let mut h: usize = 0;
-                for b in bytes {
-                        h = (((*b as usize).wrapping_add(h << 6)).wrapping_add(h << 16)).wrapping_sub(h);
+                match self.hfn_family {
+                        HashFamily::Djb2Sdbm => hash::sdbm(bytes, self.seed) % self.bit_cnt,
+                        HashFamily::Fnv1aMix => hash::fnv1a_mix(bytes, self.seed, 1) % self.bit_cnt,
+                }
+        }
+}
+
+
+impl MembershipFilter for BloomFilter {
+        fn add(&mut self, bytes: &[u8]) -> io::Result<()> {
+                BloomFilter::add(self, bytes)
+        }
+
+        fn has(&self, bytes: &[u8]) -> io::Result<bool> {
+                BloomFilter::has(self, bytes)
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_roundtrip() {
+                let params = BloomFilterParams::new(100, 3).unwrap();
+                let mut bfs = BloomFilterStructure::with_params(1, 0, &params);
+                bfs.inner.add(b"a").unwrap();
+                let tlv = bfs.inner.serialize().unwrap();
+                let restored = BloomFilter::deserialize(&srl::DeserTLV { srl_type: srl::SerializableType::BloomFilter, val: &tlv.val }).unwrap();
+                assert!(restored.bit_cnt == 100 && restored.has(b"a").unwrap());
+
+                // A bit count of zero, or one the bit vector doesn't hold, is
+                // refused rather than hashed into
+                for bit_cnt in [0usize, 101, 1000] {
+                        let mut val = tlv.val.clone();
+                        val[1..9].copy_from_slice(&bit_cnt.to_le_bytes());
+                        let e = BloomFilter::deserialize(&srl::DeserTLV { srl_type: srl::SerializableType::BloomFilter, val: &val }).unwrap_err();
+                        assert!(e.kind() == io::ErrorKind::InvalidData);
                 }
-                h % self.bit_cnt
         }
 }
\ No newline at end of file
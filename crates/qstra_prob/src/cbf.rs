@@ -0,0 +1,320 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a counting bloom filter.
+
+
+use std::io;
+
+use qstra_stor::srl;
+
+use crate::bf::BloomFilterParams;
+use crate::hash;
+
+
+#[derive(Debug)]
+pub struct CountingBloomFilterStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: CountingBloomFilter,
+}
+
+
+impl CountingBloomFilterStructure {
+        #[must_use]
+        pub fn new_default(id: u8, dbid: u8) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: CountingBloomFilter::default(),
+                }
+        }
+
+        #[must_use]
+        pub fn with_params(id: u8, dbid: u8, params: &BloomFilterParams, width: CounterWidth) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: CountingBloomFilter::new(params.bit_cnt, params.hfn_cnt, width),
+                }
+        }
+}
+
+
+// A serialized counting bloom filter structure is laid out as
+//
+//   id (u8) | dbid (u8) | width (u8) | hfn_cnt (u8) | cnt (usize)
+//   | seed (usize) | insert_cnt (usize) | counters (u8...)
+impl srl::Deserializable for CountingBloomFilterStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                if buf.len() < 4*srl::U8_OFFSET + 3*srl::USIZE_OFFSET {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CountingBloomFilterStructure: deserialize: too few bytes in buffer"));
+                }
+                let width = CounterWidth::try_from(srl::DeserTLV::deserialize_u8(&buf[2..])?)?;
+                let cnt = srl::DeserTLV::deserialize_usize(&buf[4..])?;
+                if cnt == 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl CountingBloomFilterStructure: deserialize: zero counter count"));
+                }
+                let counters = srl::DeserTLV::deserialize_vec_u8(&buf[28..])?;
+                if counters.len() != width.byte_len(cnt) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl CountingBloomFilterStructure: deserialize: counter array length mismatch"));
+                }
+                let cbf = CountingBloomFilter {
+                        counters,
+                        width,
+                        cnt,
+                        hfn_cnt: srl::DeserTLV::deserialize_u8(&buf[3..])? as usize,
+                        seed: srl::DeserTLV::deserialize_usize(&buf[12..])?,
+                        insert_cnt: srl::DeserTLV::deserialize_usize(&buf[20..])?,
+                };
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: cbf,
+                })
+        }
+}
+
+
+impl srl::Serializable<CountingBloomFilterStructure> for CountingBloomFilterStructure {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::CountingBloomFilterStructure);
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_u8(self.inner.width.value());
+
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_u8(self.inner.hfn_cnt as u8);
+
+                tlv.serialize_usize(self.inner.cnt)?;
+                tlv.serialize_usize(self.inner.seed)?;
+                tlv.serialize_usize(self.inner.insert_cnt)?;
+                tlv.serialize_slice_u8(&self.inner.counters)?;
+                Ok(tlv)
+        }
+}
+
+
+/// The number of bits in each counter.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CounterWidth {
+        #[default]
+        Bits4 = 4,
+        Bits8 = 8,
+        Bits16 = 16,
+}
+
+
+impl TryFrom<u8> for CounterWidth {
+        type Error = io::Error;
+
+        fn try_from(byte: u8) -> io::Result<Self> {
+                match byte {
+                        4 => Ok(CounterWidth::Bits4),
+                        8 => Ok(CounterWidth::Bits8),
+                        16 => Ok(CounterWidth::Bits16),
+                        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown discriminant for CounterWidth")),
+                }
+        }
+}
+
+
+impl CounterWidth {
+        #[must_use]
+        pub fn value(&self) -> u8 {
+                match self {
+                        CounterWidth::Bits4 => 4,
+                        CounterWidth::Bits8 => 8,
+                        CounterWidth::Bits16 => 16,
+                }
+        }
+
+        /// The value at which a counter saturates. A saturated counter is
+        /// never decremented again, since the true count is unknown.
+        #[must_use]
+        pub fn max(&self) -> u16 {
+                match self {
+                        CounterWidth::Bits4 => 0xF,
+                        CounterWidth::Bits8 => 0xFF,
+                        CounterWidth::Bits16 => 0xFFFF,
+                }
+        }
+
+        #[must_use]
+        fn byte_len(self, cnt: usize) -> usize {
+                match self {
+                        CounterWidth::Bits4 => cnt.div_ceil(2),
+                        CounterWidth::Bits8 => cnt,
+                        CounterWidth::Bits16 => 2*cnt,
+                }
+        }
+}
+
+
+#[derive(Debug)]
+pub struct CountingBloomFilter {
+        counters: Vec<u8>,
+        pub width: CounterWidth,
+        pub cnt: usize,
+        pub hfn_cnt: usize,
+        pub seed: usize,
+        pub insert_cnt: usize,
+}
+
+
+impl Default for CountingBloomFilter {
+        fn default() -> Self {
+                Self::new(1000, 2, CounterWidth::default())
+        }
+}
+
+
+impl CountingBloomFilter {
+        #[must_use]
+        pub fn new(cnt: usize, hfn_cnt: usize, width: CounterWidth) -> Self {
+                Self {
+                        counters: vec![0; width.byte_len(cnt)],
+                        width,
+                        cnt,
+                        hfn_cnt,
+                        seed: 0,
+                        insert_cnt: 0,
+                }
+        }
+
+        pub fn add(&mut self, bytes: &[u8]) -> io::Result<()> {
+                let max = self.width.max();
+                for idx in self.idxs(bytes) {
+                        let c = self.get(idx)?;
+                        if c < max {
+                                self.put(idx, c + 1)?;
+                        }
+                }
+                self.insert_cnt += 1;
+                Ok(())
+        }
+
+        /// Withdraw an element. Returns false and leaves the filter untouched
+        /// if the element is certainly not in the filter.
+        pub fn remove(&mut self, bytes: &[u8]) -> io::Result<bool> {
+                if !self.has(bytes)? {
+                        return Ok(false);
+                }
+                let max = self.width.max();
+                for idx in self.idxs(bytes) {
+                        let c = self.get(idx)?;
+                        if c > 0 && c < max {
+                                self.put(idx, c - 1)?;
+                        }
+                }
+                self.insert_cnt = self.insert_cnt.saturating_sub(1);
+                Ok(true)
+        }
+
+        pub fn has(&self, bytes: &[u8]) -> io::Result<bool> {
+                for idx in self.idxs(bytes) {
+                        if self.get(idx)? == 0 {
+                                return Ok(false);
+                        }
+                }
+                Ok(true)
+        }
+
+        // The same index sequence as the plain bloom filter: two base hashes
+        // followed by the Kirsch–Mitzenmacher combinations.
+        fn idxs(&self, bytes: &[u8]) -> impl Iterator<Item = usize> {
+                let cnt = self.cnt;
+                let h0 = hash::djb2(bytes, self.seed) % cnt;
+                let h1 = hash::sdbm(bytes, self.seed) % cnt;
+                [h0, h1].into_iter()
+                        .chain((3..=self.hfn_cnt).map(move |i| (h0.wrapping_add(h1.wrapping_mul(i))) % cnt))
+        }
+
+        #[inline]
+        fn get(&self, i: usize) -> io::Result<u16> {
+                self.check_idx(i)?;
+                Ok(match self.width {
+                        CounterWidth::Bits4 => u16::from((self.counters[i/2] >> ((i%2)*4)) & 0xF),
+                        CounterWidth::Bits8 => u16::from(self.counters[i]),
+                        CounterWidth::Bits16 => u16::from_le_bytes([self.counters[2*i], self.counters[2*i+1]]),
+                })
+        }
+
+        #[inline]
+        #[allow(clippy::cast_possible_truncation)]
+        fn put(&mut self, i: usize, c: u16) -> io::Result<()> {
+                self.check_idx(i)?;
+                match self.width {
+                        CounterWidth::Bits4 => {
+                                let shift = (i%2)*4;
+                                self.counters[i/2] = (self.counters[i/2] & !(0xF << shift)) | ((c as u8 & 0xF) << shift);
+                        }
+                        CounterWidth::Bits8 => { self.counters[i] = c as u8; }
+                        CounterWidth::Bits16 => { self.counters[2*i..2*i+2].copy_from_slice(&c.to_le_bytes()); }
+                }
+                Ok(())
+        }
+
+        #[inline]
+        fn check_idx(&self, i: usize) -> io::Result<()> {
+                if i >= self.cnt {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("impl CountingBloomFilter: index out of bounds: cnt is {} but requested index is {}", self.cnt, i)));
+                }
+                Ok(())
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use qstra_stor::srl::{Deserializable, Serializable};
+
+        #[test]
+        fn test_add_remove() {
+                for width in [CounterWidth::Bits4, CounterWidth::Bits8, CounterWidth::Bits16] {
+                        let mut cbf = CountingBloomFilter::new(4096, 4, width);
+                        for i in 0..100u32 {
+                                cbf.add(&i.to_le_bytes()).unwrap();
+                        }
+                        for i in 0..100u32 {
+                                assert!(cbf.has(&i.to_le_bytes()).unwrap());
+                        }
+                        for i in 0..50u32 {
+                                assert!(cbf.remove(&i.to_le_bytes()).unwrap());
+                        }
+                        for i in 50..100u32 {
+                                assert!(cbf.has(&i.to_le_bytes()).unwrap());
+                        }
+                        assert!(cbf.insert_cnt == 50);
+                }
+        }
+
+        #[test]
+        fn test_saturation() {
+                let mut cbf = CountingBloomFilter::new(64, 2, CounterWidth::Bits4);
+                for _ in 0..20 {
+                        cbf.add(b"key").unwrap();
+                }
+                for _ in 0..20 {
+                        assert!(cbf.remove(b"key").unwrap());
+                }
+                // Saturated counters stick, so the element can't be removed
+                // below the width's maximum.
+                assert!(cbf.has(b"key").unwrap());
+        }
+
+        #[test]
+        fn test_deserialize_zero_cnt() {
+                let cbfs = CountingBloomFilterStructure { id: 0, dbid: 0, inner: CountingBloomFilter::new(0, 2, CounterWidth::Bits8) };
+                let mut buf = Vec::new();
+                cbfs.serialize().unwrap().serialize_into_buf(&mut buf).unwrap();
+                let e = CountingBloomFilterStructure::deserialize(&srl::DeserTLV::new(&buf).unwrap()).unwrap_err();
+                assert!(e.kind() == io::ErrorKind::InvalidData);
+        }
+}
\ No newline at end of file
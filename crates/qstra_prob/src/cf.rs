@@ -0,0 +1,330 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a cuckoo filter.
+//!
+//! Elements are stored as short fingerprints in one of two candidate buckets,
+//! following Fan et al., "Cuckoo Filter: Practically Better Than Bloom"
+//! (2014). The alternate bucket is derived from the fingerprint alone, so an
+//! element can be deleted and its fingerprint relocated without the original
+//! bytes.
+
+
+use std::io;
+
+use qstra_stor::srl;
+
+use crate::hash;
+
+
+#[derive(Debug)]
+pub struct CuckooFilterStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: CuckooFilter,
+}
+
+
+impl CuckooFilterStructure {
+        #[must_use]
+        pub fn with_params(id: u8, dbid: u8, params: &CuckooFilterParams) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: CuckooFilter::new(params),
+                }
+        }
+}
+
+
+// A serialized cuckoo filter structure is laid out as
+//
+//   id (u8) | dbid (u8) | fp_bits (u8) | bucket_sz (u8) | bucket_cnt (usize)
+//   | max_kicks (usize) | item_cnt (usize) | rng (usize) | slots (u16...)
+impl srl::Deserializable for CuckooFilterStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                let loc = 4*srl::U8_OFFSET + 4*srl::USIZE_OFFSET;
+                if buf.len() < loc {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CuckooFilterStructure: deserialize: too few bytes in buffer"));
+                }
+                let params = CuckooFilterParams::new(
+                        srl::DeserTLV::deserialize_usize(&buf[4..])?,
+                        srl::DeserTLV::deserialize_u8(&buf[3..])? as usize,
+                        srl::DeserTLV::deserialize_u8(&buf[2..])?,
+                        srl::DeserTLV::deserialize_usize(&buf[12..])?,
+                ).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "impl CuckooFilterStructure: deserialize: invalid parameters"))?;
+                let mut cf = CuckooFilter::new(&params);
+                if buf.len() != loc + 2*cf.slots.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl CuckooFilterStructure: deserialize: slot array length mismatch"));
+                }
+                for (slot, bytes) in cf.slots.iter_mut().zip(buf[loc..].chunks_exact(2)) {
+                        *slot = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                cf.item_cnt = srl::DeserTLV::deserialize_usize(&buf[20..])?;
+                cf.rng = srl::DeserTLV::deserialize_usize(&buf[28..])? as u64;
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: cf,
+                })
+        }
+}
+
+
+impl srl::Serializable<CuckooFilterStructure> for CuckooFilterStructure {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::CuckooFilterStructure);
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_u8(self.inner.fp_bits);
+
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_u8(self.inner.bucket_sz as u8);
+
+                tlv.serialize_usize(self.inner.bucket_cnt)?;
+                tlv.serialize_usize(self.inner.max_kicks)?;
+                tlv.serialize_usize(self.inner.item_cnt)?;
+
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_usize(self.inner.rng as usize)?;
+
+                let mut bytes = Vec::with_capacity(2*self.inner.slots.len());
+                for slot in &self.inner.slots {
+                        bytes.extend(slot.to_le_bytes());
+                }
+                tlv.serialize_slice_u8(&bytes)?;
+                Ok(tlv)
+        }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CuckooFilterParams {
+        /// The number of buckets, a power of two
+        pub bucket_cnt: usize,
+        /// The number of fingerprints per bucket
+        pub bucket_sz: usize,
+        /// The size of a fingerprint in bits
+        pub fp_bits: u8,
+        /// The number of relocations tried before an insert fails
+        pub max_kicks: usize,
+}
+
+
+impl Default for CuckooFilterParams {
+        fn default() -> Self {
+                Self { bucket_cnt: 1024, bucket_sz: 4, fp_bits: 8, max_kicks: 500 }
+        }
+}
+
+
+impl CuckooFilterParams {
+        pub const MAX_BUCKET_CNT: usize = 1 << 30;
+        pub const MAX_BUCKET_SZ: usize = 8;
+        pub const MIN_FP_BITS: u8 = 4;
+        pub const MAX_FP_BITS: u8 = 16;
+
+        #[must_use]
+        pub fn new(bucket_cnt: usize, bucket_sz: usize, fp_bits: u8, max_kicks: usize) -> Option<Self> {
+                let valid = bucket_cnt.is_power_of_two()
+                        && bucket_cnt <= Self::MAX_BUCKET_CNT
+                        && (1..=Self::MAX_BUCKET_SZ).contains(&bucket_sz)
+                        && (Self::MIN_FP_BITS..=Self::MAX_FP_BITS).contains(&fp_bits)
+                        && max_kicks > 0;
+                if !valid {
+                        return None;
+                }
+                Some(Self { bucket_cnt, bucket_sz, fp_bits, max_kicks })
+        }
+
+        // Size the table for the given capacity at a load factor of 95 %.
+        #[must_use]
+        pub fn from_capacity(cpty: usize, bucket_sz: usize, fp_bits: u8, max_kicks: usize) -> Option<Self> {
+                if bucket_sz == 0 {
+                        return None;
+                }
+                let bucket_cnt = cpty.checked_mul(20)?.div_ceil(19 * bucket_sz).max(1).checked_next_power_of_two()?;
+                Self::new(bucket_cnt, bucket_sz, fp_bits, max_kicks)
+        }
+
+        /// The size in bytes of the table of a filter of these parameters.
+        #[must_use]
+        pub fn byte_len(&self) -> usize {
+                self.bucket_cnt.saturating_mul(self.bucket_sz).saturating_mul(std::mem::size_of::<u16>())
+        }
+}
+
+
+#[derive(Debug)]
+pub struct CuckooFilter {
+        // The fingerprints of bucket i live at [i*bucket_sz, (i+1)*bucket_sz),
+        // and zero marks an empty slot.
+        slots: Vec<u16>,
+        pub bucket_cnt: usize,
+        pub bucket_sz: usize,
+        pub fp_bits: u8,
+        pub max_kicks: usize,
+        pub item_cnt: usize,
+        // The state of the generator choosing relocation victims; it is
+        // persisted so that a replayed insert relocates the same fingerprints.
+        rng: u64,
+}
+
+
+impl CuckooFilter {
+        #[must_use]
+        pub fn new(params: &CuckooFilterParams) -> Self {
+                Self {
+                        slots: vec![0; params.bucket_cnt * params.bucket_sz],
+                        bucket_cnt: params.bucket_cnt,
+                        bucket_sz: params.bucket_sz,
+                        fp_bits: params.fp_bits,
+                        max_kicks: params.max_kicks,
+                        item_cnt: 0,
+                        rng: 0x2545_f491_4f6c_dd1d,
+                }
+        }
+
+        /// Insert an element. Returns false if no slot could be freed for it
+        /// within `max_kicks` relocations, in which case the filter is left
+        /// exactly as it was.
+        pub fn insert(&mut self, bytes: &[u8]) -> bool {
+                let (i1, fp) = self.idx_and_fp(bytes);
+                let i2 = self.alt_idx(i1, fp);
+                if self.put(i1, fp) || self.put(i2, fp) {
+                        self.item_cnt += 1;
+                        return true;
+                }
+
+                let rng = self.rng;
+                let mut path = Vec::with_capacity(self.max_kicks);
+                let mut idx = if self.next_rand() & 1 == 0 { i1 } else { i2 };
+                let mut homeless = fp;
+                for _ in 0..self.max_kicks {
+                        #[allow(clippy::cast_possible_truncation)]
+                        let pos = idx*self.bucket_sz + (self.next_rand() as usize) % self.bucket_sz;
+                        std::mem::swap(&mut homeless, &mut self.slots[pos]);
+                        path.push(pos);
+                        idx = self.alt_idx(idx, homeless);
+                        if self.put(idx, homeless) {
+                                self.item_cnt += 1;
+                                return true;
+                        }
+                }
+
+                // Undo the relocations, so that a failed insert has no effect
+                for pos in path.into_iter().rev() {
+                        std::mem::swap(&mut homeless, &mut self.slots[pos]);
+                }
+                self.rng = rng;
+                false
+        }
+
+        #[must_use]
+        pub fn has(&self, bytes: &[u8]) -> bool {
+                let (i1, fp) = self.idx_and_fp(bytes);
+                let i2 = self.alt_idx(i1, fp);
+                self.bucket(i1).contains(&fp) || self.bucket(i2).contains(&fp)
+        }
+
+        /// Delete one copy of an element. Returns false if it was not found.
+        /// Only elements that were inserted should be deleted, as deleting a
+        /// false positive removes the fingerprint of another element.
+        pub fn delete(&mut self, bytes: &[u8]) -> bool {
+                let (i1, fp) = self.idx_and_fp(bytes);
+                let i2 = self.alt_idx(i1, fp);
+                for idx in [i1, i2] {
+                        let start = idx*self.bucket_sz;
+                        if let Some(pos) = self.bucket(idx).iter().position(|&slot| slot == fp) {
+                                self.slots[start + pos] = 0;
+                                self.item_cnt -= 1;
+                                return true;
+                        }
+                }
+                false
+        }
+
+        #[inline]
+        fn bucket(&self, idx: usize) -> &[u16] {
+                &self.slots[idx*self.bucket_sz..(idx+1)*self.bucket_sz]
+        }
+
+        #[inline]
+        fn put(&mut self, idx: usize, fp: u16) -> bool {
+                let start = idx*self.bucket_sz;
+                if let Some(pos) = self.bucket(idx).iter().position(|&slot| slot == 0) {
+                        self.slots[start + pos] = fp;
+                        return true;
+                }
+                false
+        }
+
+        #[inline]
+        #[allow(clippy::cast_possible_truncation)]
+        fn idx_and_fp(&self, bytes: &[u8]) -> (usize, u16) {
+                let h = hash::fnv1a_mix(bytes, 0, 0) as u64;
+                let mask = (1u64 << self.fp_bits) - 1;
+                // Zero marks an empty slot, so it can't be a fingerprint
+                let fp = ((h >> 32) & mask).max(1) as u16;
+                (h as usize & (self.bucket_cnt - 1), fp)
+        }
+
+        #[inline]
+        #[allow(clippy::cast_possible_truncation)]
+        fn alt_idx(&self, idx: usize, fp: u16) -> usize {
+                (idx ^ hash::mix64(u64::from(fp)) as usize) & (self.bucket_cnt - 1)
+        }
+
+        // The xorshift64 generator
+        #[inline]
+        fn next_rand(&mut self) -> u64 {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                self.rng
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_insert_delete() {
+                let mut cf = CuckooFilter::new(&CuckooFilterParams::from_capacity(1000, 4, 12, 500).unwrap());
+                for i in 0..1000u32 {
+                        assert!(cf.insert(&i.to_le_bytes()));
+                }
+                for i in 0..1000u32 {
+                        assert!(cf.has(&i.to_le_bytes()));
+                }
+                for i in 0..500u32 {
+                        assert!(cf.delete(&i.to_le_bytes()));
+                }
+                for i in 500..1000u32 {
+                        assert!(cf.has(&i.to_le_bytes()));
+                }
+                assert!(cf.item_cnt == 500);
+        }
+
+        #[test]
+        fn test_full() {
+                let mut cf = CuckooFilter::new(&CuckooFilterParams::new(4, 2, 16, 50).unwrap());
+                let mut inserted = 0u32;
+                while cf.insert(&inserted.to_le_bytes()) {
+                        inserted += 1;
+                }
+                assert!(inserted <= 8);
+                let slots = cf.slots.clone();
+                assert!(!cf.insert(&inserted.to_le_bytes()));
+                assert!(cf.slots == slots);
+                for i in 0..inserted {
+                        assert!(cf.has(&i.to_le_bytes()));
+                }
+        }
+}
\ No newline at end of file
@@ -0,0 +1,263 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a Count-Min sketch.
+//!
+//! The sketch is a depth x width grid of counters, following Cormode and
+//! Muthukrishnan, "An Improved Data Stream Summary: The Count-Min Sketch and
+//! its Applications" (2005). Every row maps an element to one counter, and
+//! the smallest of those counters is an estimate that never falls below the
+//! true frequency. With width ceil(e/epsilon) and depth ceil(ln(1/delta)),
+//! the estimate exceeds it by more than epsilon times the total count with
+//! probability at most delta.
+
+
+use std::io;
+
+use qstra_stor::srl;
+
+use crate::hash;
+
+
+#[derive(Debug)]
+pub struct CountMinSketchStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: CountMinSketch,
+}
+
+
+impl CountMinSketchStructure {
+        #[must_use]
+        pub fn with_params(id: u8, dbid: u8, params: &CountMinSketchParams, conservative: bool) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: CountMinSketch::new(params, conservative),
+                }
+        }
+}
+
+
+// A serialized Count-Min sketch structure is laid out as
+//
+//   id (u8) | dbid (u8) | conservative (u8) | depth (u8) | width (usize)
+//   | total (usize) | counters (u64...)
+impl srl::Deserializable for CountMinSketchStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                let loc = 4*srl::U8_OFFSET + 2*srl::USIZE_OFFSET;
+                if buf.len() < loc {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl CountMinSketchStructure: deserialize: too few bytes in buffer"));
+                }
+                let params = CountMinSketchParams::new(
+                        srl::DeserTLV::deserialize_usize(&buf[4..])?,
+                        srl::DeserTLV::deserialize_u8(&buf[3..])? as usize,
+                ).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "impl CountMinSketchStructure: deserialize: invalid parameters"))?;
+                let conservative = srl::DeserTLV::deserialize_u8(&buf[2..])? != 0;
+                let mut cms = CountMinSketch::new(&params, conservative);
+                if buf.len() != loc + srl::U64_OFFSET*cms.counters.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl CountMinSketchStructure: deserialize: counter array length mismatch"));
+                }
+                for (counter, bytes) in cms.counters.iter_mut().zip(buf[loc..].chunks_exact(srl::U64_OFFSET)) {
+                        *counter = u64::from_le_bytes(bytes.try_into().unwrap());
+                }
+                cms.total = srl::DeserTLV::deserialize_usize(&buf[12..])? as u64;
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: cms,
+                })
+        }
+}
+
+
+impl srl::Serializable<CountMinSketchStructure> for CountMinSketchStructure {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::CountMinSketchStructure);
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_u8(u8::from(self.inner.conservative));
+
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_u8(self.inner.depth as u8);
+
+                tlv.serialize_usize(self.inner.width)?;
+
+                #[allow(clippy::cast_possible_truncation)]
+                tlv.serialize_usize(self.inner.total as usize)?;
+
+                let mut bytes = Vec::with_capacity(srl::U64_OFFSET*self.inner.counters.len());
+                for counter in &self.inner.counters {
+                        bytes.extend(counter.to_le_bytes());
+                }
+                tlv.serialize_slice_u8(&bytes)?;
+                Ok(tlv)
+        }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CountMinSketchParams {
+        /// The number of counters per row
+        pub width: usize,
+        /// The number of rows, each with its own hash function
+        pub depth: usize,
+}
+
+
+impl Default for CountMinSketchParams {
+        fn default() -> Self {
+                Self { width: 2048, depth: 5 }
+        }
+}
+
+
+impl CountMinSketchParams {
+        pub const MAX_WIDTH: usize = 1 << 24;
+        pub const MAX_DEPTH: usize = 32;
+
+        #[must_use]
+        pub fn new(width: usize, depth: usize) -> Option<Self> {
+                let valid = (1..=Self::MAX_WIDTH).contains(&width)
+                        && (1..=Self::MAX_DEPTH).contains(&depth);
+                if !valid {
+                        return None;
+                }
+                Some(Self { width, depth })
+        }
+
+        // Size the sketch for an additive error of epsilon times the total
+        // count, exceeded with probability delta.
+        #[must_use]
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        pub fn from_error(epsilon: f64, delta: f64) -> Option<Self> {
+                let valid = epsilon > 0.0 && epsilon < 1.0
+                        && delta > 0.0 && delta < 1.0;
+                if !valid {
+                        return None;
+                }
+                let width = (std::f64::consts::E / epsilon).ceil();
+                let depth = (1.0 / delta).ln().ceil().max(1.0);
+                if width > Self::MAX_WIDTH as f64 || depth > Self::MAX_DEPTH as f64 {
+                        return None;
+                }
+                Self::new(width as usize, depth as usize)
+        }
+}
+
+
+#[derive(Debug)]
+pub struct CountMinSketch {
+        // The counters of row i live at [i*width, (i+1)*width)
+        counters: Vec<u64>,
+        pub width: usize,
+        pub depth: usize,
+        /// Only raise the counters that hold the current estimate
+        pub conservative: bool,
+        /// The sum of all increments
+        pub total: u64,
+}
+
+
+impl CountMinSketch {
+        #[must_use]
+        pub fn new(params: &CountMinSketchParams, conservative: bool) -> Self {
+                Self {
+                        counters: vec![0; params.width * params.depth],
+                        width: params.width,
+                        depth: params.depth,
+                        conservative,
+                        total: 0,
+                }
+        }
+
+        /// Count n more occurrences of an element and return its new
+        /// estimated frequency.
+        pub fn incr(&mut self, bytes: &[u8], n: u64) -> u64 {
+                let idxs: Vec<usize> = self.idxs(bytes).collect();
+                self.total = self.total.saturating_add(n);
+                if self.conservative {
+                        // A counter never needs to exceed the new estimate,
+                        // which keeps collisions from inflating it further.
+                        let est = self.estimate(&idxs).saturating_add(n);
+                        for idx in idxs {
+                                self.counters[idx] = self.counters[idx].max(est);
+                        }
+                        return est;
+                }
+                for idx in &idxs {
+                        self.counters[*idx] = self.counters[*idx].saturating_add(n);
+                }
+                self.estimate(&idxs)
+        }
+
+        /// Estimate the frequency of an element. The estimate is never lower
+        /// than the true frequency.
+        #[must_use]
+        pub fn query(&self, bytes: &[u8]) -> u64 {
+                let idxs: Vec<usize> = self.idxs(bytes).collect();
+                self.estimate(&idxs)
+        }
+
+        #[inline]
+        fn estimate(&self, idxs: &[usize]) -> u64 {
+                idxs.iter().map(|idx| self.counters[*idx]).min().unwrap_or(0)
+        }
+
+        // One counter per row, from the Kirsch–Mitzenmacher combinations of
+        // the two halves of a single hash.
+        fn idxs(&self, bytes: &[u8]) -> impl Iterator<Item = usize> {
+                let h = hash::fnv1a_mix(bytes, 0, 0) as u64;
+                let (h0, h1) = (h & 0xFFFF_FFFF, h >> 32);
+                let width = self.width;
+                #[allow(clippy::cast_possible_truncation)]
+                (0..self.depth).map(move |i| i*width + (h0.wrapping_add((i as u64).wrapping_mul(h1)) % width as u64) as usize)
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_incr_query() {
+                let params = CountMinSketchParams::from_error(0.001, 0.01).unwrap();
+                assert!(params.width == 2719 && params.depth == 5);
+                let mut cms = CountMinSketch::new(&params, false);
+                for i in 0..1000u64 {
+                        cms.incr(&i.to_le_bytes(), i % 10 + 1);
+                }
+                assert!(cms.total == 5500);
+                let mut over = 0;
+                for i in 0..1000u64 {
+                        let est = cms.query(&i.to_le_bytes());
+                        assert!(est > i % 10);
+                        if est > i % 10 + 1 + 6 {
+                                over += 1;
+                        }
+                }
+                assert!(over < 10, "{over}");
+                assert!(cms.query(b"absent") <= 6);
+        }
+
+        #[test]
+        fn test_conservative() {
+                let params = CountMinSketchParams::new(64, 4).unwrap();
+                let mut plain = CountMinSketch::new(&params, false);
+                let mut conservative = CountMinSketch::new(&params, true);
+                for i in 0..500u32 {
+                        plain.incr(&(i % 100).to_le_bytes(), 1);
+                        conservative.incr(&(i % 100).to_le_bytes(), 1);
+                }
+                for i in 0..100u32 {
+                        let est = conservative.query(&i.to_le_bytes());
+                        assert!(est >= 5);
+                        assert!(est <= plain.query(&i.to_le_bytes()));
+                }
+        }
+}
\ No newline at end of file
@@ -0,0 +1,55 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Provide the base hash functions shared by the probabilistic structures.
+
+
+// The djb2 hash function
+#[inline]
+#[must_use]
+pub fn djb2(bytes: &[u8], seed: usize) -> usize {
+        let mut h: usize = 5381 ^ seed;
+        for b in bytes {
+                h = ((h << 5).wrapping_add(h)).wrapping_add(*b as usize);
+        }
+        h
+}
+
+
+// The sdbm hash function
+#[inline]
+#[must_use]
+pub fn sdbm(bytes: &[u8], seed: usize) -> usize {
+        let mut h: usize = seed;
+        for b in bytes {
+                h = (((*b as usize).wrapping_add(h << 6)).wrapping_add(h << 16)).wrapping_sub(h);
+        }
+        h
+}
+
+
+// The 64-bit FNV-1a hash function, salted and passed through the splitmix64
+// finalizer so that every output bit depends on every input bit
+#[inline]
+#[must_use]
+pub fn fnv1a_mix(bytes: &[u8], seed: usize, salt: u64) -> usize {
+        let mut h: u64 = 0xcbf2_9ce4_8422_2325 ^ (seed as u64);
+        for b in salt.to_le_bytes().iter().chain(bytes) {
+                h ^= u64::from(*b);
+                h = h.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let ret = mix64(h) as usize;
+        ret
+}
+
+
+// The splitmix64 finalizer
+#[inline]
+#[must_use]
+pub fn mix64(mut h: u64) -> u64 {
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^ (h >> 31)
+}
\ No newline at end of file
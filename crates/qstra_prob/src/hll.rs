@@ -0,0 +1,285 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a HyperLogLog cardinality sketch.
+//!
+//! The sketch keeps 2^p registers holding the longest run of leading zeros
+//! seen among the hashes routed to them, following Flajolet et al.,
+//! "HyperLogLog: the analysis of a near-optimal cardinality estimation
+//! algorithm" (2007). Small sketches only store their non-zero registers and
+//! switch to a dense array once that stops saving space.
+
+
+use std::collections::BTreeMap;
+use std::io;
+
+use qstra_stor::srl;
+
+use crate::hash;
+
+
+const MODE_SPARSE: u8 = 0;
+const MODE_DENSE: u8 = 1;
+
+
+#[derive(Debug)]
+pub struct HyperLogLogStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: HyperLogLog,
+}
+
+
+impl HyperLogLogStructure {
+        #[must_use]
+        pub fn new(id: u8, dbid: u8, precision: u8) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: HyperLogLog::new(precision),
+                }
+        }
+}
+
+
+// A serialized HyperLogLog structure is laid out as
+//
+//   id (u8) | dbid (u8) | precision (u8) | mode (u8) | registers
+//
+// where the registers are (idx (u16), value (u8)) pairs in sparse mode and
+// one byte per register in dense mode.
+impl srl::Deserializable for HyperLogLogStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                if buf.len() < 4*srl::U8_OFFSET {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl HyperLogLogStructure: deserialize: too few bytes in buffer"));
+                }
+                let precision = srl::DeserTLV::deserialize_u8(&buf[2..])?;
+                if !(HyperLogLog::MIN_PRECISION..=HyperLogLog::MAX_PRECISION).contains(&precision) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl HyperLogLogStructure: deserialize: invalid precision"));
+                }
+                let m = 1usize << precision;
+                let regs = &buf[4..];
+                let registers = match srl::DeserTLV::deserialize_u8(&buf[3..])? {
+                        MODE_SPARSE if regs.len().is_multiple_of(3) => {
+                                let mut sparse = BTreeMap::new();
+                                for entry in regs.chunks_exact(3) {
+                                        let idx = u16::from_le_bytes([entry[0], entry[1]]);
+                                        if idx as usize >= m {
+                                                return Err(io::Error::new(io::ErrorKind::InvalidData, "impl HyperLogLogStructure: deserialize: register index out of bounds"));
+                                        }
+                                        sparse.insert(idx, entry[2]);
+                                }
+                                Registers::Sparse(sparse)
+                        }
+                        MODE_DENSE if regs.len() == m => Registers::Dense(srl::DeserTLV::deserialize_vec_u8(regs)?),
+                        _ => {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, "impl HyperLogLogStructure: deserialize: malformed registers"));
+                        }
+                };
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: HyperLogLog { precision, registers },
+                })
+        }
+}
+
+
+impl srl::Serializable<HyperLogLogStructure> for HyperLogLogStructure {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::HyperLogLogStructure);
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_u8(self.inner.precision);
+                match &self.inner.registers {
+                        Registers::Sparse(sparse) => {
+                                tlv.serialize_u8(MODE_SPARSE);
+                                for (idx, val) in sparse {
+                                        tlv.serialize_slice_u8(&idx.to_le_bytes())?;
+                                        tlv.serialize_u8(*val);
+                                }
+                        }
+                        Registers::Dense(dense) => {
+                                tlv.serialize_u8(MODE_DENSE);
+                                tlv.serialize_slice_u8(dense)?;
+                        }
+                }
+                Ok(tlv)
+        }
+}
+
+
+#[derive(Clone, Debug)]
+enum Registers {
+        Sparse(BTreeMap<u16, u8>),
+        Dense(Vec<u8>),
+}
+
+
+#[derive(Clone, Debug)]
+pub struct HyperLogLog {
+        pub precision: u8,
+        registers: Registers,
+}
+
+
+impl HyperLogLog {
+        pub const MIN_PRECISION: u8 = 4;
+        pub const MAX_PRECISION: u8 = 16;
+        pub const DEFAULT_PRECISION: u8 = 14;
+
+        #[must_use]
+        pub fn new(precision: u8) -> Self {
+                Self {
+                        precision: precision.clamp(Self::MIN_PRECISION, Self::MAX_PRECISION),
+                        registers: Registers::Sparse(BTreeMap::new()),
+                }
+        }
+
+        #[must_use]
+        pub fn is_sparse(&self) -> bool {
+                matches!(self.registers, Registers::Sparse(_))
+        }
+
+        #[inline]
+        fn register_cnt(&self) -> usize {
+                1 << self.precision
+        }
+
+        pub fn add(&mut self, bytes: &[u8]) {
+                let h = hash::fnv1a_mix(bytes, 0, 0) as u64;
+                let p = u32::from(self.precision);
+                #[allow(clippy::cast_possible_truncation)]
+                let idx = (h >> (64 - p)) as u16;
+                // The sentinel bit bounds the run at 64 - p zeros
+                #[allow(clippy::cast_possible_truncation)]
+                let rho = ((h << p) | (1 << (p - 1))).leading_zeros() as u8 + 1;
+                self.update(idx, rho);
+        }
+
+        fn update(&mut self, idx: u16, val: u8) {
+                match &mut self.registers {
+                        Registers::Sparse(sparse) => {
+                                let reg = sparse.entry(idx).or_insert(0);
+                                *reg = (*reg).max(val);
+                                // A sparse entry takes three bytes to store
+                                // against one for a dense register.
+                                if 3*sparse.len() > self.register_cnt() {
+                                        self.densify();
+                                }
+                        }
+                        Registers::Dense(dense) => {
+                                let reg = &mut dense[idx as usize];
+                                *reg = (*reg).max(val);
+                        }
+                }
+        }
+
+        fn densify(&mut self) {
+                if let Registers::Sparse(sparse) = &self.registers {
+                        let mut dense = vec![0; self.register_cnt()];
+                        for (idx, val) in sparse {
+                                dense[*idx as usize] = *val;
+                        }
+                        self.registers = Registers::Dense(dense);
+                }
+        }
+
+        /// Estimate the number of distinct elements added.
+        #[must_use]
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        pub fn count(&self) -> u64 {
+                let m = self.register_cnt() as f64;
+                let (sum, zeros) = match &self.registers {
+                        Registers::Sparse(sparse) => {
+                                let zeros = self.register_cnt() - sparse.len();
+                                let sum: f64 = sparse.values().map(|val| 2f64.powi(-i32::from(*val))).sum();
+                                (sum + zeros as f64, zeros)
+                        }
+                        Registers::Dense(dense) => {
+                                let zeros = dense.iter().filter(|val| **val == 0).count();
+                                let sum: f64 = dense.iter().map(|val| 2f64.powi(-i32::from(*val))).sum();
+                                (sum, zeros)
+                        }
+                };
+                let alpha = match self.precision {
+                        4 => 0.673,
+                        5 => 0.697,
+                        6 => 0.709,
+                        _ => 0.7213 / (1.0 + 1.079 / m),
+                };
+                let raw = alpha * m * m / sum;
+
+                // Linear counting is the more accurate estimate for small
+                // cardinalities.
+                if raw <= 2.5 * m && zeros > 0 {
+                        return (m * (m / zeros as f64).ln()).round() as u64;
+                }
+                raw.round() as u64
+        }
+
+        /// Fold another sketch of the same precision into this one, so that
+        /// this sketch counts the union of both.
+        pub fn merge(&mut self, other: &HyperLogLog) -> io::Result<()> {
+                if self.precision != other.precision {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "impl HyperLogLog: merge: precision mismatch"));
+                }
+                match &other.registers {
+                        Registers::Sparse(sparse) => {
+                                for (idx, val) in sparse {
+                                        self.update(*idx, *val);
+                                }
+                        }
+                        Registers::Dense(dense) => {
+                                self.densify();
+                                if let Registers::Dense(own) = &mut self.registers {
+                                        for (reg, val) in own.iter_mut().zip(dense) {
+                                                *reg = (*reg).max(*val);
+                                        }
+                                }
+                        }
+                }
+                Ok(())
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_count() {
+                for n in [10u32, 1000, 100_000] {
+                        let mut hll = HyperLogLog::new(HyperLogLog::DEFAULT_PRECISION);
+                        for i in 0..n {
+                                hll.add(&i.to_le_bytes());
+                                hll.add(&i.to_le_bytes());
+                        }
+                        let est = hll.count() as f64;
+                        let err = (est - f64::from(n)).abs() / f64::from(n);
+                        assert!(err < 0.05, "n = {n}, estimate = {est}");
+                }
+        }
+
+        #[test]
+        fn test_merge() {
+                let mut a = HyperLogLog::new(12);
+                let mut b = HyperLogLog::new(12);
+                for i in 0..20_000u32 {
+                        a.add(&i.to_le_bytes());
+                }
+                for i in 10_000..30_000u32 {
+                        b.add(&i.to_le_bytes());
+                }
+                assert!(!a.is_sparse());
+                a.merge(&b).unwrap();
+                let est = a.count() as f64;
+                assert!((est - 30_000.0).abs() / 30_000.0 < 0.05, "{est}");
+                assert!(a.merge(&HyperLogLog::new(10)).is_err());
+        }
+}
\ No newline at end of file
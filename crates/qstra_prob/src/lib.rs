@@ -2,4 +2,11 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 
-pub mod bf;
\ No newline at end of file
+pub mod bf;
+pub mod cbf;
+pub mod cf;
+pub mod cms;
+pub mod hash;
+pub mod hll;
+pub mod sbf;
+pub mod topk;
\ No newline at end of file
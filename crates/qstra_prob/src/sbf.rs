@@ -0,0 +1,302 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a scalable bloom filter.
+//!
+//! The filter is a chain of plain bloom filter slices, following Almeida et
+//! al., "Scalable Bloom Filters" (2007). Once the newest slice has taken its
+//! share of elements, a new slice is appended whose capacity grows by the
+//! growth factor s and whose false-positive rate tightens by the ratio r. The
+//! rates of the slices form a geometric series P0 * r^i with P0 = P * (1 - r),
+//! so the compound false-positive rate stays below P however far the filter
+//! grows.
+
+
+use std::io;
+
+use qstra_stor::srl;
+
+use crate::bf::{BloomFilter, BloomFilterParams, HashFamily, MembershipFilter};
+
+
+const SLICE_SEED_STRIDE: usize = 0x9E37_79B9;
+
+#[derive(Debug)]
+pub struct ScalableBloomFilterStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: ScalableBloomFilter,
+}
+
+
+impl ScalableBloomFilterStructure {
+        pub fn with_params(id: u8, dbid: u8, params: &ScalableBloomFilterParams) -> io::Result<Self> {
+                Ok(Self {
+                        dbid,
+                        id,
+                        inner: ScalableBloomFilter::new(*params)?,
+                })
+        }
+}
+
+
+// A serialized scalable bloom filter structure is laid out as
+//
+//   id (u8) | dbid (u8) | initial_cpty (usize) | fpr (f64) | growth (usize)
+//   | tightening (f64) | insert_cnt (usize) | slices (BloomFilter TLV...)
+impl srl::Deserializable for ScalableBloomFilterStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                let mut loc = 2*srl::U8_OFFSET + 3*srl::USIZE_OFFSET + 2*srl::U64_OFFSET;
+                if buf.len() < loc {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl ScalableBloomFilterStructure: deserialize: too few bytes in buffer"));
+                }
+                let params = ScalableBloomFilterParams::new(
+                        srl::DeserTLV::deserialize_usize(&buf[2..])?,
+                        srl::DeserTLV::deserialize_f64(&buf[10..])?,
+                        srl::DeserTLV::deserialize_usize(&buf[18..])?,
+                        srl::DeserTLV::deserialize_f64(&buf[26..])?,
+                ).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "impl ScalableBloomFilterStructure: deserialize: invalid parameters"))?;
+
+                let mut slices = Vec::new();
+                while loc < buf.len() {
+                        let slice_tlv = srl::DeserTLV::new(&buf[loc..])?;
+                        loc += slice_tlv.len();
+                        slices.push(BloomFilter::deserialize(&slice_tlv)?);
+                }
+                if slices.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl ScalableBloomFilterStructure: deserialize: no slices"));
+                }
+
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: ScalableBloomFilter {
+                                slices,
+                                params,
+                                insert_cnt: srl::DeserTLV::deserialize_usize(&buf[34..])?,
+                        },
+                })
+        }
+}
+
+
+impl srl::Serializable<ScalableBloomFilterStructure> for ScalableBloomFilterStructure {
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::ScalableBloomFilterStructure);
+                let params = &self.inner.params;
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_usize(params.initial_cpty)?;
+                tlv.serialize_f64(params.fpr)?;
+                tlv.serialize_usize(params.growth)?;
+                tlv.serialize_f64(params.tightening)?;
+                tlv.serialize_usize(self.inner.insert_cnt)?;
+                for slice in &self.inner.slices {
+                        tlv.serialize_sertlv(&slice.serialize()?)?;
+                }
+                Ok(tlv)
+        }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScalableBloomFilterParams {
+        /// The expected number of elements in the first slice
+        pub initial_cpty: usize,
+        /// The bound P on the compound false-positive rate
+        pub fpr: f64,
+        /// The growth factor s of the slice capacities
+        pub growth: usize,
+        /// The tightening ratio r of the slice false-positive rates
+        pub tightening: f64,
+}
+
+
+impl Default for ScalableBloomFilterParams {
+        fn default() -> Self {
+                Self { initial_cpty: 1000, fpr: 0.01, growth: 2, tightening: 0.85 }
+        }
+}
+
+
+impl ScalableBloomFilterParams {
+        pub const MAX_GROWTH: usize = 16;
+
+        #[must_use]
+        pub fn new(initial_cpty: usize, fpr: f64, growth: usize, tightening: f64) -> Option<Self> {
+                let valid = initial_cpty > 0
+                        && fpr > 0.0 && fpr < 1.0
+                        && (1..=Self::MAX_GROWTH).contains(&growth)
+                        && tightening > 0.0 && tightening < 1.0;
+                if !valid {
+                        return None;
+                }
+                Some(Self { initial_cpty, fpr, growth, tightening })
+        }
+}
+
+
+#[derive(Debug)]
+pub struct ScalableBloomFilter {
+        pub slices: Vec<BloomFilter>,
+        pub params: ScalableBloomFilterParams,
+        pub insert_cnt: usize,
+}
+
+
+impl ScalableBloomFilter {
+        pub fn new(params: ScalableBloomFilterParams) -> io::Result<Self> {
+                let mut sbf = Self { slices: Vec::new(), params, insert_cnt: 0 };
+                sbf.slices.push(sbf.new_slice(0)?);
+                Ok(sbf)
+        }
+
+        #[must_use]
+        pub fn slice_cpty(&self, i: usize) -> usize {
+                let growth = self.params.growth.saturating_pow(u32::try_from(i).unwrap_or(u32::MAX));
+                self.params.initial_cpty.saturating_mul(growth)
+        }
+
+        #[must_use]
+        pub fn slice_fpr(&self, i: usize) -> f64 {
+                let r = self.params.tightening;
+                self.params.fpr * (1.0 - r) * r.powi(i32::try_from(i).unwrap_or(i32::MAX))
+        }
+
+        fn slice_params(&self, i: usize) -> io::Result<BloomFilterParams> {
+                BloomFilterParams::from_estimate(self.slice_cpty(i), self.slice_fpr(i))
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::OutOfMemory, "impl ScalableBloomFilter: new_slice: the filter can't grow any further"))
+        }
+
+        fn new_slice(&self, i: usize) -> io::Result<BloomFilter> {
+                let params = self.slice_params(i)?;
+                let mut slice = BloomFilter::new(params.bit_cnt, params.bit_cnt, params.hfn_cnt);
+                // The compound bound only holds if the slices err
+                // independently, so every slice is seeded differently and
+                // uses the better-mixed hash family.
+                slice.hfn_family = HashFamily::Fnv1aMix;
+                slice.seed = i.wrapping_mul(SLICE_SEED_STRIDE);
+                Ok(slice)
+        }
+
+        pub fn add(&mut self, bytes: &[u8]) -> io::Result<()> {
+                // Elements already present would only use up the capacity of
+                // the newest slice.
+                if self.has(bytes)? {
+                        return Ok(());
+                }
+                let last = self.slices.len() - 1;
+                if self.slices[last].insert_cnt >= self.slice_cpty(last) {
+                        let slice = self.new_slice(last + 1)?;
+                        self.slices.push(slice);
+                }
+                self.slices.last_mut().unwrap().add(bytes)?;
+                self.insert_cnt += 1;
+                Ok(())
+        }
+
+        /// Add the elements in order, once sure the filter can grow to take
+        /// them all, so that it's left as it was if it can't.
+        pub fn add_batch(&mut self, elts: &[&[u8]]) -> io::Result<()> {
+                self.check_room(elts.len())?;
+                for elt in elts {
+                        self.add(elt)?;
+                }
+                Ok(())
+        }
+
+        // Fail as add would if the filter couldn't take n more elements. The
+        // elements already present take no room, so this may refuse a batch
+        // that would have fit, but only once the filter can't grow anyway.
+        fn check_room(&self, n: usize) -> io::Result<()> {
+                let mut i = self.slices.len() - 1;
+                let mut room = self.slice_cpty(i).saturating_sub(self.slices[i].insert_cnt);
+                while room < n {
+                        i += 1;
+                        self.slice_params(i)?;
+                        room = room.saturating_add(self.slice_cpty(i));
+                }
+                Ok(())
+        }
+
+        pub fn has(&self, bytes: &[u8]) -> io::Result<bool> {
+                for slice in self.slices.iter().rev() {
+                        if slice.has(bytes)? {
+                                return Ok(true);
+                        }
+                }
+                Ok(false)
+        }
+}
+
+
+impl MembershipFilter for ScalableBloomFilter {
+        fn add(&mut self, bytes: &[u8]) -> io::Result<()> {
+                ScalableBloomFilter::add(self, bytes)
+        }
+
+        fn has(&self, bytes: &[u8]) -> io::Result<bool> {
+                ScalableBloomFilter::has(self, bytes)
+        }
+
+        fn add_batch(&mut self, elts: &[&[u8]]) -> io::Result<()> {
+                ScalableBloomFilter::add_batch(self, elts)
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use qstra_stor::srl::{Deserializable, Serializable};
+
+        #[test]
+        fn test_growth() {
+                let params = ScalableBloomFilterParams::new(100, 0.01, 2, 0.8).unwrap();
+                let mut sbf = ScalableBloomFilter::new(params).unwrap();
+                for i in 0..2000u32 {
+                        sbf.add(&i.to_le_bytes()).unwrap();
+                }
+                assert!(sbf.slices.len() > 1);
+                for i in 0..2000u32 {
+                        assert!(sbf.has(&i.to_le_bytes()).unwrap());
+                }
+
+                let false_positives = (2000..12000u32).filter(|i| sbf.has(&i.to_le_bytes()).unwrap()).count();
+                assert!(false_positives < 100, "{false_positives}");
+
+                // A batch is refused whole once the filter can't grow to take
+                // it, with small slices standing in for the large ones
+                let params = ScalableBloomFilterParams::new(1000, 0.01, 16, 0.85).unwrap();
+                let mut sbf = ScalableBloomFilter::new(params).unwrap();
+                sbf.slices.extend((1..=4).map(|_| BloomFilter::default()));
+                sbf.slices[4].insert_cnt = sbf.slice_cpty(4) - 1;
+                let (a, b) = (b"a".as_slice(), b"b".as_slice());
+                assert!(sbf.add_batch(&[a, b]).unwrap_err().kind() == io::ErrorKind::OutOfMemory);
+                assert!(!sbf.has(a).unwrap() && sbf.insert_cnt == 0);
+                sbf.add_batch(&[a]).unwrap();
+                assert!(sbf.has(a).unwrap() && sbf.slices.len() == 5);
+        }
+
+        #[test]
+        fn test_roundtrip() {
+                let mut sbfs = ScalableBloomFilterStructure::with_params(3, 1, &ScalableBloomFilterParams::new(10, 0.05, 4, 0.5).unwrap()).unwrap();
+                for i in 0..100u32 {
+                        sbfs.inner.add(&i.to_le_bytes()).unwrap();
+                }
+                let mut buf = Vec::new();
+                sbfs.serialize().unwrap().serialize_into_buf(&mut buf).unwrap();
+                let restored = ScalableBloomFilterStructure::deserialize(&srl::DeserTLV::new(&buf).unwrap()).unwrap();
+                assert!(restored.id == 3 && restored.dbid == 1);
+                assert!(restored.inner.params == sbfs.inner.params);
+                assert!(restored.inner.slices.len() == sbfs.inner.slices.len());
+                assert!(restored.inner.insert_cnt == sbfs.inner.insert_cnt);
+                for i in 0..100u32 {
+                        assert!(restored.inner.has(&i.to_le_bytes()).unwrap());
+                }
+        }
+}
\ No newline at end of file
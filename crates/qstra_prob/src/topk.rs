@@ -0,0 +1,237 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Implement a Top-K heavy hitters structure.
+//!
+//! The structure tracks at most k keys with the Space-Saving algorithm of
+//! Metwally et al., "Efficient Computation of Frequent and Top-k Elements in
+//! Data Streams" (2005). An untracked key takes over the slot of the key with
+//! the smallest count and inherits that count as its error, so every count
+//! overestimates the true frequency by at most its error.
+
+
+use std::collections::HashMap;
+use std::io;
+
+use qstra_stor::srl;
+
+
+#[derive(Debug)]
+pub struct TopKStructure {
+        pub dbid: u8,
+        pub id: u8,
+        pub inner: TopK,
+}
+
+
+impl TopKStructure {
+        #[must_use]
+        pub fn new(id: u8, dbid: u8, k: usize) -> Self {
+                Self {
+                        dbid,
+                        id,
+                        inner: TopK::new(k),
+                }
+        }
+}
+
+
+// A serialized Top-K structure is laid out as
+//
+//   id (u8) | dbid (u8) | k (usize) | entry_cnt (usize)
+//   | entries (key_len (usize) | key (u8...) | count (usize) | error (usize))...
+impl srl::Deserializable for TopKStructure {
+        fn deserialize(tlv: &srl::DeserTLV) -> io::Result<Self>
+        where Self: Sized
+        {
+                let buf = &tlv.val;
+                let mut loc = 2*srl::U8_OFFSET + 2*srl::USIZE_OFFSET;
+                if buf.len() < loc {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl TopKStructure: deserialize: too few bytes in buffer"));
+                }
+                let k = srl::DeserTLV::deserialize_usize(&buf[2..])?;
+                let entry_cnt = srl::DeserTLV::deserialize_usize(&buf[10..])?;
+                if !(1..=TopK::MAX_K).contains(&k) || entry_cnt > k {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl TopKStructure: deserialize: invalid parameters"));
+                }
+                let mut topk = TopK::new(k);
+                for _ in 0..entry_cnt {
+                        if buf.len() < loc + srl::USIZE_OFFSET {
+                                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl TopKStructure: deserialize: truncated key length"));
+                        }
+                        let key_len = srl::DeserTLV::deserialize_usize(&buf[loc..])?;
+                        loc += srl::USIZE_OFFSET;
+                        let key = buf.get(loc..loc.saturating_add(key_len))
+                                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "impl TopKStructure: deserialize: truncated key"))?
+                                .to_vec();
+                        loc += key_len;
+                        if buf.len() < loc + 2*srl::USIZE_OFFSET {
+                                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "impl TopKStructure: deserialize: truncated count"));
+                        }
+                        let count = srl::DeserTLV::deserialize_usize(&buf[loc..])? as u64;
+                        let error = srl::DeserTLV::deserialize_usize(&buf[loc+srl::USIZE_OFFSET..])? as u64;
+                        loc += 2*srl::USIZE_OFFSET;
+                        topk.index.insert(key.clone(), topk.entries.len());
+                        topk.entries.push(TopKEntry { key, count, error });
+                }
+                if loc != buf.len() || topk.index.len() != topk.entries.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl TopKStructure: deserialize: malformed entries"));
+                }
+                Ok(Self {
+                        dbid: srl::DeserTLV::deserialize_u8(&buf[1..])?,
+                        id: srl::DeserTLV::deserialize_u8(&buf[0..])?,
+                        inner: topk,
+                })
+        }
+}
+
+
+impl srl::Serializable<TopKStructure> for TopKStructure {
+        #[allow(clippy::cast_possible_truncation)]
+        fn serialize(&self) -> io::Result<srl::SerTLV> {
+                let mut tlv = srl::SerTLV::new(srl::SerializableType::TopKStructure);
+                tlv.serialize_u8(self.id);
+                tlv.serialize_u8(self.dbid);
+                tlv.serialize_usize(self.inner.k)?;
+                tlv.serialize_usize(self.inner.entries.len())?;
+                for entry in &self.inner.entries {
+                        tlv.serialize_usize(entry.key.len())?;
+                        tlv.serialize_slice_u8(&entry.key)?;
+                        tlv.serialize_usize(entry.count as usize)?;
+                        tlv.serialize_usize(entry.error as usize)?;
+                }
+                Ok(tlv)
+        }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopKEntry {
+        pub key: Vec<u8>,
+        /// The estimated frequency, never lower than the true one
+        pub count: u64,
+        /// The count inherited from the key this one replaced
+        pub error: u64,
+}
+
+
+#[derive(Debug)]
+pub struct TopK {
+        pub k: usize,
+        entries: Vec<TopKEntry>,
+        index: HashMap<Vec<u8>, usize>,
+}
+
+
+impl TopK {
+        pub const MAX_K: usize = 1 << 16;
+        pub const DEFAULT_K: usize = 10;
+
+        #[must_use]
+        pub fn new(k: usize) -> Self {
+                let k = k.clamp(1, Self::MAX_K);
+                Self {
+                        k,
+                        entries: Vec::with_capacity(k),
+                        index: HashMap::with_capacity(k),
+                }
+        }
+
+        /// Count n more occurrences of a key. Returns the key that was
+        /// dropped from the structure to make room for it, if any.
+        pub fn add(&mut self, key: &[u8], n: u64) -> Option<Vec<u8>> {
+                if let Some(&pos) = self.index.get(key) {
+                        self.entries[pos].count = self.entries[pos].count.saturating_add(n);
+                        return None;
+                }
+                if self.entries.len() < self.k {
+                        self.index.insert(key.to_vec(), self.entries.len());
+                        self.entries.push(TopKEntry { key: key.to_vec(), count: n, error: 0 });
+                        return None;
+                }
+
+                // The first of the smallest entries is replaced, so that a
+                // replayed log evicts the same keys.
+                let (pos, min) = self.entries.iter().enumerate()
+                        .min_by_key(|(pos, entry)| (entry.count, *pos))
+                        .map(|(pos, entry)| (pos, entry.count))?;
+                let evicted = std::mem::replace(&mut self.entries[pos], TopKEntry {
+                        key: key.to_vec(),
+                        count: min.saturating_add(n),
+                        error: min,
+                });
+                self.index.remove(&evicted.key);
+                self.index.insert(key.to_vec(), pos);
+                Some(evicted.key)
+        }
+
+        /// Whether a key is currently among the tracked heavy hitters.
+        #[must_use]
+        pub fn has(&self, key: &[u8]) -> bool {
+                self.index.contains_key(key)
+        }
+
+        /// The estimated frequency of a key, or zero if it isn't tracked.
+        #[must_use]
+        pub fn count(&self, key: &[u8]) -> u64 {
+                self.index.get(key).map_or(0, |&pos| self.entries[pos].count)
+        }
+
+        /// The tracked keys, most frequent first.
+        #[must_use]
+        pub fn list(&self) -> Vec<&TopKEntry> {
+                let mut entries: Vec<&TopKEntry> = self.entries.iter().collect();
+                entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+                entries
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use qstra_stor::srl::{Deserializable, Serializable};
+
+        #[test]
+        fn test_heavy_hitters() {
+                let mut topk = TopK::new(5);
+                for round in 0..100u32 {
+                        for heavy in 0..3u32 {
+                                topk.add(&heavy.to_le_bytes(), 10);
+                        }
+                        topk.add(&(1000 + round).to_le_bytes(), 1);
+                }
+                let list = topk.list();
+                assert!(list.len() == 5);
+                for heavy in 0..3u32 {
+                        assert!(topk.has(&heavy.to_le_bytes()));
+                        assert!(topk.count(&heavy.to_le_bytes()) == 1000);
+                }
+                assert!(list[..3].iter().all(|entry| entry.count == 1000));
+                assert!(topk.count(&1000u32.to_le_bytes()) == 0);
+        }
+
+        #[test]
+        fn test_roundtrip() {
+                let mut topks = TopKStructure::new(2, 1, 3);
+                for key in [b"a".as_slice(), b"bb", b"ccc", b"a", b"dddd"] {
+                        topks.inner.add(key, 1);
+                }
+                let mut buf = Vec::new();
+                topks.serialize().unwrap().serialize_into_buf(&mut buf).unwrap();
+                let restored = TopKStructure::deserialize(&srl::DeserTLV::new(&buf).unwrap()).unwrap();
+                assert!(restored.id == 2 && restored.dbid == 1);
+                assert!(restored.inner.k == 3);
+                assert!(restored.inner.list() == topks.inner.list());
+                assert!(restored.inner.count(b"a") == 2);
+
+                // A structure cut short anywhere is refused rather than read
+                // past its end
+                let tlv = topks.serialize().unwrap();
+                for len in 0..tlv.val.len() {
+                        let cut = srl::DeserTLV { srl_type: srl::SerializableType::TopKStructure, val: &tlv.val[..len] };
+                        assert!(TopKStructure::deserialize(&cut).unwrap_err().kind() == io::ErrorKind::UnexpectedEof);
+                }
+        }
+}
\ No newline at end of file
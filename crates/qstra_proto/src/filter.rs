@@ -0,0 +1,112 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Encode the parameters of a new bloom filter, and decode the description
+//! of one.
+
+
+use std::io;
+
+use crate::frame;
+
+
+/// The parameters of a new bloom filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterParams {
+        /// A plain filter of the default size
+        Default,
+        /// A plain filter of the given number of bits and hash functions
+        Explicit { bit_cnt: u64, hfn_cnt: u8 },
+        /// A plain filter sized for the expected number of elements and the
+        /// target false-positive rate
+        Estimate { capacity: u64, fpr: f64 },
+        /// A filter that grows by adding slices of growing capacity and
+        /// tightening false-positive rates, bounded by fpr overall
+        Scalable { capacity: u64, fpr: f64, growth: u8, tightening: f64 },
+}
+
+
+impl FilterParams {
+        /// The operation of the database family that creates the filter.
+        #[must_use]
+        pub fn op(&self) -> u8 {
+                match self {
+                        FilterParams::Scalable { .. } => frame::OP_DB_NEW_SBF,
+                        _ => frame::OP_DB_NEW_BF,
+                }
+        }
+
+        /// Append the parameters as the value of a command creating a filter
+        /// expects them, after the filter id.
+        pub fn encode_into(&self, buf: &mut Vec<u8>) {
+                match *self {
+                        FilterParams::Default => {}
+                        FilterParams::Explicit { bit_cnt, hfn_cnt } => {
+                                buf.push(frame::PARAMS_EXPLICIT);
+                                buf.extend(bit_cnt.to_le_bytes());
+                                buf.push(hfn_cnt);
+                        }
+                        FilterParams::Estimate { capacity, fpr } => {
+                                buf.push(frame::PARAMS_ESTIMATE);
+                                buf.extend(capacity.to_le_bytes());
+                                buf.extend(fpr.to_le_bytes());
+                        }
+                        FilterParams::Scalable { capacity, fpr, growth, tightening } => {
+                                buf.extend(capacity.to_le_bytes());
+                                buf.extend(fpr.to_le_bytes());
+                                buf.push(growth);
+                                buf.extend(tightening.to_le_bytes());
+                        }
+                }
+        }
+}
+
+
+/// The size and fill of a plain or scalable bloom filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterInfo {
+        pub capacity: u64,
+        /// The size of the filter in bytes
+        pub size: u64,
+        pub slice_cnt: u64,
+        pub insert_cnt: u64,
+        /// The growth factor, or zero for a plain filter
+        pub growth: u64,
+}
+
+
+impl FilterInfo {
+        /// Decode the value of a response describing a filter.
+        pub fn decode(val: &[u8]) -> io::Result<Self> {
+                let stats: Vec<u64> = val.chunks_exact(8)
+                        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                        .collect();
+                let &[capacity, size, slice_cnt, insert_cnt, growth] = &stats[..] else {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl FilterInfo: decode: malformed response"));
+                };
+                Ok(Self { capacity, size, slice_cnt, insert_cnt, growth })
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_filters() {
+                let mut buf = vec![1];
+                FilterParams::Estimate { capacity: 1000, fpr: 0.01 }.encode_into(&mut buf);
+                assert!(buf.len() == 18 && buf[1] == frame::PARAMS_ESTIMATE);
+                assert!(FilterParams::Scalable { capacity: 10, fpr: 0.01, growth: 2, tightening: 0.5 }.op() == frame::OP_DB_NEW_SBF);
+
+                let mut val = Vec::new();
+                for stat in [1000u64, 1692, 1, 0, 2] {
+                        val.extend(stat.to_le_bytes());
+                }
+                let info = FilterInfo::decode(&val).unwrap();
+                assert!(info.capacity == 1000 && info.growth == 2);
+                assert!(FilterInfo::decode(&val[..32]).is_err());
+        }
+}
\ No newline at end of file
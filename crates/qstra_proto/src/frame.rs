@@ -0,0 +1,230 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! Encode command frames and decode response frames.
+//!
+//! A command frame is laid out as
+//!
+//!   family (u8) | op (u8) | req_id (u16) | len (u32) | val (u8...)
+//!
+//! and a response frame, in the second version of the protocol, as
+//!
+//!   rc (u8) | 0xFF | req_id (u16) | len (u32) | val (u8...)
+//!
+//! all little-endian, where the value of an error response is its detail
+//! message.
+
+
+use std::fmt;
+use std::io;
+
+
+pub const HEADER_SZ: usize = 8;
+/// The first version of the protocol, in which responses end in a sentinel.
+/// Every connection starts out in it until a handshake says otherwise.
+pub const PROTOCOL_V1: u8 = 1;
+/// Responses are length-prefixed frames mirroring those of the requests
+pub const PROTOCOL_V2: u8 = 2;
+/// The latest version of the protocol
+pub const PROTOCOL_VERSION: u8 = PROTOCOL_V2;
+/// The request id of a client that doesn't match up responses by id
+pub const NO_REQUEST_ID: u16 = 0xFFFF;
+
+pub const FAMILY_CTL: u8 = 1;
+pub const FAMILY_DB: u8 = 2;
+pub const FAMILY_BF: u8 = 3;
+
+pub const OP_CTL_WAL_REPLAY: u8 = 0;
+pub const OP_CTL_LOAD_DATA: u8 = 1;
+pub const OP_CTL_WRITE_DATA: u8 = 2;
+pub const OP_CTL_CREATE_DATABASE: u8 = 4;
+pub const OP_CTL_LIST_DATABASES: u8 = 5;
+pub const OP_CTL_LOOKUP_DATABASE: u8 = 7;
+pub const OP_CTL_HANDSHAKE: u8 = 8;
+pub const OP_CTL_INFO: u8 = 9;
+pub const OP_CTL_CHECKPOINT: u8 = 10;
+pub const OP_DB_NEW_BF: u8 = 0;
+pub const OP_DB_NEW_SBF: u8 = 2;
+pub const OP_DB_LOOKUP_OBJECT: u8 = 9;
+pub const OP_DB_LIST_OBJECTS: u8 = 10;
+pub const OP_BF_ADD: u8 = 0;
+pub const OP_BF_ADD_BATCH: u8 = 1;
+pub const OP_BF_HAS: u8 = 2;
+pub const OP_BF_HAS_BATCH: u8 = 3;
+pub const OP_BF_INFO: u8 = 4;
+// Set on the op of a data command that addresses its object by name
+pub const OP_BY_NAME: u8 = 0x80;
+
+// A batch of elements is a list of LVs in a single LV
+pub const MAX_BATCH_LEN: usize = u8::MAX as usize;
+
+pub const PARAMS_EXPLICIT: u8 = 0;
+pub const PARAMS_ESTIMATE: u8 = 1;
+pub const TOKEN_FALSE: u8 = 0;
+pub const TOKEN_TRUE: u8 = 1;
+
+pub const RC_SUCCESS: u8 = 0;
+const RESPONSE_MARKER: u8 = 0xFF;
+
+
+/// Append a command frame to the buffer.
+pub fn encode_frame(buf: &mut Vec<u8>, family: u8, op: u8, req_id: u16, val: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(val.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "encode_frame: value too long"))?;
+        buf.reserve(HEADER_SZ + val.len());
+        buf.push(family);
+        buf.push(op);
+        buf.extend(req_id.to_le_bytes());
+        buf.extend(len.to_le_bytes());
+        buf.extend(val);
+        Ok(())
+}
+
+
+/// Append a length-prefixed value to the buffer.
+pub fn encode_lv(buf: &mut Vec<u8>, val: &[u8]) -> io::Result<()> {
+        let len = u8::try_from(val.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "encode_lv: value longer than 255 bytes"))?;
+        buf.push(len);
+        buf.extend(val);
+        Ok(())
+}
+
+
+/// Pack the elements into as few batches as fit in an LV each, for the batch
+/// commands.
+pub fn encode_batches<T: AsRef<[u8]>>(elts: &[T]) -> io::Result<Vec<Vec<u8>>> {
+        let mut batches: Vec<Vec<u8>> = Vec::new();
+        for elt in elts {
+                let elt = elt.as_ref();
+                if elt.len() >= MAX_BATCH_LEN {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, "encode_batches: element too long for a batch"));
+                }
+                let batch = match batches.last_mut() {
+                        Some(batch) if batch.len() + 1 + elt.len() <= MAX_BATCH_LEN => batch,
+                        _ => {
+                                batches.push(Vec::new());
+                                batches.last_mut().unwrap()
+                        }
+                };
+                encode_lv(batch, elt)?;
+        }
+        Ok(batches)
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResponseHeader {
+        pub rc: u8,
+        pub req_id: u16,
+        pub len: usize,
+}
+
+
+impl ResponseHeader {
+        pub fn decode(buf: &[u8; HEADER_SZ]) -> io::Result<Self> {
+                if buf[1] != RESPONSE_MARKER {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "impl ResponseHeader: decode: not a response frame"));
+                }
+                Ok(Self {
+                        rc: buf[0],
+                        req_id: u16::from_le_bytes([buf[2], buf[3]]),
+                        len: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize,
+                })
+        }
+
+        #[must_use]
+        pub fn is_success(&self) -> bool {
+                self.rc == RC_SUCCESS
+        }
+}
+
+
+/// An error response from the server, which carries the error code and the
+/// detail message of the response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerError {
+        pub code: u8,
+        pub detail: String,
+}
+
+
+impl fmt::Display for ServerError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                if self.detail.is_empty() {
+                        write!(f, "server error {}", self.code)
+                } else {
+                        write!(f, "server error {}: {}", self.code, self.detail)
+                }
+        }
+}
+
+
+impl std::error::Error for ServerError {}
+
+
+impl ServerError {
+        pub const EXISTS: u8 = 1;
+        pub const MALFORMED: u8 = 2;
+        pub const NAME_NOT_FOUND: u8 = 3;
+        pub const FILTER_FULL: u8 = 4;
+        pub const FRAME_TOO_LARGE: u8 = 5;
+        pub const UNKNOWN_COMMAND: u8 = 6;
+        pub const TRUNCATED_FRAME: u8 = 7;
+        pub const UNSUPPORTED_VERSION: u8 = 8;
+        pub const INVALID_PARAMETERS: u8 = 9;
+        pub const DATABASE_NOT_FOUND: u8 = 10;
+        pub const OBJECT_NOT_FOUND: u8 = 11;
+        pub const INTERNAL_ERROR: u8 = 12;
+        pub const READ_ONLY: u8 = 13;
+
+        /// Wrap the error response into an I/O error of the closest kind. The
+        /// server error can be recovered with `get_ref` and `downcast_ref`.
+        #[must_use]
+        pub fn into_io_error(self) -> io::Error {
+                let kind = match self.code {
+                        Self::EXISTS => io::ErrorKind::AlreadyExists,
+                        Self::NAME_NOT_FOUND | Self::DATABASE_NOT_FOUND | Self::OBJECT_NOT_FOUND => io::ErrorKind::NotFound,
+                        Self::INVALID_PARAMETERS | Self::FRAME_TOO_LARGE => io::ErrorKind::InvalidInput,
+                        Self::UNKNOWN_COMMAND | Self::UNSUPPORTED_VERSION => io::ErrorKind::Unsupported,
+                        Self::READ_ONLY => io::ErrorKind::PermissionDenied,
+                        _ => io::ErrorKind::Other,
+                };
+                io::Error::new(kind, self)
+        }
+}
+
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn test_frames() {
+                let mut buf = Vec::new();
+                let mut val = vec![0, 1];
+                encode_lv(&mut val, b"abc").unwrap();
+                encode_frame(&mut buf, FAMILY_BF, OP_BF_ADD, 7, &val).unwrap();
+                assert!(buf == [3, 0, 7, 0, 6, 0, 0, 0, 0, 1, 3, b'a', b'b', b'c']);
+                assert!(encode_lv(&mut val, &[0; 256]).is_err());
+
+                let header = ResponseHeader::decode(&[0, 0xFF, 7, 0, 1, 0, 0, 0]).unwrap();
+                assert!(header == ResponseHeader { rc: 0, req_id: 7, len: 1 } && header.is_success());
+                assert!(ResponseHeader::decode(&[0, 1, 7, 0, 1, 0, 0, 0]).is_err());
+
+                let e = ServerError { code: ServerError::OBJECT_NOT_FOUND, detail: "no filter 1".into() }.into_io_error();
+                assert!(e.kind() == io::ErrorKind::NotFound);
+                assert!(e.to_string() == "server error 11: no filter 1");
+        }
+
+        #[test]
+        fn test_batches() {
+                let long = [7; 200];
+                assert!(encode_batches(&[&b"ab"[..], &long, b"c"]).unwrap().len() == 1);
+                let split = encode_batches(&[&long[..], &long, b"c"]).unwrap();
+                assert!(split.len() == 2 && split[1].len() == 201 + 2);
+                assert!(encode_batches(&[[0; 255]]).is_err());
+                assert!(encode_batches::<&[u8]>(&[]).unwrap().is_empty());
+        }
+}
\ No newline at end of file
@@ -0,0 +1,13 @@
+// Copyright © 2025-Present Kasperi Apell <apkaspell@gmail.com>
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+//
+//! The protocol the server and its clients share: the framing of commands
+//! and responses, and the values of the commands both sides build or read.
+
+
+pub mod filter;
+pub mod frame;
+
+pub use filter::{FilterInfo, FilterParams};
+pub use frame::ServerError;
\ No newline at end of file
@@ -6,6 +6,7 @@ use std::io;
 
 pub const U8_OFFSET: usize = std::mem::size_of::<u8>();
 pub const USIZE_OFFSET: usize = std::mem::size_of::<usize>();
+pub const U64_OFFSET: usize = std::mem::size_of::<u64>();
 
 
 #[repr(u8)]
@@ -14,6 +15,14 @@ pub enum SerializableType {
         Database = 1,
         BloomFilterStructure = 2,
         BitVec = 3,
+        CountingBloomFilterStructure = 4,
+        ScalableBloomFilterStructure = 5,
+        BloomFilter = 6,
+        CuckooFilterStructure = 7,
+        HyperLogLogStructure = 8,
+        CountMinSketchStructure = 9,
+        TopKStructure = 10,
+        LogSequence = 11,
 }
 
 
@@ -22,6 +31,14 @@ impl TryFrom<u8> for SerializableType {
 
         fn try_from(byte: u8) -> io::Result<Self> {
                 match byte {
+                        11 => Ok(SerializableType::LogSequence),
+                        10 => Ok(SerializableType::TopKStructure),
+                        9 => Ok(SerializableType::CountMinSketchStructure),
+                        8 => Ok(SerializableType::HyperLogLogStructure),
+                        7 => Ok(SerializableType::CuckooFilterStructure),
+                        6 => Ok(SerializableType::BloomFilter),
+                        5 => Ok(SerializableType::ScalableBloomFilterStructure),
+                        4 => Ok(SerializableType::CountingBloomFilterStructure),
                         3 => Ok(SerializableType::BitVec),
                         2 => Ok(SerializableType::BloomFilterStructure),
                         1 => Ok(SerializableType::Database),
@@ -40,6 +57,14 @@ impl SerializableType {
                         SerializableType::Database => 1,
                         SerializableType::BloomFilterStructure => 2,
                         SerializableType::BitVec => 3,
+                        SerializableType::CountingBloomFilterStructure => 4,
+                        SerializableType::ScalableBloomFilterStructure => 5,
+                        SerializableType::BloomFilter => 6,
+                        SerializableType::CuckooFilterStructure => 7,
+                        SerializableType::HyperLogLogStructure => 8,
+                        SerializableType::CountMinSketchStructure => 9,
+                        SerializableType::TopKStructure => 10,
+                        SerializableType::LogSequence => 11,
                 }
         }
 }
@@ -89,6 +114,13 @@ impl<'a> DeserTLV<'a> {
                 Ok(usize::from_le_bytes(bytes))
         }
 
+        pub fn deserialize_f64(buf: &[u8]) -> io::Result<f64> {
+                let bytes = buf[0..U64_OFFSET]
+                        .try_into()
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "slice length mismatch"))?;
+                Ok(f64::from_le_bytes(bytes))
+        }
+
         pub fn deserialize_vec_u8(buf: &[u8]) -> io::Result<Vec<u8>> {
                 Ok(buf.to_vec())
         }
@@ -142,6 +174,11 @@ impl SerTLV {
                 Ok(())
         }
 
+        pub fn serialize_f64(&mut self, x: f64) -> io::Result<()> {
+                self.val.extend_from_slice(&f64::to_le_bytes(x));
+                Ok(())
+        }
+
         pub fn serialize_slice_u8(&mut self, x: &[u8]) -> io::Result<usize> {
                 let ret = x.len();
                 self.val.extend_from_slice(x);